@@ -0,0 +1,322 @@
+//! An AFL++ custom mutator that generates and mutates test cases from an
+//! ANTLR 4 grammar.
+//!
+//! The grammar and the rule to start generating from are read from the
+//! `SYNFUZZ_GRAMMAR` and `SYNFUZZ_START` environment variables when AFL++
+//! loads the library:
+//!
+//! ```text
+//! SYNFUZZ_GRAMMAR=Json.g4 SYNFUZZ_START=json \
+//! AFL_CUSTOM_MUTATOR_LIBRARY=target/release/libsynfuzz_afl.so \
+//! AFL_CUSTOM_MUTATOR_ONLY=1 afl-fuzz -i seeds -o out -- ./target @@
+//! ```
+//!
+//! The queue holds the decisions that synfuzz::generate_from_bytes consumes
+//! rather than generated values. afl_custom_post_process turns them into the
+//! value handed to the target, so any seed file, even an empty one, is a
+//! valid starting point.
+
+extern crate rand;
+extern crate synfuzz;
+extern crate synfuzz_antlr4;
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::env;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use synfuzz::Mutator;
+use synfuzz_antlr4::generate_rules;
+
+/// State is the per instance data handed back to AFL++ by afl_custom_init
+struct State {
+    mutator: Mutator,
+    rng: SmallRng,
+    fuzz_buf: Vec<u8>,
+    post_process_buf: Vec<u8>,
+    trim: Trim,
+}
+
+/// Trim tracks the progress of trimming a single test case. AFL++ fixes the
+/// number of steps up front so whenever a candidate is accepted the
+/// candidates of the smaller test case continue from the current step
+#[derive(Default)]
+struct Trim {
+    current: Vec<u8>,
+    candidates: Vec<Vec<u8>>,
+    steps: usize,
+    step: usize,
+    base: usize,
+}
+
+impl State {
+    fn new(seed: u32) -> Result<State, String> {
+        let grammar = env::var("SYNFUZZ_GRAMMAR")
+            .map_err(|_| String::from("SYNFUZZ_GRAMMAR must be set to an ANTLR 4 grammar"))?;
+        let start = env::var("SYNFUZZ_START")
+            .map_err(|_| String::from("SYNFUZZ_START must be set to the rule to start from"))?;
+        let rules = generate_rules(&grammar).map_err(|e| e.to_string())?;
+        if !rules.read().unwrap().contains_key(&start) {
+            return Err(format!("rule '{}' does not exist in {}", start, grammar));
+        }
+
+        Ok(State::with_mutator(Mutator::new(rules, start), seed))
+    }
+
+    fn with_mutator(mutator: Mutator, seed: u32) -> State {
+        State {
+            mutator,
+            rng: SmallRng::seed_from_u64(u64::from(seed)),
+            fuzz_buf: vec![],
+            post_process_buf: vec![],
+            trim: Trim::default(),
+        }
+    }
+
+    fn fuzz(&mut self, buf: &[u8], add_buf: &[u8], max_size: usize) -> &[u8] {
+        let seed = self.rng.gen();
+        self.fuzz_buf = if !add_buf.is_empty() && self.rng.gen_bool(0.2) {
+            self.mutator.crossover(buf, add_buf, max_size, seed)
+        } else {
+            self.mutator.mutate(buf, max_size, seed)
+        };
+        &self.fuzz_buf
+    }
+
+    fn post_process(&mut self, buf: &[u8]) -> &[u8] {
+        self.post_process_buf = self.mutator.generate(buf);
+        &self.post_process_buf
+    }
+
+    fn init_trim(&mut self, buf: &[u8]) -> usize {
+        let candidates = self.mutator.shrink(buf);
+        self.trim = Trim {
+            current: buf.to_vec(),
+            steps: candidates.len(),
+            candidates,
+            step: 0,
+            base: 0,
+        };
+        self.trim.steps
+    }
+
+    fn trim(&self) -> &[u8] {
+        &self.trim.candidates[self.trim.step - self.trim.base]
+    }
+
+    fn post_trim(&mut self, success: bool) -> usize {
+        let trim = &mut self.trim;
+        trim.step += 1;
+
+        if success {
+            trim.current = trim.candidates[trim.step - 1 - trim.base].clone();
+            trim.candidates = self.mutator.shrink(&trim.current);
+            trim.base = trim.step;
+        }
+
+        if trim.step - trim.base >= trim.candidates.len() {
+            trim.step = trim.steps;
+        }
+
+        trim.step
+    }
+}
+
+/// afl_custom_init loads the grammar and returns the mutator state, or null
+/// if the grammar can't be loaded
+#[no_mangle]
+pub extern "C" fn afl_custom_init(_afl: *mut c_void, seed: u32) -> *mut c_void {
+    guard(ptr::null_mut(), || match State::new(seed) {
+        Ok(state) => Box::into_raw(Box::new(state)) as *mut c_void,
+        Err(e) => {
+            eprintln!("synfuzz: {}", e);
+            ptr::null_mut()
+        }
+    })
+}
+
+/// afl_custom_fuzz mutates buf, or crosses it over with add_buf, and points
+/// out_buf at the result
+///
+/// # Safety
+///
+/// data must have been returned by afl_custom_init and buf and add_buf must
+/// be valid for buf_size and add_buf_size bytes
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_fuzz(
+    data: *mut c_void,
+    buf: *mut u8,
+    buf_size: usize,
+    out_buf: *mut *mut u8,
+    add_buf: *mut u8,
+    add_buf_size: usize,
+    max_size: usize,
+) -> usize {
+    let (fuzzed, size) = guard((buf, 0), || {
+        let state = &mut *(data as *mut State);
+        let buf = bytes(buf, buf_size);
+        let add_buf = bytes(add_buf, add_buf_size);
+        let fuzzed = state.fuzz(buf, add_buf, max_size);
+        (fuzzed.as_ptr() as *mut u8, fuzzed.len())
+    });
+    *out_buf = fuzzed;
+    size
+}
+
+/// afl_custom_post_process generates the value that is handed to the target
+/// from the decisions in buf
+///
+/// # Safety
+///
+/// data must have been returned by afl_custom_init and buf must be valid for
+/// buf_size bytes
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_post_process(
+    data: *mut c_void,
+    buf: *mut u8,
+    buf_size: usize,
+    out_buf: *mut *mut u8,
+) -> usize {
+    let (value, size) = guard((buf, 0), || {
+        let state = &mut *(data as *mut State);
+        let value = state.post_process(bytes(buf, buf_size));
+        (value.as_ptr() as *mut u8, value.len())
+    });
+    *out_buf = value;
+    size
+}
+
+/// afl_custom_init_trim starts trimming buf and returns the number of trim
+/// steps
+///
+/// # Safety
+///
+/// data must have been returned by afl_custom_init and buf must be valid for
+/// buf_size bytes
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_init_trim(
+    data: *mut c_void,
+    buf: *mut u8,
+    buf_size: usize,
+) -> i32 {
+    guard(-1, || {
+        let state = &mut *(data as *mut State);
+        state.init_trim(bytes(buf, buf_size)) as i32
+    })
+}
+
+/// afl_custom_trim points out_buf at the candidate for the current trim step
+///
+/// # Safety
+///
+/// data must have been returned by afl_custom_init and trimming must have
+/// been started with afl_custom_init_trim
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_trim(data: *mut c_void, out_buf: *mut *mut u8) -> usize {
+    let (candidate, size) = guard((ptr::null_mut(), 0), || {
+        let state = &mut *(data as *mut State);
+        let candidate = state.trim();
+        (candidate.as_ptr() as *mut u8, candidate.len())
+    });
+    *out_buf = candidate;
+    size
+}
+
+/// afl_custom_post_trim records whether the last candidate kept the same
+/// coverage and returns the next trim step
+///
+/// # Safety
+///
+/// data must have been returned by afl_custom_init and trimming must have
+/// been started with afl_custom_init_trim
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_post_trim(data: *mut c_void, success: u8) -> i32 {
+    guard(-1, || {
+        let state = &mut *(data as *mut State);
+        state.post_trim(success != 0) as i32
+    })
+}
+
+/// afl_custom_deinit frees the mutator state
+///
+/// # Safety
+///
+/// data must have been returned by afl_custom_init and must not be used
+/// afterwards
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_deinit(data: *mut c_void) {
+    if !data.is_null() {
+        guard((), || drop(Box::from_raw(data as *mut State)));
+    }
+}
+
+/// guard runs f and returns error if it panics, since a panic must not
+/// unwind into AFL++. The panic message has already been printed by the
+/// panic hook
+fn guard<T, F: FnOnce() -> T>(error: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(error)
+}
+
+unsafe fn bytes<'a>(buf: *const u8, size: usize) -> &'a [u8] {
+    if buf.is_null() || size == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(buf, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+    use synfuzz::{byte, choice, many1, register_rule, rule, seq};
+
+    fn state() -> State {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        let expr = choice!(
+            seq!(byte(0x28), rule("expr", rules.clone()), byte(0x29)),
+            many1(byte(0x31))
+        );
+        register_rule(&rules, "expr", expr);
+        State::with_mutator(Mutator::new(rules, "expr"), 1)
+    }
+
+    #[test]
+    fn trim_runs_to_completion() {
+        let mut state = state();
+        let buf = [0x00, 0x00, 0x01, 0x03, 0xff];
+        let steps = state.init_trim(&buf);
+        assert!(steps > 0);
+
+        let mut step = 0;
+        while step < steps {
+            let candidate = state.trim().to_vec();
+            // accept every candidate that generates a smaller value
+            let smaller = state.mutator.generate(&candidate).len()
+                < state.mutator.generate(&state.trim.current).len();
+            step = state.post_trim(smaller);
+        }
+
+        assert_eq!(state.mutator.generate(&state.trim.current), b"1".to_vec());
+    }
+
+    #[test]
+    fn guard_catches_panics() {
+        assert_eq!(guard(-1, || 1), 1);
+        assert_eq!(guard(-1, || panic!("trim failed")), -1);
+    }
+
+    #[test]
+    fn fuzz_respects_max_size() {
+        let mut state = state();
+        for _ in 0..100 {
+            let fuzzed = state.fuzz(&[0x00, 0x01, 0x02], &[0x01], 4).to_vec();
+            assert!(fuzzed.len() <= 4);
+            state.post_process(&fuzzed);
+        }
+    }
+}
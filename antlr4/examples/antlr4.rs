@@ -1,17 +1,37 @@
+extern crate synfuzz;
 extern crate synfuzz_antlr4;
 
-use synfuzz_antlr4::generate_rules;
+use synfuzz::write_dictionary;
+use synfuzz_antlr4::{generate_dictionary, generate_rules};
 
-use std::{env, process::exit};
+use std::{env, io, process::exit};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     if args.len() != 3 {
         println!("{} <path> <rule>", args[0]);
+        println!("{} <path> --dict", args[0]);
         exit(1);
     }
 
+    if args[2] == "--dict" {
+        let dictionary = match generate_dictionary(&args[1]) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("{}", e);
+                exit(1);
+            }
+        };
+
+        let stdout = io::stdout();
+        if let Err(e) = write_dictionary(&dictionary, stdout.lock()) {
+            println!("{}", e);
+            exit(1);
+        }
+        return;
+    }
+
     let rules = match generate_rules(&args[1]) {
         Ok(r) => r,
         Err(e) => {
@@ -0,0 +1,6 @@
+extern crate synfuzz_antlr4;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    synfuzz_antlr4::process_root().unwrap();
+}
@@ -0,0 +1,4 @@
+grammar digits;
+
+number : NUMBER ;
+NUMBER : '-'? [0-9]+ ;
@@ -0,0 +1,6 @@
+grammar Calculator;
+
+expr : term (('+' | '-') term)* ;
+term : factor (('*' | '/') factor)* ;
+factor : NUMBER | '(' expr ')' ;
+NUMBER : [1-9] [0-9]* ;
@@ -0,0 +1,36 @@
+//! An example of generating the rules of ANTLR 4 grammars at build time.
+//! build.rs calls synfuzz_antlr4::process_root, which writes a module for
+//! every grammar under src to OUT_DIR, and each module is declared with
+//! grammar_mod!
+
+extern crate synfuzz;
+extern crate synfuzz_antlr4;
+
+// the module is named after the grammar in src/digits.g4
+synfuzz_antlr4::grammar_mod!(pub digits);
+synfuzz_antlr4::grammar_mod!(
+    /// The rules of src/grammars/Calculator.g4
+    pub calculator,
+    "/grammars/Calculator.rs"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use synfuzz::{rule, target_size, Generator};
+
+    #[test]
+    fn generates_from_the_modules() {
+        let number = rule("number", digits::rules());
+        // the calculator grammar is recursive, so its size is bounded to
+        // stop the nesting of expressions
+        let expr = target_size(rule("expr", calculator::rules()), 1, 64);
+        for _ in 0..20 {
+            let value = String::from_utf8(number.generate()).unwrap();
+            assert!(value.parse::<i64>().is_ok() || value.len() > 18);
+
+            let value = String::from_utf8(expr.generate()).unwrap();
+            assert!(value.starts_with(|c: char| c == '(' || c.is_ascii_digit()));
+        }
+    }
+}
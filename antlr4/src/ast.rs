@@ -9,6 +9,10 @@ impl Grammar {
         Grammar { name, rules }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn rules(&self) -> &Vec<Rule> {
         &self.rules
     }
@@ -31,7 +35,6 @@ pub enum Operation {
     Token(String),
     Rule(String),
     StringLiteral(String),
-    Range((String, String)),
     Any,
     CharacterClass(Vec<Operation>),
     Char(char),
@@ -68,16 +71,42 @@ impl Rule {
     }
 }
 
-pub fn unroll_quantifier(quantifiers: Vec<String>, token: Operation) -> Operation {
+#[derive(Debug, Clone, Copy)]
+pub enum Quantifier {
+    Optional,
+    Star,
+    Plus,
+}
+
+pub fn unroll_quantifier(quantifiers: Vec<Quantifier>, token: Operation) -> Operation {
     let mut ret = token;
     for quantifier in quantifiers {
-        match quantifier.as_str() {
-            "?" => ret = Operation::Optional(Box::new(ret)),
-            "*" => ret = Operation::Star(Box::new(ret)),
-            "+" => ret = Operation::Plus(Box::new(ret)),
-            _ => panic!("noooooooo"),
+        ret = match quantifier {
+            Quantifier::Optional => Operation::Optional(Box::new(ret)),
+            Quantifier::Star => Operation::Star(Box::new(ret)),
+            Quantifier::Plus => Operation::Plus(Box::new(ret)),
         }
     }
 
     ret
 }
+
+/// Error is an error found by an action of the parser, such as an invalid
+/// escape sequence in a character set
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    /// The byte offset of the offending text in the grammar
+    pub location: usize,
+    pub text: String,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(location: usize, text: &str, message: &str) -> Error {
+        Error {
+            location,
+            text: text.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+}
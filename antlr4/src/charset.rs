@@ -1,86 +1,193 @@
-use ast::Operation;
+use ast::{Error, Operation};
 use std::char;
 use std::iter::Peekable;
-use std::str::Chars;
+use std::str::CharIndices;
 
 // https://github.com/antlr/antlr4/blob/master/doc/lexer-rules.md
 
-/// parse an ANTLR4 character set into a Choice of all possible generators
-pub fn parse_charset(charset: &str) -> Operation {
-    let mut chars = charset.chars().peekable();
+/// parse an ANTLR4 character set into a Choice of all possible generators.
+/// offset is where the set starts in the grammar and locates any error
+pub fn parse_charset(charset: &str, offset: usize) -> Result<Operation, Error> {
+    let mut chars = charset.char_indices().peekable();
 
-    let mut choices = vec![];
-    let mut state = State::Start;
+    match chars.next() {
+        Some((_, '[')) => {}
+        Some((i, ch)) => {
+            return Err(Error::new(
+                offset + i,
+                &ch.to_string(),
+                &format!("expected `[` and got `{}`", ch),
+            ))
+        }
+        None => return Err(Error::new(offset, "", "expected `[`")),
+    }
 
+    let mut choices = vec![];
     loop {
-        let ch = match chars.next() {
-            Some(ch) => ch,
-            None => panic!("unexpected end of charset"),
+        let (start, ch) = match chars.next() {
+            Some(next) => next,
+            None => {
+                return Err(Error::new(
+                    offset + charset.len(),
+                    "",
+                    "unexpected end of character set",
+                ))
+            }
         };
+        if ch == ']' {
+            break;
+        }
 
-        if state == State::Start {
-            if ch == '[' {
-                state = State::InCharset;
-            } else {
-                panic!("expected `[` and got `{}`", ch);
-            };
-        } else if state == State::InCharset {
-            if ch == ']' {
-                state = State::End;
-                break;
-            } else {
-                let first = match ch {
-                    '\\' => escape(&mut chars),
-                    _ => ch,
-                };
-
-                if chars.peek() == Some(&'-') {
-                    chars.next();
-                    let second_ch = chars.next().unwrap();
-                    let second = match second_ch {
-                        '\\' => escape(&mut chars),
-                        _ => second_ch,
-                    };
-                    choices.push(Operation::CharRange((first, second)));
-                } else {
-                    choices.push(Operation::Char(first))
-                }
-            }
-        } else {
-            panic!("invalid state")
+        let first = match ch {
+            '\\' => escape(charset, start, &mut chars, offset)?,
+            _ => ch,
+        };
+        if chars.peek().map(|&(_, ch)| ch) != Some('-') {
+            choices.push(Operation::Char(first));
+            continue;
+        }
+
+        chars.next();
+        // a - before the closing ] is literal
+        if chars.peek().map(|&(_, ch)| ch) == Some(']') {
+            choices.push(Operation::Char(first));
+            choices.push(Operation::Char('-'));
+            continue;
+        }
+        let second = match chars.next() {
+            Some((i, '\\')) => escape(charset, i, &mut chars, offset)?,
+            Some((_, ch)) => ch,
+            None => continue,
+        };
+        if second < first {
+            let end = next_index(charset, &mut chars);
+            return Err(Error::new(
+                offset + start,
+                &charset[start..end],
+                "the range ends before it starts",
+            ));
         }
+        choices.push(Operation::CharRange((first, second)));
     }
 
-    Operation::CharacterClass(choices)
+    Ok(Operation::CharacterClass(choices))
 }
 
-fn escape(chars: &mut Peekable<Chars>) -> char {
+/// parse_range parses the range between the string literals l and r, which
+/// are located at offset and r_offset, into a CharRange
+pub fn parse_range(l: &str, r: &str, offset: usize, r_offset: usize) -> Result<Operation, Error> {
+    let n = parse_char(l, offset)?;
+    let m = parse_char(r, r_offset)?;
+    if m < n {
+        return Err(Error::new(
+            offset,
+            &format!("'{}'..'{}'", l, r),
+            "the range ends before it starts",
+        ));
+    }
+
+    Ok(Operation::CharRange((n, m)))
+}
+
+/// unescape_literal resolves the escape sequences of the string literal
+/// literal, which is located at offset in the grammar
+pub fn unescape_literal(literal: &str, offset: usize) -> Result<String, Error> {
+    let mut out = String::with_capacity(literal.len());
+    let mut chars = literal.char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => out.push(escape(literal, i, &mut chars, offset)?),
+            _ => out.push(ch),
+        }
+    }
+
+    Ok(out)
+}
+
+/// parse_char parses a string literal that is a single, possibly escaped,
+/// char. offset is where the literal starts, including its quote
+fn parse_char(literal: &str, offset: usize) -> Result<char, Error> {
+    let quoted = format!("'{}'", literal);
+    // the escapes are located relative to the quoted literal
+    let mut chars = quoted.char_indices().peekable();
+    chars.next();
+
+    let ch = match chars.next() {
+        Some((i, '\\')) => escape(&quoted, i, &mut chars, offset)?,
+        Some((_, '\'')) | None => {
+            return Err(Error::new(
+                offset,
+                &quoted,
+                "expected a single character in a range",
+            ))
+        }
+        Some((_, ch)) => ch,
+    };
+    if chars.next().map(|(_, ch)| ch) != Some('\'') {
+        return Err(Error::new(
+            offset,
+            &quoted,
+            "expected a single character in a range",
+        ));
+    }
+
+    Ok(ch)
+}
+
+/// escape resolves the escape sequence of the \ at start of source, which is
+/// located at offset in the grammar
+fn escape(
+    source: &str,
+    start: usize,
+    chars: &mut Peekable<CharIndices>,
+    offset: usize,
+) -> Result<char, Error> {
     // TODO: support unicode property names
-    match chars.next() {
-        Some('u') => {
+    let ch = match chars.next() {
+        Some((_, 'u')) => {
             // TODO: support curly based code points for > U+FFFF
-            let code_point = chars.take(4).collect::<String>();
-            let numeric_value = u32::from_str_radix(&code_point, 16).unwrap();
-            char::from_u32(numeric_value).expect("invalid codepoint specified")
+            let code_point = chars.by_ref().take(4).map(|(_, ch)| ch).collect::<String>();
+            let end = next_index(source, chars);
+            let invalid = |message| Error::new(offset + start, &source[start..end], message);
+            if code_point.len() != 4 || !code_point.chars().all(|ch| ch.is_ascii_hexdigit()) {
+                return Err(invalid("expected 4 hex digits in unicode escape sequence"));
+            }
+            let numeric_value = u32::from_str_radix(&code_point, 16).unwrap_or_default();
+            return char::from_u32(numeric_value).ok_or_else(|| invalid("invalid code point"));
         }
-        Some('n') => '\n',
-        Some('r') => '\r',
-        Some('b') => 7 as char,
-        Some('t') => '\t',
-        Some('f') => 12 as char,
-        Some(']') => ']',
-        Some('\\') => '\\',
-        Some('-') => '-',
-        None => panic!("unexpected end of input in character set escape sequence"),
-        _ => panic!("invalid escape sequence"),
-    }
+        Some((_, 'n')) => '\n',
+        Some((_, 'r')) => '\r',
+        Some((_, 'b')) => 8 as char,
+        Some((_, 't')) => '\t',
+        Some((_, 'f')) => 12 as char,
+        Some((_, ']')) => ']',
+        Some((_, '\\')) => '\\',
+        Some((_, '-')) => '-',
+        Some((_, '\'')) => '\'',
+        Some(_) => {
+            let end = next_index(source, chars);
+            return Err(Error::new(
+                offset + start,
+                &source[start..end],
+                "invalid escape sequence",
+            ));
+        }
+        None => {
+            return Err(Error::new(
+                offset + start,
+                &source[start..],
+                "unexpected end of input in escape sequence",
+            ))
+        }
+    };
+
+    Ok(ch)
 }
 
-#[derive(PartialEq)]
-enum State {
-    Start,
-    InCharset,
-    End,
+/// next_index returns the index of the next char of source, or its length at
+/// the end
+fn next_index(source: &str, chars: &mut Peekable<CharIndices>) -> usize {
+    chars.peek().map_or(source.len(), |&(i, _)| i)
 }
 
 #[cfg(test)]
@@ -92,8 +199,79 @@ mod tests {
         let tests = vec!["[a-zA-Z0-9]", r#"[\t\b\u0097]"#];
 
         for test in tests {
-            let thing = parse_charset(test);
+            let thing = parse_charset(test, 0).unwrap();
             println!("{}: {:?}", test, thing);
         }
+
+        match parse_charset("[+-]", 0).unwrap() {
+            Operation::CharacterClass(choices) => assert_eq!(choices.len(), 2),
+            other => panic!("expected a character class, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_charset_errors() {
+        let tests = vec![
+            ("a-z]", 10, Error::new(10, "a", "expected `[` and got `a`")),
+            (
+                r"[a\u00g1]",
+                10,
+                Error::new(
+                    12,
+                    r"\u00g1",
+                    "expected 4 hex digits in unicode escape sequence",
+                ),
+            ),
+            (
+                r"[\uD800]",
+                0,
+                Error::new(1, r"\uD800", "invalid code point"),
+            ),
+            (r"[\q]", 0, Error::new(1, r"\q", "invalid escape sequence")),
+            (
+                "[z-a]",
+                0,
+                Error::new(1, "z-a", "the range ends before it starts"),
+            ),
+            (
+                "[ab",
+                0,
+                Error::new(3, "", "unexpected end of character set"),
+            ),
+        ];
+
+        for (charset, offset, expected) in tests {
+            assert_eq!(parse_charset(charset, offset).unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn test_unescape_literal() {
+        assert_eq!(
+            unescape_literal(r"a\r\n\t\b\f\'\\\u00e9", 0).unwrap(),
+            "a\r\n\t\u{8}\u{c}'\\\u{e9}"
+        );
+        assert_eq!(
+            unescape_literal(r"ab\q", 10).unwrap_err(),
+            Error::new(12, r"\q", "invalid escape sequence")
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let range = parse_range(r"\u0000", r"\uFFFF", 0, 10).unwrap();
+        match range {
+            Operation::CharRange(range) => assert_eq!(range, ('\0', '\u{ffff}')),
+            other => panic!("expected a range, got {:?}", other),
+        }
+
+        assert_eq!(
+            parse_range("ab", "z", 4, 10).unwrap_err(),
+            Error::new(4, "'ab'", "expected a single character in a range")
+        );
+        assert_eq!(
+            parse_range("a", r"\x", 4, 10).unwrap_err(),
+            Error::new(11, r"\x", "invalid escape sequence")
+        );
     }
 }
@@ -0,0 +1,112 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::{parse_grammar, translate_grammar, AntlrError};
+
+/// generate_module takes the path to an ANTLR4 grammar file and returns the
+/// Rust source of a module with a function, rules, that builds the same rules
+/// as generate_rules without parsing the grammar at runtime
+pub fn generate_module<P: AsRef<Path>>(path: P) -> Result<String, AntlrError> {
+    let path = path.as_ref();
+    let rule_set = translate_grammar(&parse_grammar(path)?);
+
+    Ok(format!(
+        "// generated by synfuzz-antlr4 from {}, do not edit\n\n\
+         /// rules builds the rules of the grammar\n\
+         pub fn rules() -> ::std::sync::Arc<::std::sync::RwLock<::synfuzz::Rules>> {}\n",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        rule_set.to_rust()
+    ))
+}
+
+/// process_file generates the module of the grammar at input and writes it to
+/// output
+pub fn process_file<P, Q>(input: P, output: Q) -> Result<(), AntlrError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let output = output.as_ref();
+    let module = generate_module(input)?;
+    File::create(output)
+        .and_then(|mut f| f.write_all(module.as_bytes()))
+        .map_err(AntlrError::in_file(output))
+}
+
+/// process_dir generates the module of every .g4 grammar under dir and writes
+/// it to the same path relative to out_dir with an .rs extension
+pub fn process_dir<P, Q>(dir: P, out_dir: Q) -> Result<(), AntlrError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    for entry in fs::read_dir(dir).map_err(AntlrError::in_file(dir))? {
+        let path = entry.map_err(AntlrError::in_file(dir))?.path();
+        let output = out_dir.as_ref().join(path.file_name().unwrap_or_default());
+        if path.is_dir() {
+            process_dir(&path, &output)?;
+        } else if path.extension().is_some_and(|extension| extension == "g4") {
+            fs::create_dir_all(out_dir.as_ref()).map_err(AntlrError::in_file(&out_dir))?;
+            process_file(&path, output.with_extension("rs"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// process_root generates the module of every .g4 grammar under src into
+/// OUT_DIR. It is meant to be called from build.rs, after which a module is
+/// declared with grammar_mod!
+///
+/// ```ignore
+/// extern crate synfuzz_antlr4;
+///
+/// fn main() {
+///     synfuzz_antlr4::process_root().unwrap();
+/// }
+/// ```
+///
+/// examples/build-script is a crate that builds its grammars this way
+pub fn process_root() -> Result<(), AntlrError> {
+    let out_dir = env::var_os("OUT_DIR").ok_or_else(|| AntlrError::IoError {
+        file: String::from("OUT_DIR"),
+        error: io::Error::new(
+            io::ErrorKind::NotFound,
+            "the environment variable is not set, process_root must be called from build.rs",
+        ),
+    })?;
+    process_dir("src", out_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_dir() {
+        let dir = env::temp_dir().join(format!("synfuzz-antlr4-codegen-{}", std::process::id()));
+        let src = dir.join("src");
+        let out_dir = dir.join("out");
+        fs::create_dir_all(src.join("grammars")).unwrap();
+        fs::write(
+            src.join("grammars").join("Test.g4"),
+            "grammar Test;\nexpr : NUM | expr '+' NUM ;\nNUM : [0-9]+ ;\n",
+        )
+        .unwrap();
+        fs::write(src.join("lib.rs"), "").unwrap();
+
+        process_dir(&src, &out_dir).unwrap();
+        let module = fs::read_to_string(out_dir.join("grammars").join("Test.rs")).unwrap();
+        let outputs = fs::read_dir(&out_dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(outputs, 1);
+        assert!(module.starts_with("// generated by synfuzz-antlr4 from Test.g4, do not edit\n"));
+        assert!(module.contains("pub fn rules() -> "));
+        assert!(module.contains("::synfuzz::register_rule(&rules, \"expr\", "));
+        assert!(module.contains("::synfuzz::register_rule(&rules, \"NUM\", "));
+    }
+}
@@ -0,0 +1,88 @@
+use ast::{Grammar, Operation};
+use charset::unescape_literal;
+use synfuzz::Dictionary;
+
+use super::{parse_grammar, AntlrError};
+
+/// generate_dictionary takes the path to an ANTLR4 grammar file and returns a
+/// Dictionary of every string literal (keywords, operators and punctuation)
+/// used by its rules, in the order they first appear. The result can be
+/// written out with synfuzz::write_dictionary for use by AFL or libFuzzer
+pub fn generate_dictionary(path: &str) -> Result<Dictionary, AntlrError> {
+    let grammar = parse_grammar(path)?;
+    Ok(grammar_dictionary(&grammar))
+}
+
+fn grammar_dictionary(grammar: &Grammar) -> Dictionary {
+    let mut entries = vec![];
+    for rule in grammar.rules() {
+        for operation in rule.body() {
+            collect_literals(operation, &mut entries);
+        }
+    }
+
+    Dictionary { entries }
+}
+
+fn collect_literals(operation: &Operation, entries: &mut Vec<Vec<u8>>) {
+    match operation {
+        Operation::StringLiteral(s) => {
+            // the AST doesn't locate literals, so a malformed escape sequence
+            // keeps the literal verbatim
+            let literal = unescape_literal(s, 0)
+                .unwrap_or_else(|_| s.clone())
+                .into_bytes();
+            if !literal.is_empty() && !entries.contains(&literal) {
+                entries.push(literal);
+            }
+        }
+        Operation::Optional(op) | Operation::Star(op) | Operation::Plus(op) => {
+            collect_literals(op, entries)
+        }
+        Operation::Group(ops) => {
+            for op in ops {
+                collect_literals(op, entries);
+            }
+        }
+        Operation::Alternate(alternates) => {
+            for op in alternates.iter().flat_map(|a| a.iter()) {
+                collect_literals(op, entries);
+            }
+        }
+        // a negated literal is by definition not a token of the language
+        Operation::Not(_) => {}
+        Operation::Token(_)
+        | Operation::Rule(_)
+        | Operation::Any
+        | Operation::CharacterClass(_)
+        | Operation::Char(_)
+        | Operation::CharRange(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use antlr4::GrammarParser;
+
+    #[test]
+    fn test_grammar_dictionary() {
+        let grammar = GrammarParser::new()
+            .parse(
+                r#"grammar Test;
+                stmt : 'if' expr 'then' stmt ('else' stmt)? | ID '=' expr ';' ;
+                expr : ID | '(' expr ')' | expr '+' expr ;
+                ID : [a-z]+ ;
+                WS : ~'\n' -> skip ;
+                NL : '\r\n' ;
+                "#,
+            )
+            .unwrap();
+
+        let entries = grammar_dictionary(&grammar).entries;
+        let expected: Vec<&[u8]> = vec![
+            b"if", b"then", b"else", b"=", b";", b"(", b")", b"+", b"\r\n",
+        ];
+        assert_eq!(entries, expected);
+    }
+}
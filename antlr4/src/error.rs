@@ -0,0 +1,268 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use lalrpop_util::ParseError;
+
+use antlr4::Token;
+use ast;
+
+/// AntlrError is an error reading or parsing an ANTLR4 grammar
+#[derive(Debug, Fail)]
+pub enum AntlrError {
+    /// file is the path that couldn't be read or written
+    #[fail(display = "{}: {}", file, error)]
+    IoError { file: String, error: io::Error },
+    #[fail(display = "{}", _0)]
+    ParseError(Box<Diagnostic>),
+}
+
+impl AntlrError {
+    /// in_file returns a function that wraps an I/O error with the path of
+    /// the file it happened on, for use with map_err
+    pub(crate) fn in_file<P: AsRef<Path>>(file: P) -> impl FnOnce(io::Error) -> AntlrError {
+        let file = file.as_ref().display().to_string();
+        move |error| AntlrError::IoError { file, error }
+    }
+
+    /// from_parse_error locates an error of the parser in the source of the
+    /// grammar, which was read from file
+    pub(crate) fn from_parse_error(
+        error: ParseError<usize, Token, ast::Error>,
+        source: &str,
+        file: Option<&str>,
+    ) -> AntlrError {
+        let end = source.trim_end().len();
+        let diagnostic = match error {
+            ParseError::InvalidToken { location } => match source[location..].chars().next() {
+                Some(ch) => Diagnostic::new(
+                    source,
+                    file,
+                    location,
+                    &ch.to_string(),
+                    format!("invalid token `{}`", ch),
+                    vec![],
+                ),
+                None => Diagnostic::new(
+                    source,
+                    file,
+                    end,
+                    "",
+                    String::from("unexpected end of grammar"),
+                    vec![],
+                ),
+            },
+            ParseError::UnrecognizedToken {
+                token: Some((location, Token(_, text), _)),
+                expected,
+            } => Diagnostic::new(
+                source,
+                file,
+                location,
+                text,
+                format!("unexpected `{}`", text),
+                describe(expected),
+            ),
+            ParseError::UnrecognizedToken {
+                token: None,
+                expected,
+            } => Diagnostic::new(
+                source,
+                file,
+                end,
+                "",
+                String::from("unexpected end of grammar"),
+                describe(expected),
+            ),
+            ParseError::ExtraToken {
+                token: (location, Token(_, text), _),
+            } => Diagnostic::new(
+                source,
+                file,
+                location,
+                text,
+                format!("extra token `{}`", text),
+                vec![],
+            ),
+            ParseError::User { error } => Diagnostic::new(
+                source,
+                file,
+                error.location,
+                &error.text,
+                error.message,
+                vec![],
+            ),
+        };
+
+        AntlrError::ParseError(Box::new(diagnostic))
+    }
+}
+
+/// describe names the terminals that the parser expected, which it names
+/// after the regular expressions that match them
+fn describe(expected: Vec<String>) -> Vec<String> {
+    expected
+        .into_iter()
+        .map(|terminal| {
+            if !terminal.starts_with("r#") {
+                terminal
+            } else if terminal.contains("[A-Z]") {
+                String::from("a token name")
+            } else if terminal.contains("[a-z]") {
+                String::from("a rule name")
+            } else if terminal.contains('\'') {
+                String::from("a string literal")
+            } else {
+                String::from("a character set")
+            }
+        })
+        .collect()
+}
+
+/// Diagnostic describes where and why a grammar is invalid. It displays like
+/// a rustc error, quoting the line of the grammar with a caret under the
+/// offending text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The path of the grammar, if it was read from a file
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    /// The offending text, which is empty at the end of the grammar
+    pub text: String,
+    /// The tokens that were expected instead, as named by the parser
+    pub expected: Vec<String>,
+    /// The line of the grammar holding the offending text
+    pub source_line: String,
+}
+
+impl Diagnostic {
+    /// new locates text at the byte offset of source
+    fn new(
+        source: &str,
+        file: Option<&str>,
+        offset: usize,
+        text: &str,
+        message: String,
+        expected: Vec<String>,
+    ) -> Diagnostic {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |i| offset + i);
+
+        Diagnostic {
+            file: file.map(String::from),
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            message,
+            text: text.to_owned(),
+            expected,
+            source_line: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        let gutter = " ".repeat(self.line.to_string().len());
+        match self.file {
+            Some(ref file) => writeln!(f, "{}--> {}:{}:{}", gutter, file, self.line, self.column)?,
+            None => writeln!(f, "{}--> {}:{}", gutter, self.line, self.column)?,
+        }
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+
+        // keep tabs so the caret lines up with the quoted line
+        let indent = self
+            .source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let remaining = self
+            .source_line
+            .chars()
+            .count()
+            .saturating_sub(self.column - 1);
+        let width = self.text.chars().count().min(remaining).max(1);
+        write!(f, "{} | {}{}", gutter, indent, "^".repeat(width))?;
+
+        if !self.expected.is_empty() {
+            write!(
+                f,
+                "\n{} = expected one of {}",
+                gutter,
+                self.expected.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use antlr4::GrammarParser;
+
+    fn diagnose(source: &str) -> Diagnostic {
+        let error = GrammarParser::new().parse(source).unwrap_err();
+        match AntlrError::from_parse_error(error, source, Some("Test.g4")) {
+            AntlrError::ParseError(diagnostic) => *diagnostic,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let diagnostic = diagnose("grammar Test;\nexpr : NUM ; ;\n");
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 14));
+        assert_eq!(diagnostic.text, ";");
+        assert!(diagnostic.expected.contains(&String::from("a token name")));
+
+        let diagnostic = diagnose("grammar Test;\nNUM : [0-9\\q]+ ;\n");
+        assert_eq!(
+            diagnostic,
+            Diagnostic {
+                file: Some(String::from("Test.g4")),
+                line: 2,
+                column: 11,
+                message: String::from("invalid escape sequence"),
+                text: String::from("\\q"),
+                expected: vec![],
+                source_line: String::from("NUM : [0-9\\q]+ ;"),
+            }
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "invalid escape sequence\n \
+             --> Test.g4:2:11\n  \
+             |\n\
+             2 | NUM : [0-9\\q]+ ;\n  \
+             |           ^^"
+        );
+
+        let diagnostic = diagnose("grammar Test;\nNUM : 'z'..'a' ;\n");
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 7));
+        assert_eq!(diagnostic.message, "the range ends before it starts");
+
+        let diagnostic = diagnose("grammar Test;\nexpr : NUM\n\n");
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 11));
+        assert_eq!(diagnostic.message, "unexpected end of grammar");
+
+        let diagnostic = diagnose("grammar Test;\n\texpr : @ ;\n");
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 9));
+        assert_eq!(diagnostic.message, "invalid token `@`");
+        assert!(diagnostic
+            .to_string()
+            .ends_with("2 | \texpr : @ ;\n  | \t       ^"));
+    }
+}
@@ -1,3 +1,6 @@
+// failure_derive expands to impls nested inside an anonymous const
+#![allow(non_local_definitions)]
+
 #[macro_use]
 extern crate failure;
 #[macro_use]
@@ -7,156 +10,296 @@ extern crate synfuzz;
 
 mod ast;
 mod charset;
+mod codegen;
+mod dictionary;
+mod error;
+
+pub use codegen::{generate_module, process_dir, process_file, process_root};
+pub use dictionary::generate_dictionary;
+pub use error::{AntlrError, Diagnostic};
 
 use ast::RuleType;
 
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::RwLock;
 
-use synfuzz::{
-    byte, register_rule, Any, CharLiteral, CharRange, Choice, Generator, JoinWith, Many, Many1,
-    Not, Optional, Rule, Rules, Sequence, StringLiteral,
-};
+use synfuzz::{RuleSet, Rules, Tree};
 
-lalrpop_mod!(pub antlr4);
+lalrpop_mod!(
+    #[allow(warnings, clippy::all)]
+    pub antlr4
+);
+
+/// grammar_mod declares a module holding the rules generated from a grammar by
+/// process_root, which builds them with its rules function. The path of the
+/// generated file is relative to OUT_DIR and defaults to the name of the module
+/// with an .rs extension
+///
+/// ```ignore
+/// grammar_mod!(pub calculator, "/grammars/calculator.rs");
+///
+/// let value = rule("expr", calculator::rules()).generate();
+/// ```
+///
+/// examples/build-script is a crate that builds its grammars this way
+#[macro_export]
+macro_rules! grammar_mod {
+    ($(#[$attr:meta])* $vis:vis $name:ident) => {
+        $crate::grammar_mod!($(#[$attr])* $vis $name, concat!("/", stringify!($name), ".rs"));
+    };
+    ($(#[$attr:meta])* $vis:vis $name:ident, $path:expr) => {
+        $(#[$attr])* $vis mod $name {
+            include!(concat!(env!("OUT_DIR"), $path));
+        }
+    };
+}
 
 /// generate_rules takes the path to an ANTLR4 grammar file and returns a set of
 /// rules that represent the parsed file
 pub fn generate_rules(path: &str) -> Result<Arc<RwLock<Rules>>, AntlrError> {
-    let mut f = File::open(path)?;
+    let parse_tree = parse_grammar(path)?;
+    Ok(translate_grammar(&parse_tree).to_rules())
+}
+
+/// generate_rules_from_str takes the source of an ANTLR4 grammar, such as one
+/// embedded with include_str!, and returns a set of rules that represent it
+///
+/// ```
+/// # extern crate synfuzz;
+/// # extern crate synfuzz_antlr4;
+/// # use synfuzz::{rule, Generator};
+/// # use synfuzz_antlr4::generate_rules_from_str;
+/// let rules = generate_rules_from_str(
+///     "grammar Digits;
+///      digits : DIGIT+ ;
+///      DIGIT : [0-9] ;",
+/// )
+/// .unwrap();
+/// let value = rule("digits", rules).generate();
+/// ```
+pub fn generate_rules_from_str(source: &str) -> Result<Arc<RwLock<Rules>>, AntlrError> {
+    let parse_tree = parse_source(source, None)?;
+    Ok(translate_grammar(&parse_tree).to_rules())
+}
+
+/// generate_rules_from_reader reads an ANTLR4 grammar from reader and returns a
+/// set of rules that represent it
+pub fn generate_rules_from_reader<R: Read>(reader: R) -> Result<Arc<RwLock<Rules>>, AntlrError> {
+    let parse_tree = read_grammar(reader, None)?;
+    Ok(translate_grammar(&parse_tree).to_rules())
+}
+
+/// generate_rules_from_files takes the paths to several ANTLR4 grammar files and
+/// returns the rules of all of them. A rule defined by more than one file is
+/// taken from the last of them
+pub fn generate_rules_from_files<P: AsRef<Path>>(
+    paths: &[P],
+) -> Result<Arc<RwLock<Rules>>, AntlrError> {
+    let mut rule_set = RuleSet::default();
+    for path in paths {
+        let parse_tree = parse_grammar(path)?;
+        rule_set.rules.extend(translate_grammar(&parse_tree).rules);
+    }
+
+    Ok(rule_set.to_rules())
+}
+
+/// register_rules takes the path to an ANTLR4 grammar file and registers the
+/// rules that represent it in rules, replacing rules with the same name
+pub fn register_rules<P: AsRef<Path>>(
+    path: P,
+    rules: &Arc<RwLock<Rules>>,
+) -> Result<(), AntlrError> {
+    let parse_tree = parse_grammar(path)?;
+    translate_grammar(&parse_tree).register(rules);
+    Ok(())
+}
+
+/// register_rules_from_str takes the source of an ANTLR4 grammar and registers
+/// the rules that represent it in rules, replacing rules with the same name
+pub fn register_rules_from_str(source: &str, rules: &Arc<RwLock<Rules>>) -> Result<(), AntlrError> {
+    let parse_tree = parse_source(source, None)?;
+    translate_grammar(&parse_tree).register(rules);
+    Ok(())
+}
+
+/// register_rules_from_reader reads an ANTLR4 grammar from reader and registers
+/// the rules that represent it in rules, replacing rules with the same name
+pub fn register_rules_from_reader<R: Read>(
+    reader: R,
+    rules: &Arc<RwLock<Rules>>,
+) -> Result<(), AntlrError> {
+    let parse_tree = read_grammar(reader, None)?;
+    translate_grammar(&parse_tree).register(rules);
+    Ok(())
+}
+
+fn parse_grammar<P: AsRef<Path>>(path: P) -> Result<ast::Grammar, AntlrError> {
+    let path = path.as_ref();
+    let f = File::open(path).map_err(AntlrError::in_file(path))?;
+    read_grammar(f, Some(&path.display().to_string()))
+}
+
+/// read_grammar reads a grammar from reader and parses it. file names where it
+/// was read from in errors, which name a reader without one <input>
+fn read_grammar<R: Read>(mut reader: R, file: Option<&str>) -> Result<ast::Grammar, AntlrError> {
     let mut buf = String::new();
-    f.read_to_string(&mut buf)?;
+    reader
+        .read_to_string(&mut buf)
+        .map_err(AntlrError::in_file(file.unwrap_or("<input>")))?;
+    parse_source(&buf, file)
+}
 
-    let parse_tree = antlr4::GrammarParser::new().parse(&buf)?;
-    let rules = Arc::new(RwLock::new(HashMap::new()));
+fn parse_source(source: &str, file: Option<&str>) -> Result<ast::Grammar, AntlrError> {
+    antlr4::GrammarParser::new()
+        .parse(source)
+        .map_err(|err| AntlrError::from_parse_error(err, source, file))
+}
 
+/// translate_grammar translates the rules of a parsed grammar into a RuleSet
+fn translate_grammar(parse_tree: &ast::Grammar) -> RuleSet {
+    let mut rule_set = RuleSet::default();
     for rule in parse_tree.rules().iter() {
         let parts = rule
             .body()
             .iter()
-            .map(|part| translate_rule(part, rule.rule_type(), &rules))
-            .collect::<Vec<Box<dyn Generator>>>();
-        match rule.rule_type() {
-            ast::RuleType::Parser => {
-                let root = JoinWith {
-                    generators: parts,
-                    delimiter: Box::new(byte(0x20)),
-                };
-                register_rule(&rules, rule.name(), root);
-            }
-            ast::RuleType::Lexer | ast::RuleType::Fragment => {
-                let root = Sequence { generators: parts };
-                register_rule(&rules, rule.name(), root);
-            }
-        };
+            .map(|part| translate_rule(part, rule.rule_type()))
+            .collect::<Vec<Tree>>();
+        rule_set
+            .rules
+            .insert(rule.name().to_owned(), join(parts, rule.rule_type()));
     }
 
-    Ok(rules)
+    rule_set
 }
 
-fn translate_rule(
-    operation: &ast::Operation,
-    rule_type: ast::RuleType,
-    rules: &Arc<RwLock<Rules>>,
-) -> Box<dyn Generator> {
+/// join joins the parts of a parser rule with spaces, so that its tokens
+/// stay separate, and concatenates the parts of a lexer rule
+fn join(parts: Vec<Tree>, rule_type: RuleType) -> Tree {
+    match rule_type {
+        RuleType::Parser => Tree::JoinWith {
+            generators: parts,
+            delimiter: Box::new(Tree::ByteLiteral(0x20)),
+        },
+        RuleType::Lexer | RuleType::Fragment => Tree::Sequence(parts),
+    }
+}
+
+fn translate_rule(operation: &ast::Operation, rule_type: ast::RuleType) -> Tree {
     match operation {
-        ast::Operation::Alternate(op) => {
-            let choices = op
-                .iter()
+        ast::Operation::Alternate(op) => Tree::Choice(
+            op.iter()
                 .map(|alternate| {
                     let parts = alternate
                         .iter()
-                        .map(|part| translate_rule(part, rule_type, rules))
-                        .collect::<Vec<Box<dyn Generator>>>();
-                    match rule_type {
-                        RuleType::Parser => Box::new(JoinWith {
-                            generators: parts,
-                            delimiter: Box::new(byte(0x20)),
-                        }) as Box<dyn Generator>,
-                        RuleType::Lexer | RuleType::Fragment => {
-                            Box::new(Sequence { generators: parts }) as Box<dyn Generator>
-                        }
-                    }
+                        .map(|part| translate_rule(part, rule_type))
+                        .collect::<Vec<Tree>>();
+                    join(parts, rule_type)
                 })
-                .collect::<Vec<Box<dyn Generator>>>();
-            return Box::new(Choice { choices: choices });
-        }
-        ast::Operation::Any => Box::new(Any {}),
-        ast::Operation::CharacterClass(cc) => Box::new(Choice {
-            choices: cc
-                .iter()
-                .map(|choice| translate_rule(choice, rule_type, rules))
                 .collect(),
-        }),
+        ),
+        ast::Operation::Any => Tree::Any,
+        ast::Operation::CharacterClass(cc) => Tree::Choice(
+            cc.iter()
+                .map(|choice| translate_rule(choice, rule_type))
+                .collect(),
+        ),
         ast::Operation::Group(op) => {
             let parts = op
                 .iter()
-                .map(|part| translate_rule(part, rule_type, rules))
-                .collect::<Vec<Box<dyn Generator>>>();
-            match rule_type {
-                RuleType::Parser => Box::new(JoinWith {
-                    generators: parts,
-                    delimiter: Box::new(byte(0x20)),
-                }) as Box<dyn Generator>,
-                RuleType::Lexer | RuleType::Fragment => {
-                    Box::new(Sequence { generators: parts }) as Box<dyn Generator>
-                }
-            }
+                .map(|part| translate_rule(part, rule_type))
+                .collect::<Vec<Tree>>();
+            join(parts, rule_type)
         }
-        ast::Operation::Optional(op) => Box::new(Optional {
-            generator: translate_rule(op, rule_type, rules),
-        }) as Box<dyn Generator>,
-        ast::Operation::Plus(op) => Box::new(Many1 {
-            generator: translate_rule(op, rule_type, rules),
-        }) as Box<dyn Generator>,
-        ast::Operation::Range((l, r)) => {
-            let n = l.chars().next().unwrap();
-            let m = r.chars().next().unwrap();
-            Box::new(CharRange { n: n, m: m }) as Box<dyn Generator>
-        }
-        ast::Operation::Rule(op) => Box::new(Rule {
-            rules: rules.clone(),
-            name: op.clone(),
-        }) as Box<dyn Generator>,
-        ast::Operation::Star(op) => Box::new(Many {
-            generator: translate_rule(op, rule_type, rules),
-        }) as Box<dyn Generator>,
-        ast::Operation::StringLiteral(s) => {
-            Box::new(StringLiteral { s: s.clone() }) as Box<dyn Generator>
-        }
-        ast::Operation::Token(t) => Box::new(Rule {
-            rules: rules.clone(),
-            name: t.clone(),
-        }) as Box<dyn Generator>,
-        ast::Operation::CharRange((n, m)) => Box::new(CharRange { n: *n, m: *m }),
-        ast::Operation::Char(c) => Box::new(CharLiteral { ch: *c }),
-        ast::Operation::Not(op) => Box::new(Not {
-            generator: translate_rule(op, rule_type, rules),
-        }),
+        ast::Operation::Optional(op) => Tree::Optional(Box::new(translate_rule(op, rule_type))),
+        ast::Operation::Plus(op) => Tree::Many1(Box::new(translate_rule(op, rule_type))),
+        ast::Operation::Rule(op) => Tree::Rule(op.clone()),
+        ast::Operation::Star(op) => Tree::Many(Box::new(translate_rule(op, rule_type))),
+        ast::Operation::StringLiteral(s) => Tree::StringLiteral(s.clone()),
+        ast::Operation::Token(t) => Tree::Rule(t.clone()),
+        ast::Operation::CharRange((n, m)) => Tree::CharRange { n: *n, m: *m },
+        ast::Operation::Char(c) => Tree::CharLiteral(*c),
+        ast::Operation::Not(op) => Tree::Not(Box::new(translate_rule(op, rule_type))),
     }
 }
 
-#[derive(Debug, Fail)]
-pub enum AntlrError {
-    #[fail(display = "{}", _0)]
-    IoError(std::io::Error),
-    #[fail(display = "{}", _0)]
-    ParseError(String),
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use synfuzz::{byte, register_rule};
 
-impl From<std::io::Error> for AntlrError {
-    fn from(other: std::io::Error) -> Self {
-        AntlrError::IoError(other)
+    const DIGITS: &str = "grammar Digits;\ndigits : DIGIT+ ;\nDIGIT : [0-9] ;\n";
+
+    fn names(rules: &Arc<RwLock<Rules>>) -> Vec<String> {
+        let mut names = rules.read().unwrap().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
     }
-}
-impl<'a> From<lalrpop_util::ParseError<usize, antlr4::Token<'a>, &'a str>> for AntlrError {
-    fn from(other: lalrpop_util::ParseError<usize, antlr4::Token<'a>, &'a str>) -> Self {
-        AntlrError::ParseError(other.to_string())
+
+    #[test]
+    fn test_generate_rules_from_source() {
+        let rules = generate_rules_from_str(DIGITS).unwrap();
+        assert_eq!(names(&rules), vec!["DIGIT", "digits"]);
+
+        let rules = generate_rules_from_reader(DIGITS.as_bytes()).unwrap();
+        assert_eq!(names(&rules), vec!["DIGIT", "digits"]);
+
+        match generate_rules_from_str("grammar Digits;\ndigits : ;;\n") {
+            Err(AntlrError::ParseError(diagnostic)) => {
+                assert_eq!(diagnostic.file, None);
+                assert_eq!((diagnostic.line, diagnostic.column), (2, 11));
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {}
+    #[test]
+    fn test_generate_rules_from_files() {
+        let dir = env::temp_dir().join(format!("synfuzz-antlr4-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let digits = dir.join("Digits.g4");
+        let letters = dir.join("Letters.g4");
+        fs::write(&digits, DIGITS).unwrap();
+        fs::write(
+            &letters,
+            "grammar Letters;\nword : LETTER+ ;\nLETTER : [a-z] ;\n",
+        )
+        .unwrap();
+
+        let rules = generate_rules_from_files(&[&digits, &letters]);
+        let missing = dir.join("Missing.g4");
+        let error = generate_rules_from_files(&[&digits, &missing]).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            names(&rules.unwrap()),
+            vec!["DIGIT", "LETTER", "digits", "word"]
+        );
+        match error {
+            AntlrError::IoError { file, error } => {
+                assert_eq!(file, missing.display().to_string());
+                assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            }
+            other => panic!("expected an I/O error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_register_rules() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(&rules, "DIGIT", byte(b'x'));
+        register_rule(&rules, "space", byte(b' '));
+
+        register_rules_from_str(DIGITS, &rules).unwrap();
+        assert_eq!(names(&rules), vec!["DIGIT", "digits", "space"]);
+        let digit = rules.read().unwrap()["DIGIT"].generate();
+        assert!(digit[0].is_ascii_digit());
+    }
+}
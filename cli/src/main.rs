@@ -0,0 +1,508 @@
+//! synfuzz generates a corpus of test cases from an ANTLR 4 grammar.
+//!
+//! ```text
+//! synfuzz Json.g4 json --count 1000 --seed 1 --output corpus
+//! synfuzz Json.g4 json --count 1000 --dedup --max-size 4096 | xargs -0 ...
+//! synfuzz Json.g4 json --count 1000 --stats json > /dev/null
+//! synfuzz Json.g4 json --count 100 --size 4000-4200 --output corpus
+//! synfuzz Json.g4 json --count 100 --negate --negation edit,case-flip
+//! synfuzz Json.g4 json --count 100 --defects 0.01 --defect-rule STRING=0.1
+//! ```
+//!
+//! Samples are written to individual files in the output directory, named by
+//! the --name template, or to stdout with each sample followed by a NUL byte
+//! when no output directory is given. With --stats, statistics about the
+//! samples are written to stderr once they have all been generated.
+
+extern crate clap;
+extern crate rand;
+extern crate synfuzz;
+extern crate synfuzz_antlr4;
+
+use clap::{App, Arg, ArgMatches};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use std::str::FromStr;
+use std::time::Instant;
+
+use synfuzz::{
+    hash, load_rules, optimize_rules, target_size, write_rules_dot, Context, Defects, Generator,
+    Negation, Rule, RuleSet, Statistics,
+};
+use synfuzz_antlr4::generate_rules;
+
+/// The number of attempts made for each requested sample before giving up on
+/// finding one that is small enough and, with --dedup, not seen before
+const ATTEMPTS_PER_SAMPLE: usize = 100;
+
+/// Sample is a single generated test case
+struct Sample {
+    value: Vec<u8>,
+    negated: bool,
+}
+
+/// Sampler draws samples of the start rule, negating a fraction of them, and
+/// discards those that are too large or duplicates
+struct Sampler {
+    start: Box<dyn Generator>,
+    rng: StdRng,
+    invalid_ratio: f64,
+    negations: Vec<Negation>,
+    defects: Option<Defects>,
+    max_size: Option<usize>,
+    dedup: bool,
+    seen: HashSet<u64>,
+    stats: Option<Statistics>,
+}
+
+impl Sampler {
+    fn next(&mut self) -> Option<Sample> {
+        for _ in 0..ATTEMPTS_PER_SAMPLE {
+            let negated = self.rng.gen_bool(self.invalid_ratio);
+            let started = Instant::now();
+            let (value, derivations) = {
+                let mut ctx = Context::from_rng(&mut self.rng).negations(&self.negations);
+                if self.stats.is_some() {
+                    ctx = ctx.recording();
+                }
+                if let Some(ref defects) = self.defects {
+                    ctx = ctx.defects(defects.clone());
+                }
+                let value = if negated {
+                    self.start.negate_with(&mut ctx)
+                } else {
+                    self.start.generate_with(&mut ctx)
+                };
+                (value, ctx.take_derivations())
+            };
+            let elapsed = started.elapsed();
+
+            if self.max_size.is_some_and(|max| value.len() > max) {
+                continue;
+            }
+
+            if self.dedup && !self.seen.insert(hash(&value)) {
+                continue;
+            }
+
+            if let Some(ref mut stats) = self.stats {
+                stats.record(&value, &derivations, negated, elapsed);
+            }
+            return Some(Sample { value, negated });
+        }
+
+        None
+    }
+}
+
+/// Output is where samples are written
+enum Output {
+    Directory { path: PathBuf, name: String },
+    Stdout,
+}
+
+impl Output {
+    fn write(&self, sample: &Sample, index: usize, count: usize) -> io::Result<()> {
+        match *self {
+            Output::Directory { ref path, ref name } => fs::write(
+                path.join(file_name(name, sample, index, count)),
+                &sample.value,
+            ),
+            Output::Stdout => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(&sample.value)?;
+                stdout.write_all(b"\0")
+            }
+        }
+    }
+}
+
+/// file_name expands the placeholders of a --name template. {n} is the
+/// index of the sample padded to the width of count, {hash} the hash of its
+/// value and {kind} is valid or invalid
+fn file_name(template: &str, sample: &Sample, index: usize, count: usize) -> String {
+    let width = count.saturating_sub(1).to_string().len();
+    let kind = if sample.negated { "invalid" } else { "valid" };
+    template
+        .replace("{n}", &format!("{:0width$}", index, width = width))
+        .replace("{hash}", &format!("{:016x}", hash(&sample.value)))
+        .replace("{kind}", kind)
+}
+
+/// check_name rejects a --name template that would give every sample the
+/// same file name, overwriting all but the last of them
+fn check_name(template: &str, count: usize) -> Result<(), String> {
+    if count > 1 && !template.contains("{n}") && !template.contains("{hash}") {
+        return Err(String::from(
+            "--name must contain {n} or {hash} when --count is more than 1",
+        ));
+    }
+    Ok(())
+}
+
+fn parse<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>, String> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid value '{}' for --{}", value, name)),
+        None => Ok(None),
+    }
+}
+
+/// parse_defects parses the default --defects probability and the
+/// NAME=PROBABILITY pairs of --defect-rule
+fn parse_defects(matches: &ArgMatches) -> Result<Option<Defects>, String> {
+    let probability = parse(matches, "defects")?;
+    let rules = matches.values_of("defect-rule");
+    if probability.is_none() && rules.is_none() {
+        return Ok(None);
+    }
+
+    let probability = probability.unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(String::from("--defects must be between 0 and 1"));
+    }
+    let mut defects = Defects::new(probability);
+    for rule in rules.into_iter().flatten() {
+        let invalid = || format!("invalid value '{}' for --defect-rule", rule);
+        let mut parts = rule.splitn(2, '=');
+        let name = parts.next().unwrap();
+        let probability = parts
+            .next()
+            .and_then(|p| p.parse::<f64>().ok())
+            .filter(|p| (0.0..=1.0).contains(p))
+            .ok_or_else(invalid)?;
+        defects = defects.rule(name, probability);
+    }
+    Ok(Some(defects))
+}
+
+/// parse_size parses a --size of MIN-MAX bytes or of exactly SIZE bytes
+fn parse_size(size: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("invalid value '{}' for --size", size);
+    let mut bounds = size.splitn(2, '-').map(|b| b.trim().parse::<usize>());
+    let min = bounds.next().unwrap().map_err(|_| invalid())?;
+    let max = match bounds.next() {
+        Some(max) => max.map_err(|_| invalid())?,
+        None => min,
+    };
+    if min > max {
+        return Err(invalid());
+    }
+    Ok((min, max))
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let grammar = matches.value_of("grammar").unwrap();
+    let start = matches.value_of("rule").unwrap();
+    let count = parse(matches, "count")?.unwrap_or(1);
+    let max_size = parse(matches, "max-size")?;
+    let invalid_ratio = if matches.is_present("negate") {
+        1.0
+    } else {
+        parse(matches, "invalid-ratio")?.unwrap_or(0.0)
+    };
+    if !(0.0..=1.0).contains(&invalid_ratio) {
+        return Err(String::from("--invalid-ratio must be between 0 and 1"));
+    }
+
+    let stats = matches.value_of("stats");
+    let negations = match matches.value_of("negation") {
+        Some(negations) => negations
+            .split(',')
+            .map(|n| n.trim().parse())
+            .collect::<Result<Vec<Negation>, String>>()?,
+        None => Negation::ALL.to_vec(),
+    };
+    let defects = parse_defects(matches)?;
+    let size = match matches.value_of("size") {
+        Some(size) => Some(parse_size(size)?),
+        None => None,
+    };
+
+    let rules = if grammar.ends_with(".synfuzz") {
+        load_rules(grammar).map_err(|e| format!("{}: {}", grammar, e))?
+    } else {
+        generate_rules(grammar).map_err(|e| e.to_string())?
+    };
+    if matches.is_present("optimize") {
+        optimize_rules(&rules).map_err(|e| e.to_string())?;
+    }
+    if matches.is_present("print-rules") {
+        let rule_set = RuleSet::from_rules(&rules).map_err(|e| e.to_string())?;
+        print!("{}", rule_set);
+        return Ok(());
+    }
+    if matches.is_present("rules-dot") {
+        return write_rules_dot(&rules, io::stdout()).map_err(|e| e.to_string());
+    }
+    if !rules.read().unwrap().contains_key(start) {
+        return Err(format!("rule '{}' does not exist in {}", start, grammar));
+    }
+
+    let seed = match parse(matches, "seed")? {
+        Some(seed) => seed,
+        None => {
+            let seed = rand::random();
+            eprintln!("synfuzz: using seed {}", seed);
+            seed
+        }
+    };
+
+    let output = match matches.value_of("output") {
+        Some(path) => {
+            check_name(matches.value_of("name").unwrap(), count)?;
+            fs::create_dir_all(path).map_err(|e| format!("{}: {}", path, e))?;
+            Output::Directory {
+                path: PathBuf::from(path),
+                name: matches.value_of("name").unwrap().to_owned(),
+            }
+        }
+        None => Output::Stdout,
+    };
+
+    let mut sampler = Sampler {
+        stats: stats.map(|_| Statistics::new().rules(&rules)),
+        start: {
+            let start = Rule {
+                rules,
+                name: start.to_owned(),
+            };
+            match size {
+                Some((min, max)) => Box::new(target_size(start, min, max)),
+                None => Box::new(start),
+            }
+        },
+        rng: StdRng::seed_from_u64(seed),
+        invalid_ratio,
+        negations,
+        defects,
+        max_size,
+        dedup: matches.is_present("dedup"),
+        seen: HashSet::new(),
+    };
+
+    for index in 0..count {
+        let sample = match sampler.next() {
+            Some(sample) => sample,
+            None => {
+                return Err(format!(
+                    "only generated {} of {} samples, the remaining attempts were too large or duplicates",
+                    index, count
+                ))
+            }
+        };
+        output
+            .write(&sample, index, count)
+            .map_err(|e| e.to_string())?;
+    }
+
+    if let Some(ref statistics) = sampler.stats {
+        match stats {
+            Some("json") => eprintln!("{}", statistics.to_json()),
+            _ => eprint!("{}", statistics),
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("synfuzz")
+        .about("Generates a corpus of test cases from an ANTLR 4 or synfuzz grammar")
+        .arg(
+            Arg::with_name("grammar")
+                .help("The grammar to generate from: a .synfuzz file in the synfuzz grammar notation or an ANTLR 4 grammar")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("rule")
+                .help("The rule to start generating from")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("count")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .help("The number of samples to generate [default: 1]"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .short("s")
+                .long("seed")
+                .takes_value(true)
+                .help("Seed the random number generator to make the corpus reproducible"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Write each sample to a file in this directory instead of NUL delimited records to stdout"),
+        )
+        .arg(
+            Arg::with_name("name")
+                .long("name")
+                .takes_value(true)
+                .default_value("{n}")
+                .help("The file name template. {n} is replaced by the index of the sample, {hash} by the hash of its contents and {kind} by valid or invalid"),
+        )
+        .arg(
+            Arg::with_name("dedup")
+                .long("dedup")
+                .help("Skip samples that have already been generated"),
+        )
+        .arg(
+            Arg::with_name("max-size")
+                .long("max-size")
+                .takes_value(true)
+                .help("Skip samples larger than this many bytes"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .help("Steer samples towards MIN-MAX bytes, or SIZE bytes, instead of the default repetition limits"),
+        )
+        .arg(
+            Arg::with_name("negate")
+                .long("negate")
+                .conflicts_with("invalid-ratio")
+                .help("Generate only negated samples"),
+        )
+        .arg(
+            Arg::with_name("negation")
+                .long("negation")
+                .takes_value(true)
+                .help("The comma separated strategies for negating literals: edit, case-flip, truncate, double, invalid and random [default: all]"),
+        )
+        .arg(
+            Arg::with_name("defects")
+                .long("defects")
+                .takes_value(true)
+                .help("The probability, between 0 and 1, of injecting a defect by negating any part of a valid sample"),
+        )
+        .arg(
+            Arg::with_name("defect-rule")
+                .long("defect-rule")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("NAME=PROBABILITY overrides the probability of injecting a defect into the rule NAME"),
+        )
+        .arg(
+            Arg::with_name("invalid-ratio")
+                .long("invalid-ratio")
+                .takes_value(true)
+                .help("The fraction of samples, between 0 and 1, that are negated [default: 0]"),
+        )
+        .arg(
+            Arg::with_name("optimize")
+                .long("optimize")
+                .help("Remove redundant structure from the rules and inline trivial rules, which then no longer appear in statistics or take --defect-rule"),
+        )
+        .arg(
+            Arg::with_name("print-rules")
+                .long("print-rules")
+                .help("Print the rules built from the grammar instead of generating samples"),
+        )
+        .arg(
+            Arg::with_name("rules-dot")
+                .long("rules-dot")
+                .conflicts_with("print-rules")
+                .help("Write the graph of which rules reference which in the Graphviz DOT format instead of generating samples"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .takes_value(true)
+                .possible_values(&["summary", "json"])
+                .help("Write statistics about the samples to stderr as a summary or JSON"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("synfuzz: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+    use synfuzz::{byte, choice, register_rule};
+
+    fn sampler(dedup: bool, max_size: Option<usize>) -> Sampler {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(&rules, "bit", choice!(byte(0x30), byte(0x31)));
+        Sampler {
+            start: Box::new(Rule {
+                rules,
+                name: String::from("bit"),
+            }),
+            rng: StdRng::seed_from_u64(1),
+            invalid_ratio: 0.0,
+            negations: Negation::ALL.to_vec(),
+            defects: None,
+            max_size,
+            dedup,
+            seen: HashSet::new(),
+            stats: None,
+        }
+    }
+
+    #[test]
+    fn dedup_runs_out_of_samples() {
+        let mut sampler = sampler(true, None);
+        let mut values = vec![sampler.next().unwrap().value, sampler.next().unwrap().value];
+        values.sort();
+        assert_eq!(values, vec![b"0".to_vec(), b"1".to_vec()]);
+        assert!(sampler.next().is_none());
+    }
+
+    #[test]
+    fn max_size_skips_large_samples() {
+        assert!(sampler(false, Some(0)).next().is_none());
+        assert!(sampler(false, Some(1)).next().is_some());
+    }
+
+    #[test]
+    fn size_is_a_range_or_exact() {
+        assert_eq!(parse_size("4000-4200"), Ok((4000, 4200)));
+        assert_eq!(parse_size("4096"), Ok((4096, 4096)));
+        assert!(parse_size("4200-4000").is_err());
+        assert!(parse_size("big").is_err());
+    }
+
+    #[test]
+    fn file_name_expands_placeholders() {
+        let sample = Sample {
+            value: b"1".to_vec(),
+            negated: true,
+        };
+        assert_eq!(
+            file_name("{kind}-{n}.txt", &sample, 7, 1000),
+            "invalid-007.txt"
+        );
+        assert_eq!(file_name("{hash}", &sample, 0, 1), "af63ac4c86019afc");
+    }
+
+    #[test]
+    fn name_must_vary_with_count() {
+        assert!(check_name("sample", 1).is_ok());
+        assert!(check_name("sample", 2).is_err());
+        assert!(check_name("{kind}", 2).is_err());
+        assert!(check_name("{kind}-{n}", 2).is_ok());
+        assert!(check_name("{hash}", 2).is_ok());
+    }
+}
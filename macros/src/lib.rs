@@ -0,0 +1,161 @@
+extern crate proc_macro;
+extern crate synfuzz;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// grammar declares the rules of a grammar inline, written in the synfuzz
+/// grammar notation described by synfuzz::parse_rule_set, and evaluates to
+/// their Arc<RwLock<Rules>>. The grammar is parsed while compiling so
+/// syntax errors and references to rules that aren't defined are reported
+/// as compile errors pointing at the offending token. Rules may reference
+/// themselves and each other in any order. Comments are written as Rust
+/// comments and the crate using grammar! must depend on synfuzz
+///
+/// ```
+/// #[macro_use]
+/// extern crate synfuzz_macros;
+/// extern crate synfuzz;
+///
+/// use synfuzz::{rule, Generator};
+///
+/// fn main() {
+///     let rules = grammar! {
+///         // the tree is nested to at most 3 levels by the target size
+///         tree = target_size(node, 1, 64) ;
+///         node = leaf @3 | '[' sep_by(node, ',') ']' @1 ;
+///         leaf = '0'..'9'+ | "nil" ;
+///     };
+///     let value = rule("tree", rules).generate();
+/// }
+/// ```
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate synfuzz_macros;
+/// extern crate synfuzz;
+///
+/// fn main() {
+///     // error: rule 'list' is not defined
+///     let rules = grammar! {
+///         item = '(' list ')' ;
+///     };
+/// }
+/// ```
+#[proc_macro]
+pub fn grammar(input: TokenStream) -> TokenStream {
+    let mut source = Source {
+        text: String::new(),
+        column: 0,
+        spans: vec![],
+    };
+    source.render(input);
+
+    match synfuzz::parse_rule_set(&source.text) {
+        Ok(rule_set) => rule_set
+            .to_rust()
+            .parse()
+            .expect("the generated grammar is valid Rust"),
+        Err(err) => compile_error(&err.message, source.locate(err.column)),
+    }
+}
+
+/// Source is the text of a grammar rendered from the tokens it was written
+/// in, on a single line, along with the span of each token
+struct Source {
+    text: String,
+    /// The column, in chars, at the end of text
+    column: usize,
+    /// The column each token starts at and its span
+    spans: Vec<(usize, Span)>,
+}
+
+impl Source {
+    fn render(&mut self, tokens: TokenStream) {
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, group.span_open());
+                    self.render(group.stream());
+                    self.push(close, group.span_close());
+                    self.push(" ", group.span_close());
+                }
+                TokenTree::Punct(ref punct) if punct.as_char() == '#' => {
+                    // doc comments reach the macro as attributes, and # starts
+                    // a comment that would run to the end of the line
+                    if let Some(TokenTree::Group(_)) = tokens.peek() {
+                        tokens.next();
+                    }
+                }
+                TokenTree::Punct(punct) => {
+                    self.push(&punct.as_char().to_string(), punct.span());
+                    if punct.spacing() == Spacing::Alone {
+                        self.push(" ", punct.span());
+                    }
+                }
+                TokenTree::Ident(ident) => {
+                    self.push(&ident.to_string(), ident.span());
+                    // calls are only recognised when the parenthesis follows
+                    // the name immediately
+                    match tokens.peek() {
+                        Some(TokenTree::Group(ref group))
+                            if group.delimiter() == Delimiter::Parenthesis => {}
+                        _ => self.push(" ", ident.span()),
+                    }
+                }
+                TokenTree::Literal(literal) => {
+                    self.push(&literal.to_string(), literal.span());
+                    self.push(" ", literal.span());
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, text: &str, span: Span) {
+        if text.is_empty() {
+            return;
+        }
+        if text != " " {
+            self.spans.push((self.column, span));
+        }
+        self.text.push_str(text);
+        self.column += text.chars().count();
+    }
+
+    /// locate returns the span of the token at the column of an error
+    /// returned by parse_rule_set, which is a column of the rendered text
+    fn locate(&self, column: usize) -> Span {
+        self.spans
+            .iter()
+            .rev()
+            .find(|&&(start, _)| start < column)
+            .map_or_else(Span::call_site, |&(_, span)| span)
+    }
+}
+
+/// compile_error returns an invocation of compile_error! with message at span
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut literal = Literal::string(message);
+    literal.set_span(span);
+    let mut group = Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(literal)),
+    );
+    group.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+
+    vec![
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(group),
+    ]
+    .into_iter()
+    .collect()
+}
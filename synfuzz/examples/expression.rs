@@ -1,4 +1,3 @@
-#[macro_use]
 extern crate synfuzz;
 
 use std::collections::HashMap;
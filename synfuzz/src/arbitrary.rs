@@ -0,0 +1,136 @@
+use quickcheck::{Arbitrary, Gen};
+use std::fmt;
+use std::marker::PhantomData;
+
+use super::{generate_from_bytes, shrink, Generator};
+
+/// Grammar names the Generator that Generated values come from. quickcheck
+/// builds values from their type alone so the Generator is supplied by an
+/// implementation of this trait on a marker type
+///
+/// ```ignore
+/// struct Expr;
+///
+/// impl Grammar for Expr {
+///     fn generator() -> Box<dyn Generator> {
+///         Box::new(rule("expr", expression_rules()))
+///     }
+/// }
+///
+/// quickcheck! {
+///     fn parses(expr: Generated<Expr>) -> bool {
+///         parse(&expr.value).is_ok()
+///     }
+/// }
+/// ```
+pub trait Grammar: 'static {
+    /// generator returns the Generator to build values from. It is called
+    /// for every value and every round of shrinking, so Generators that are
+    /// expensive to build should be cached
+    fn generator() -> Box<dyn Generator>;
+}
+
+/// Generated is a quickcheck Arbitrary value generated by the Generator of
+/// G. It keeps the decisions it was generated from so that shrinking
+/// rewrites them rather than the value and every shrunk value is still valid
+/// for the grammar
+pub struct Generated<G> {
+    pub value: Vec<u8>,
+    decisions: Vec<u8>,
+    grammar: PhantomData<G>,
+}
+
+impl<G: Grammar> Generated<G> {
+    /// from_decisions generates the value of G for decisions with
+    /// generate_from_bytes
+    pub fn from_decisions(decisions: Vec<u8>) -> Generated<G> {
+        Generated {
+            value: generate_from_bytes(&*G::generator(), &decisions),
+            decisions,
+            grammar: PhantomData,
+        }
+    }
+
+    /// decisions returns the decisions the value was generated from
+    pub fn decisions(&self) -> &[u8] {
+        &self.decisions
+    }
+}
+
+impl<G> Clone for Generated<G> {
+    fn clone(&self) -> Generated<G> {
+        Generated {
+            value: self.value.clone(),
+            decisions: self.decisions.clone(),
+            grammar: PhantomData,
+        }
+    }
+}
+
+impl<G> fmt::Debug for Generated<G> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(&self.value))
+    }
+}
+
+impl<G: Grammar> Arbitrary for Generated<G> {
+    /// The number of decisions drawn grows with the size of g
+    fn arbitrary(g: &mut Gen) -> Generated<G> {
+        let decisions = (0..g.size()).map(|_| u8::arbitrary(g)).collect();
+        Generated::from_decisions(decisions)
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Generated<G>>> {
+        let candidates = shrink(&*G::generator(), &self.decisions);
+        Box::new(candidates.into_iter().map(Generated::from_decisions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{QuickCheck, TestResult};
+    use {byte, choice, many1, seq};
+
+    struct Digits;
+
+    impl Grammar for Digits {
+        fn generator() -> Box<dyn Generator> {
+            Box::new(choice!(
+                seq!(byte(0x28), many1(byte(0x31)), byte(0x29)),
+                many1(byte(0x31))
+            ))
+        }
+    }
+
+    fn is_valid(digits: Generated<Digits>) -> bool {
+        let ones = digits.value.iter().filter(|b| **b == 0x31).count();
+        ones > 0 && (ones == digits.value.len() || ones + 2 == digits.value.len())
+    }
+
+    #[test]
+    fn values_are_valid() {
+        QuickCheck::new().quickcheck(is_valid as fn(Generated<Digits>) -> bool);
+    }
+
+    #[test]
+    fn shrunk_values_are_valid() {
+        let digits = Generated::<Digits>::from_decisions(vec![0x00, 0x03, 0xff]);
+        assert_eq!(digits.value, b"(1111)".to_vec());
+        for shrunk in digits.shrink() {
+            assert!(is_valid(shrunk));
+        }
+    }
+
+    #[test]
+    fn finds_failures() {
+        fn fewer_than_three(digits: Generated<Digits>) -> TestResult {
+            TestResult::from_bool(digits.value.iter().filter(|b| **b == 0x31).count() < 3)
+        }
+
+        let result = QuickCheck::new()
+            .quicktest(fewer_than_three as fn(Generated<Digits>) -> TestResult)
+            .unwrap_err();
+        assert!(result.is_failure());
+    }
+}
@@ -0,0 +1,186 @@
+use rand::rngs::SmallRng;
+use rand::{FromEntropy, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use super::{Context, Derivation, Generator};
+use runner::hash;
+
+/// The default number of duplicates in a row after which a Batch gives up
+const DEFAULT_STALL: usize = 1000;
+
+/// Dedup is what makes a value a duplicate of one that was already generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dedup {
+    /// The value is the same
+    Value,
+    /// The value was produced by expanding the same rules in the same
+    /// structure, regardless of what the rules produced
+    Shape,
+    /// Either the value or its shape is the same
+    Both,
+}
+
+/// Batch generates values that are all different from each other. Values are
+/// generated until there are enough unique ones or too many duplicates have
+/// been generated in a row, which happens once a small grammar has been
+/// exhausted. By default Choice is steered towards the alternatives it has
+/// picked least often so that more of the grammar is explored
+#[derive(Debug)]
+pub struct Batch<'a> {
+    generator: &'a dyn Generator,
+    dedup: Dedup,
+    stall: usize,
+    novelty: bool,
+    rng: SmallRng,
+}
+
+impl<'a> Batch<'a> {
+    /// new creates a Batch of values of generator that are deduplicated by
+    /// value
+    pub fn new(generator: &'a dyn Generator) -> Batch<'a> {
+        Batch {
+            generator,
+            dedup: Dedup::Value,
+            stall: DEFAULT_STALL,
+            novelty: true,
+            rng: SmallRng::from_entropy(),
+        }
+    }
+
+    /// dedup sets what makes a value a duplicate
+    pub fn dedup(mut self, dedup: Dedup) -> Batch<'a> {
+        self.dedup = dedup;
+        self
+    }
+
+    /// stall sets the number of duplicates in a row after which generation
+    /// gives up
+    pub fn stall(mut self, stall: usize) -> Batch<'a> {
+        self.stall = stall;
+        self
+    }
+
+    /// novelty sets whether Choice is steered towards the alternatives it
+    /// has picked least often
+    pub fn novelty(mut self, novelty: bool) -> Batch<'a> {
+        self.novelty = novelty;
+        self
+    }
+
+    /// seed makes the generated values reproducible
+    pub fn seed(mut self, seed: u64) -> Batch<'a> {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// generate returns up to count unique values. Fewer are returned if
+    /// generation stalls
+    pub fn generate(self, count: usize) -> Vec<Vec<u8>> {
+        let generator = self.generator;
+        let mut ctx = Context::from_rng(self.rng).recording();
+        if self.novelty {
+            ctx = ctx.seeking_novelty();
+        }
+
+        let mut values = HashSet::new();
+        let mut shapes = HashSet::new();
+        let mut unique = vec![];
+        let mut duplicates = 0;
+        while unique.len() < count && duplicates < self.stall {
+            let value = generator.generate_with(&mut ctx);
+            let shape = shape(&ctx.take_derivations());
+            let value_hash = hash(&value);
+
+            let novel = match self.dedup {
+                Dedup::Value => !values.contains(&value_hash),
+                Dedup::Shape => !shapes.contains(&shape),
+                Dedup::Both => !values.contains(&value_hash) && !shapes.contains(&shape),
+            };
+            if !novel {
+                duplicates += 1;
+                continue;
+            }
+
+            duplicates = 0;
+            values.insert(value_hash);
+            shapes.insert(shape);
+            unique.push(value);
+        }
+
+        if unique.len() < count {
+            debug!(
+                "generation stalled after {} unique values of {}",
+                unique.len(),
+                count
+            );
+        }
+        unique
+    }
+}
+
+/// shape hashes the names of the rules of derivations and how they nest,
+/// ignoring what they produced
+fn shape(derivations: &[Derivation]) -> u64 {
+    fn write(derivation: &Derivation, hasher: &mut DefaultHasher) {
+        derivation.rule.hash(hasher);
+        derivation.children.len().hash(hasher);
+        for child in &derivation.children {
+            write(child, hasher);
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    derivations.len().hash(&mut hasher);
+    for derivation in derivations {
+        write(derivation, &mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+    use {byte, choice, many1, register_rule, rule, seq};
+
+    #[test]
+    fn stalls_once_exhausted() {
+        let bits = choice!(byte(0x30), byte(0x31));
+        let mut values = Batch::new(&bits).seed(1).stall(100).generate(10);
+        values.sort();
+        assert_eq!(values, vec![b"0".to_vec(), b"1".to_vec()]);
+    }
+
+    #[test]
+    fn dedup_by_shape() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(
+            &rules,
+            "list",
+            choice!(
+                seq!(
+                    rule("digits", rules.clone()),
+                    byte(0x2c),
+                    rule("list", rules.clone())
+                ),
+                rule("digits", rules.clone())
+            ),
+        );
+        register_rule(&rules, "digits", many1(byte(0x31)));
+        let list = rule("list", rules);
+
+        let values = Batch::new(&list)
+            .seed(1)
+            .dedup(Dedup::Shape)
+            .stall(200)
+            .generate(3);
+        let commas = values
+            .iter()
+            .map(|v| v.iter().filter(|b| **b == 0x2c).count())
+            .collect::<HashSet<_>>();
+        assert_eq!(commas.len(), values.len());
+    }
+}
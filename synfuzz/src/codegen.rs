@@ -0,0 +1,229 @@
+use std::fmt::Write;
+
+use super::{RuleSet, Tree};
+
+impl Tree {
+    /// to_rust returns a Rust expression that builds the Generators of the
+    /// Tree. Rules are built from a variable named rules that holds an
+    /// Arc<RwLock<Rules>> and synfuzz is referred to as ::synfuzz
+    pub fn to_rust(&self) -> String {
+        let mut out = String::new();
+        self.write_rust(&mut out);
+        out
+    }
+
+    fn write_rust(&self, out: &mut String) {
+        match *self {
+            Tree::CharLiteral(ch) => {
+                let _ = write!(out, "::synfuzz::CharLiteral {{ ch: {:?} }}", ch);
+            }
+            Tree::StringLiteral(ref s) => {
+                let _ = write!(
+                    out,
+                    "::synfuzz::StringLiteral {{ s: ::std::string::String::from({:?}) }}",
+                    s
+                );
+            }
+            Tree::ByteLiteral(byte) => {
+                let _ = write!(out, "::synfuzz::ByteLiteral {{ byte: {:#04x} }}", byte);
+            }
+            Tree::CharRange { n, m } => {
+                let _ = write!(out, "::synfuzz::CharRange {{ n: {:?}, m: {:?} }}", n, m);
+            }
+            Tree::Any => out.push_str("::synfuzz::Any {}"),
+            Tree::Dictionary(ref entries) => {
+                out.push_str("::synfuzz::Dictionary { entries: vec![");
+                for (i, entry) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    let _ = write!(out, "{:?}.to_vec()", ByteString(entry));
+                }
+                out.push_str("] }");
+            }
+            Tree::Mix {
+                ref generator,
+                ref dictionary,
+                probability,
+            } => {
+                out.push_str("::synfuzz::Mix { generator: ");
+                write_box(out, generator);
+                out.push_str(", dictionary: ");
+                write_box(out, dictionary);
+                let _ = write!(out, ", probability: {:?} }}", probability);
+            }
+            Tree::Choice(ref choices) => {
+                out.push_str("::synfuzz::Choice { choices: ");
+                write_vec(out, choices);
+                out.push_str(" }");
+            }
+            Tree::WeightedChoice {
+                ref choices,
+                ref weights,
+            } => {
+                out.push_str("::synfuzz::WeightedChoice { choices: ");
+                write_vec(out, choices);
+                let _ = write!(out, ", weights: vec!{:?} }}", weights);
+            }
+            Tree::Many(ref g) => write_wrapper(out, "Many", g),
+            Tree::Many1(ref g) => write_wrapper(out, "Many1", g),
+            Tree::Optional(ref g) => write_wrapper(out, "Optional", g),
+            Tree::Rule(ref name) => {
+                let _ = write!(
+                    out,
+                    "::synfuzz::Rule {{ rules: rules.clone(), name: ::std::string::String::from({:?}) }}",
+                    name
+                );
+            }
+            Tree::Sequence(ref generators) => {
+                out.push_str("::synfuzz::Sequence { generators: ");
+                write_vec(out, generators);
+                out.push_str(" }");
+            }
+            Tree::RepeatN { n, ref generator } => {
+                let _ = write!(out, "::synfuzz::RepeatN {{ n: {}, generator: ", n);
+                write_box(out, generator);
+                out.push_str(" }");
+            }
+            Tree::Range {
+                n,
+                m,
+                ref generator,
+            } => {
+                let _ = write!(out, "::synfuzz::Range {{ n: {}, m: {}, generator: ", n, m);
+                write_box(out, generator);
+                out.push_str(" }");
+            }
+            Tree::JoinWith {
+                ref generators,
+                ref delimiter,
+            } => {
+                out.push_str("::synfuzz::JoinWith { generators: ");
+                write_vec(out, generators);
+                out.push_str(", delimiter: ");
+                write_box(out, delimiter);
+                out.push_str(" }");
+            }
+            Tree::SepBy {
+                ref generator,
+                ref separator,
+            } => write_separated(out, "SepBy", generator, separator),
+            Tree::SepBy1 {
+                ref generator,
+                ref separator,
+            } => write_separated(out, "SepBy1", generator, separator),
+            Tree::Not(ref g) => write_wrapper(out, "Not", g),
+            Tree::TargetSize {
+                min,
+                max,
+                ref generator,
+            } => {
+                let _ = write!(
+                    out,
+                    "::synfuzz::TargetSize {{ min: {}, max: {}, generator: ",
+                    min, max
+                );
+                write_box(out, generator);
+                out.push_str(" }");
+            }
+        }
+    }
+}
+
+impl RuleSet {
+    /// to_rust returns a Rust block expression that builds the rules of the
+    /// RuleSet and evaluates to their Arc<RwLock<Rules>>
+    pub fn to_rust(&self) -> String {
+        let mut out = String::from(
+            "{\n    let rules = ::std::sync::Arc::new(::std::sync::RwLock::new(\
+             ::std::collections::HashMap::new()));\n",
+        );
+        for (name, tree) in &self.rules {
+            let _ = write!(out, "    ::synfuzz::register_rule(&rules, {:?}, ", name);
+            tree.write_rust(&mut out);
+            out.push_str(");\n");
+        }
+        out.push_str("    rules\n}");
+        out
+    }
+}
+
+fn write_box(out: &mut String, tree: &Tree) {
+    out.push_str("::std::boxed::Box::new(");
+    tree.write_rust(out);
+    out.push(')');
+}
+
+/// write_vec writes a Vec<Box<dyn Generator>> of trees, leaving the type of
+/// the boxes to be inferred since dyn can't precede an absolute path in
+/// the 2015 edition
+fn write_vec(out: &mut String, trees: &[Tree]) {
+    out.push_str("vec![");
+    for (i, tree) in trees.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_box(out, tree);
+        out.push_str(" as ::std::boxed::Box<_>");
+    }
+    out.push(']');
+}
+
+fn write_wrapper(out: &mut String, kind: &str, generator: &Tree) {
+    let _ = write!(out, "::synfuzz::{} {{ generator: ", kind);
+    write_box(out, generator);
+    out.push_str(" }");
+}
+
+fn write_separated(out: &mut String, kind: &str, generator: &Tree, separator: &Tree) {
+    let _ = write!(out, "::synfuzz::{} {{ generator: ", kind);
+    write_box(out, generator);
+    out.push_str(", separator: ");
+    write_box(out, separator);
+    out.push_str(" }");
+}
+
+/// ByteString formats bytes as a Rust byte string literal with Debug
+struct ByteString<'a>(&'a [u8]);
+
+impl<'a> ::std::fmt::Debug for ByteString<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str("b\"")?;
+        for byte in self.0 {
+            for c in ::std::ascii::escape_default(*byte) {
+                ::std::fmt::Write::write_char(f, c as char)?;
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parse_rule_set;
+
+    #[test]
+    fn writes_rust() {
+        let rule_set = parse_rule_set(
+            "list = '[' sep_by(item, \", \")? ']' ;\n\
+             item = 0x41 @2 | dictionary(\"\\xff\\\"\") | !item{2} ;",
+        )
+        .unwrap();
+        assert_eq!(
+            rule_set.rules["item"].to_rust(),
+            "::synfuzz::WeightedChoice { choices: vec![\
+             ::std::boxed::Box::new(::synfuzz::ByteLiteral { byte: 0x41 }) \
+             as ::std::boxed::Box<_>, \
+             ::std::boxed::Box::new(::synfuzz::Dictionary { entries: vec![b\"\\xff\\\"\".to_vec()] }) \
+             as ::std::boxed::Box<_>, \
+             ::std::boxed::Box::new(::synfuzz::Not { generator: \
+             ::std::boxed::Box::new(::synfuzz::RepeatN { n: 2, generator: \
+             ::std::boxed::Box::new(::synfuzz::Rule { rules: rules.clone(), \
+             name: ::std::string::String::from(\"item\") }) }) }) \
+             as ::std::boxed::Box<_>], weights: vec![2, 1, 1] }"
+        );
+        assert!(rule_set
+            .to_rust()
+            .starts_with("{\n    let rules = ::std::sync::Arc::new("));
+    }
+}
@@ -1,79 +1,167 @@
-use rand::thread_rng;
-use rand::Rng;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 use std::sync::RwLock;
 
-use super::Generator;
+use super::{Context, Generator, Node};
 
 /// The maximum number of repetitions for the Many and Many1 Generators
-const MANY_MAX: usize = 5;
+pub(crate) const MANY_MAX: usize = 5;
 /// The maximum number of repetitions for the SepBy and SepBy1 Generators
-const SEP_BY_MAX: usize = 5;
+pub(crate) const SEP_BY_MAX: usize = 5;
 /// The maximum number of repetitions for the negation of the RepeatN
 /// Generator
 const REPEAT_MAX: usize = 5;
+/// The maximum number of rules followed to find the chars that Not excludes
+const RULE_DEPTH_MAX: usize = 16;
 
 /// Choice is a Generator that will pick one of the Generators specified in
-///2its choices. Each call to the generate method may return a value from a
+/// its choices. Each call to the generate method may return a value from a
 /// different Generator
 #[derive(Debug)]
 pub struct Choice {
-    pub choices: Vec<Box<Generator>>,
+    pub choices: Vec<Box<dyn Generator>>,
 }
 
 impl Generator for Choice {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate Choice");
-        match thread_rng().choose(&self.choices) {
-            Some(generator) => generator.generate(),
-            None => panic!("no choices specified"),
+        if self.choices.is_empty() {
+            panic!("no choices specified");
         }
+
+        let index = if ctx.is_exhausted() || ctx.remaining_size() == Some(0) {
+            ctx.choose_minimal(&self.choices)
+        } else {
+            match ctx.choose_growing(&self.choices) {
+                Some(index) => index,
+                None => ctx.choose(self as *const Choice as usize, self.choices.len()),
+            }
+        };
+        ctx.generate(&*self.choices[index])
     }
 
-    fn negate(&self) -> Vec<u8> {
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("negate Choice");
         // must match any of of choices, except one, or anything not a choice
         match self.choices.len() {
             0 => vec![], // TODO: generate something non-empty
-            1 => self.choices[0].negate(),
-            _ => {
-                let generate_nothing = thread_rng().gen();
-                if generate_nothing {
-                    vec![]
-                } else {
-                    vec![]
-                }
-            }
+            1 => self.choices[0].negate_with(ctx),
+            // TODO: generate values that match none of the choices
+            _ => vec![],
         }
     }
+
+    fn node(&self) -> Node<'_> {
+        Node::Choice(self)
+    }
 }
 
 /// choice is a helper to create a Choice Generator. There is also a macro
 /// that generates the Vec and Boxes the individual generators being passed
 /// as choices for brevity and simplicity
-pub fn choice(choices: Vec<Box<Generator>>) -> impl Generator {
-    Choice { choices: choices }
+pub fn choice(choices: Vec<Box<dyn Generator>>) -> impl Generator {
+    Choice { choices }
+}
+
+/// WeightedChoice is a Generator that will pick one of the Generators
+/// specified in its choices with a probability proportional to its weight.
+/// The weights only apply to picking at random: like Choice it is steered
+/// towards the smallest alternatives once the input or a target size runs
+/// out. A choice without a weight has a weight of 0, and when every weight
+/// is 0 the choices are picked uniformly
+#[derive(Debug)]
+pub struct WeightedChoice {
+    pub choices: Vec<Box<dyn Generator>>,
+    pub weights: Vec<usize>,
+}
+
+impl Generator for WeightedChoice {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("generate WeightedChoice");
+        if self.choices.is_empty() {
+            panic!("no choices specified");
+        }
+
+        let index = if ctx.is_exhausted() || ctx.remaining_size() == Some(0) {
+            ctx.choose_minimal(&self.choices)
+        } else {
+            match ctx.choose_growing(&self.choices) {
+                Some(index) => index,
+                None => self.pick(ctx),
+            }
+        };
+        ctx.generate(&*self.choices[index])
+    }
+
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("negate WeightedChoice");
+        match self.choices.len() {
+            1 => self.choices[0].negate_with(ctx),
+            // TODO: generate values that match none of the choices
+            _ => vec![],
+        }
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::WeightedChoice(self)
+    }
+}
+
+impl WeightedChoice {
+    /// pick returns the index of a choice picked at random by weight
+    fn pick(&self, ctx: &mut Context) -> usize {
+        let weights = &self.weights[..self.weights.len().min(self.choices.len())];
+        let total = weights.iter().sum();
+        if total == 0 {
+            return ctx.below(self.choices.len());
+        }
+
+        let mut pick = ctx.range(0, total);
+        let mut index = 0;
+        while pick >= weights[index] {
+            pick -= weights[index];
+            index += 1;
+        }
+        index
+    }
+}
+
+/// weighted_choice is a helper to create a WeightedChoice Generator from
+/// pairs of a weight and a Generator
+pub fn weighted_choice(choices: Vec<(usize, Box<dyn Generator>)>) -> impl Generator {
+    assert!(
+        choices.iter().any(|(weight, _)| *weight > 0),
+        "at least one weight must be greater than 0"
+    );
+    let (weights, choices) = choices.into_iter().unzip();
+    WeightedChoice { choices, weights }
 }
 
 /// Many is a Generator that will generate 0 or more values of its generator
 #[derive(Debug)]
 pub struct Many {
-    pub generator: Box<Generator>,
+    pub generator: Box<dyn Generator>,
 }
 
 impl Generator for Many {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate Many");
-        let num = thread_rng().gen_range(0, MANY_MAX);
-        (0..num).flat_map(|_| self.generator.generate()).collect()
+        repeat(ctx, 0, MANY_MAX, false, |ctx, _| {
+            ctx.generate(&*self.generator)
+        })
     }
 
-    fn negate(&self) -> Vec<u8> {
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("negate Many");
         // generate nothing or the negation of generator 0..MANY_MAX times
-        let num = thread_rng().gen_range(0, MANY_MAX);
-        (0..num).flat_map(|_| self.generator.negate()).collect()
+        repeat(ctx, 0, MANY_MAX, false, |ctx, _| {
+            self.generator.negate_with(ctx)
+        })
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::Many(self)
     }
 }
 
@@ -87,21 +175,27 @@ pub fn many(generator: impl Generator + 'static) -> impl Generator {
 /// Many1 is a Generator that will generate 1 or more values of its generator
 #[derive(Debug)]
 pub struct Many1 {
-    pub generator: Box<Generator>,
+    pub generator: Box<dyn Generator>,
 }
 
 impl Generator for Many1 {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate Many1");
-        let num = thread_rng().gen_range(1, MANY_MAX);
-        (0..num).flat_map(|_| self.generator.generate()).collect()
+        repeat(ctx, 1, MANY_MAX, false, |ctx, _| {
+            ctx.generate(&*self.generator)
+        })
     }
 
-    fn negate(&self) -> Vec<u8> {
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("negate Many1");
         // generate nothing or the negation of generator 0..MANY_MAX times
-        let num: usize = thread_rng().gen_range(0, MANY_MAX);
-        (0..num).flat_map(|_| self.generator.negate()).collect()
+        repeat(ctx, 0, MANY_MAX, false, |ctx, _| {
+            self.generator.negate_with(ctx)
+        })
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::Many1(self)
     }
 }
 
@@ -116,27 +210,31 @@ pub fn many1(generator: impl Generator + 'static) -> impl Generator {
 /// of its generator or and empty value
 #[derive(Debug)]
 pub struct Optional {
-    pub generator: Box<Generator>,
+    pub generator: Box<dyn Generator>,
 }
 
 impl Generator for Optional {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate Optional");
-        if thread_rng().gen() {
-            self.generator.generate()
+        if ctx.remaining_size() != Some(0) && ctx.flip() {
+            ctx.generate(&*self.generator)
         } else {
             vec![]
         }
     }
 
-    fn negate(&self) -> Vec<u8> {
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("negate Optional");
-        if thread_rng().gen() {
-            self.generator.negate()
+        if ctx.remaining_size() != Some(0) && ctx.flip() {
+            self.generator.negate_with(ctx)
         } else {
             vec![]
         }
     }
+
+    fn node(&self) -> Node<'_> {
+        Node::Optional(self)
+    }
 }
 
 /// optional is a helper to create an Optional Generator
@@ -152,31 +250,53 @@ pub fn optional(generator: impl Generator + 'static) -> impl Generator {
 ///
 /// Only names that have already been registered should be used. If a
 /// corresponding rule does not exist when generate is called it will panic.
-#[derive(Debug)]
 pub struct Rule {
-    pub rules: Arc<RwLock<HashMap<String, Box<Generator>>>>,
+    pub rules: Arc<RwLock<HashMap<String, Box<dyn Generator>>>>,
     pub name: String,
 }
 
+/// Debug only writes the name of the rule rather than every rule, which would
+/// never end for a recursive rule
+impl fmt::Debug for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rule").field("name", &self.name).finish()
+    }
+}
+
 impl Generator for Rule {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate Rule {}", self.name);
         let rules = self.rules.read().unwrap();
         match rules.get(&self.name) {
-            Some(generator) => generator.generate(),
+            Some(generator) => {
+                ctx.enter_rule(&self.name, false);
+                // the body may get a defect of its own, even if it is a leaf
+                let value = ctx.generate(&**generator);
+                ctx.exit_rule(value.len());
+                value
+            }
             None => panic!("rule '{}' does not exist", self.name),
         }
     }
 
-    fn negate(&self) -> Vec<u8> {
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("negate Rule {}", self.name);
         // invoke the negation of the rule
         let rules = self.rules.read().unwrap();
         match rules.get(&self.name) {
-            Some(generator) => generator.negate(),
+            Some(generator) => {
+                ctx.enter_rule(&self.name, true);
+                let value = generator.negate_with(ctx);
+                ctx.exit_rule(value.len());
+                value
+            }
             None => panic!("rule '{}' does not exist", self.name),
         }
     }
+
+    fn node(&self) -> Node<'_> {
+        Node::Rule(self)
+    }
 }
 
 /// rule is a helper to create a Rule Generator
@@ -186,7 +306,7 @@ where
 {
     Rule {
         name: name.into(),
-        rules: rules,
+        rules,
     }
 }
 
@@ -209,7 +329,7 @@ where
 /// multithreaded applications this should be wrapped in an Arc<Mutex<T>>
 /// to provide safe access. Realistically, as long as all rules are added
 /// before generation begins, locking should be unecessary
-pub type Rules = HashMap<String, Box<Generator>>;
+pub type Rules = HashMap<String, Box<dyn Generator>>;
 
 /// Sequence is a Generator that generates all of its generators in the order
 /// in which they are specified. This is useful for sequences of specific
@@ -217,28 +337,36 @@ pub type Rules = HashMap<String, Box<Generator>>;
 /// more helpful
 #[derive(Debug)]
 pub struct Sequence {
-    pub generators: Vec<Box<Generator>>,
+    pub generators: Vec<Box<dyn Generator>>,
 }
 
 impl Generator for Sequence {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate Sequence");
-        self.generators.iter().flat_map(|g| g.generate()).collect()
+        self.generators
+            .iter()
+            .flat_map(|g| ctx.generate(&**g))
+            .collect()
     }
 
-    fn negate(&self) -> Vec<u8> {
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("negate Sequence");
-        self.generators.iter().flat_map(|g| g.negate()).collect()
+        self.generators
+            .iter()
+            .flat_map(|g| g.negate_with(ctx))
+            .collect()
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::Sequence(self)
     }
 }
 
 /// seq is a helper to create a Sequence Generator. This is also a macro that
 /// handles creating the Vec and boxing the individual Generators being
 /// specified for brevity and simplicity
-pub fn seq(generators: Vec<Box<Generator>>) -> impl Generator {
-    Sequence {
-        generators: generators,
-    }
+pub fn seq(generators: Vec<Box<dyn Generator>>) -> impl Generator {
+    Sequence { generators }
 }
 
 /// RepeastN is a Generator that will product the specified Generator between
@@ -246,67 +374,87 @@ pub fn seq(generators: Vec<Box<Generator>>) -> impl Generator {
 #[derive(Debug)]
 pub struct RepeatN {
     pub n: usize,
-    pub generator: Box<Generator>,
+    pub generator: Box<dyn Generator>,
 }
 
 impl Generator for RepeatN {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate RepeatN");
         (0..self.n)
-            .flat_map(|_| self.generator.generate())
+            .flat_map(|_| ctx.generate(&*self.generator))
             .collect()
     }
 
-    fn negate(&self) -> Vec<u8> {
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("negate RepeatN");
         // repeats any number except n times
-        let mut repetitions = thread_rng().gen_range(0, REPEAT_MAX);
+        let mut repetitions = ctx.range(0, REPEAT_MAX);
         if repetitions == self.n {
             repetitions += 1;
         }
 
         (0..repetitions)
-            .flat_map(|_| self.generator.negate())
+            .flat_map(|_| self.generator.negate_with(ctx))
             .collect()
     }
+
+    fn node(&self) -> Node<'_> {
+        Node::RepeatN(self)
+    }
 }
 
 /// repeat_n is a helper to create a RepeatN Generator
 pub fn repeat_n(generator: impl Generator + 'static, n: usize) -> impl Generator {
     RepeatN {
-        n: n,
+        n,
         generator: Box::new(generator),
     }
 }
 
 /// Range is a Generator that will produce the specified Generator between n
-/// and m times
+/// and m times inclusive
 #[derive(Debug)]
 pub struct Range {
     pub n: usize,
     pub m: usize,
-    pub generator: Box<Generator>,
+    pub generator: Box<dyn Generator>,
 }
 
 impl Generator for Range {
-    fn generate(&self) -> Vec<u8> {
-        trace!("repeat Range");
-        let times = thread_rng().gen_range(self.n, self.m);
-        (self.n..times)
-            .flat_map(|_| self.generator.generate())
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("generate Range");
+        repeat(ctx, self.n, self.m.saturating_add(1), true, |ctx, _| {
+            ctx.generate(&*self.generator)
+        })
+    }
+
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("negate Range");
+        // repeats fewer than n or more than m times
+        let repetitions = if self.n > 0 && (self.m == usize::MAX || ctx.flip()) {
+            ctx.range(0, self.n)
+        } else if self.m < usize::MAX {
+            ctx.range(self.m + 1, self.m.saturating_add(1 + REPEAT_MAX))
+        } else {
+            // every count is in range so only the values are negated
+            ctx.range(0, REPEAT_MAX)
+        };
+
+        (0..repetitions)
+            .flat_map(|_| self.generator.negate_with(ctx))
             .collect()
     }
 
-    fn negate(&self) -> Vec<u8> {
-        unimplemented!()
+    fn node(&self) -> Node<'_> {
+        Node::Range(self)
     }
 }
 
 /// range is a helper to create a Range Generator
 pub fn range(generator: impl Generator + 'static, n: usize, m: usize) -> impl Generator {
     Range {
-        n: n,
-        m: m,
+        n,
+        m,
         generator: Box::new(generator),
     }
 }
@@ -320,45 +468,58 @@ pub fn range(generator: impl Generator + 'static, n: usize, m: usize) -> impl Ge
 /// a rule.
 #[derive(Debug)]
 pub struct JoinWith {
-    pub generators: Vec<Box<Generator>>,
-    pub delimiter: Box<Generator>,
+    pub generators: Vec<Box<dyn Generator>>,
+    pub delimiter: Box<dyn Generator>,
 }
 
 impl Generator for JoinWith {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate JoinWith");
         let mut first = true;
         self.generators
             .iter()
             .flat_map(|g| {
-                let mut value = g.generate();
+                let mut value = vec![];
                 if !first {
-                    let mut d = self.delimiter.generate();
-                    d.extend(value);
-                    value = d;
+                    value = ctx.generate(&*self.delimiter);
                 } else {
                     first = false;
                 }
+                value.extend(ctx.generate(&**g));
                 value
             })
             .collect()
     }
 
-    fn negate(&self) -> Vec<u8> {
-        unimplemented!()
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("negate JoinWith");
+        // negate every value but keep the delimiters so the negated values
+        // are still separated the way the grammar expects
+        let mut value = vec![];
+        for (i, g) in self.generators.iter().enumerate() {
+            if i > 0 {
+                value.extend(self.delimiter.generate_with(ctx));
+            }
+            value.extend(g.negate_with(ctx));
+        }
+
+        value
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::JoinWith(self)
     }
 }
 
 /// join_with is a helper to create a JoinWith Generator. This is also a
 /// macro that handles creating the Vec and boxing the individual Generators
 /// being specified for brevity and simplicity
-
 pub fn join_with(
-    generators: Vec<Box<Generator>>,
+    generators: Vec<Box<dyn Generator>>,
     delimiters: impl Generator + 'static,
 ) -> impl Generator {
     JoinWith {
-        generators: generators,
+        generators,
         delimiter: Box::new(delimiters),
     }
 }
@@ -368,35 +529,98 @@ pub fn join_with(
 /// separator being present, only there there is more than one
 #[derive(Debug)]
 pub struct SepBy {
-    pub generator: Box<Generator>,
-    pub separator: Box<Generator>,
+    pub generator: Box<dyn Generator>,
+    pub separator: Box<dyn Generator>,
 }
 
 impl Generator for SepBy {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate SepBy");
-        let limit = thread_rng().gen_range(0, SEP_BY_MAX);
+        separated(&*self.generator, &*self.separator, 0, false, ctx)
+    }
 
-        let mut first = true;
-        (0..limit)
-            .flat_map(|_| {
-                let mut value = self.generator.generate();
-                if first {
-                    first = false;
-                } else {
-                    let mut separator = self.separator.generate();
-                    separator.extend(self.generator.generate());
-                    value = separator;
-                }
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("negate SepBy");
+        // generate nothing or the negation of generator 0..SEP_BY_MAX times
+        separated(&*self.generator, &*self.separator, 0, true, ctx)
+    }
 
-                value
-            })
-            .collect()
+    fn node(&self) -> Node<'_> {
+        Node::SepBy(self)
+    }
+}
+
+/// generate at least min values, or negations, of generator with separator
+/// between each of them
+fn separated(
+    generator: &dyn Generator,
+    separator: &dyn Generator,
+    min: usize,
+    negate: bool,
+    ctx: &mut Context,
+) -> Vec<u8> {
+    repeat(ctx, min, SEP_BY_MAX, false, |ctx, i| {
+        let mut value = vec![];
+        if negate {
+            if i > 0 {
+                value.extend(separator.generate_with(ctx));
+            }
+            value.extend(generator.negate_with(ctx));
+        } else {
+            if i > 0 {
+                value.extend(ctx.generate(separator));
+            }
+            value.extend(ctx.generate(generator));
+        }
+        value
+    })
+}
+
+/// repeat concatenates between low inclusive and high exclusive values
+/// generated by item, which is passed the index of each value. When the size
+/// is being steered the number of values is picked by the remaining size
+/// instead: values are generated until it has been used up, each steered
+/// towards a share of what is left so that nested repetitions leave room for
+/// more values. high is then only respected if bounded is true. Bytes of a
+/// value that weren't reported with Context::produced are counted once the
+/// value is generated, so that the remaining size still runs out
+fn repeat<F>(ctx: &mut Context, low: usize, high: usize, bounded: bool, mut item: F) -> Vec<u8>
+where
+    F: FnMut(&mut Context, usize) -> Vec<u8>,
+{
+    let mut value = vec![];
+    if ctx.remaining_size().is_none() {
+        let count = ctx.range(low, high);
+        for i in 0..count {
+            value.extend(item(ctx, i));
+        }
+        return value;
     }
 
-    fn negate(&self) -> Vec<u8> {
-        unimplemented!()
+    let mut i = 0;
+    loop {
+        let remaining = ctx.remaining_size().unwrap_or(0);
+        if i >= low && (remaining == 0 || (bounded && i + 1 >= high)) {
+            break;
+        }
+
+        let produced = ctx.produced_len();
+        ctx.enter_share();
+        let generated = item(ctx, i);
+        ctx.exit_size();
+        let reported = ctx.produced_len() - produced;
+        if generated.len() > reported {
+            ctx.produced(generated.len() - reported);
+        }
+        // stop repeating values that never make progress towards the size
+        if generated.is_empty() && i >= low {
+            break;
+        }
+        value.extend(generated);
+        i += 1;
     }
+
+    value
 }
 
 /// sep_by is a helper to create a SepBy Generator
@@ -415,34 +639,24 @@ pub fn sep_by(
 /// separator being present, only there there is more than one
 #[derive(Debug)]
 pub struct SepBy1 {
-    pub generator: Box<Generator>,
-    pub separator: Box<Generator>,
+    pub generator: Box<dyn Generator>,
+    pub separator: Box<dyn Generator>,
 }
 
 impl Generator for SepBy1 {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate SepBy1");
-        let limit = thread_rng().gen_range(1, SEP_BY_MAX);
-
-        let mut first = true;
-        (0..limit)
-            .flat_map(|_| {
-                let mut value = self.generator.generate();
-                if first {
-                    first = false;
-                } else {
-                    let mut separator = self.separator.generate();
-                    separator.extend(self.generator.generate());
-                    value = separator;
-                }
+        separated(&*self.generator, &*self.separator, 1, false, ctx)
+    }
 
-                value
-            })
-            .collect()
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("negate SepBy1");
+        // generate nothing or the negation of generator 0..SEP_BY_MAX times
+        separated(&*self.generator, &*self.separator, 0, true, ctx)
     }
 
-    fn negate(&self) -> Vec<u8> {
-        unimplemented!()
+    fn node(&self) -> Node<'_> {
+        Node::SepBy1(self)
     }
 }
 
@@ -457,23 +671,42 @@ pub fn sep_by1(
     }
 }
 
-/// Not is a generator that will return the negation of it's generator. The
-/// implemenation is dependent on the negate implementation for all other
-/// generators
+/// Not is a generator that will return the negation of it's generator. When
+/// the generator matches a single char, such as a literal, a range or a
+/// choice of them, the negation is any other single char, like ~ in a lexer
+/// rule. Otherwise the implemenation is dependent on the negate
+/// implementation for all other generators
 #[derive(Debug)]
 pub struct Not {
-    pub generator: Box<Generator>,
+    pub generator: Box<dyn Generator>,
 }
 
 impl Generator for Not {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("generate Not");
-        self.generator.negate()
+        let mut ranges = vec![];
+        if !char_set(self.generator.node(), &mut ranges, 0) {
+            return self.generator.negate_with(ctx);
+        }
+
+        match complement(&ranges, ctx.char()) {
+            Some(c) => {
+                let mut s = String::with_capacity(4);
+                s.push(c);
+                ctx.produced(s.len());
+                s.into_bytes()
+            }
+            None => self.generator.negate_with(ctx),
+        }
     }
 
-    fn negate(&self) -> Vec<u8> {
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
         trace!("negate Not");
-        self.generator.generate()
+        self.generator.generate_with(ctx)
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::Not(self)
     }
 }
 
@@ -484,12 +717,134 @@ pub fn not(generator: impl Generator + 'static) -> impl Generator {
     }
 }
 
+/// char_set adds the inclusive ranges of the chars matched by node to ranges
+/// and returns true if node only ever matches a single char from them.
+/// Rules are followed to the depth of RULE_DEPTH_MAX
+fn char_set(node: Node, ranges: &mut Vec<(u32, u32)>, depth: usize) -> bool {
+    match node {
+        Node::CharLiteral(literal) => ranges.push((literal.ch as u32, literal.ch as u32)),
+        Node::StringLiteral(literal) => {
+            let mut chars = literal.s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => ranges.push((c as u32, c as u32)),
+                _ => return false,
+            }
+        }
+        Node::ByteLiteral(literal) if literal.byte.is_ascii() => {
+            ranges.push((u32::from(literal.byte), u32::from(literal.byte)))
+        }
+        Node::CharRange(range) => ranges.push((range.n as u32, range.m as u32)),
+        Node::Choice(choice) => {
+            return choice
+                .choices
+                .iter()
+                .all(|c| char_set(c.node(), ranges, depth))
+        }
+        Node::WeightedChoice(choice) => {
+            return choice
+                .choices
+                .iter()
+                .all(|c| char_set(c.node(), ranges, depth))
+        }
+        Node::Sequence(sequence) if sequence.generators.len() == 1 => {
+            return char_set(sequence.generators[0].node(), ranges, depth)
+        }
+        Node::Rule(rule) if depth < RULE_DEPTH_MAX => {
+            let rules = rule.rules.read().unwrap();
+            return match rules.get(&rule.name) {
+                Some(generator) => char_set(generator.node(), ranges, depth + 1),
+                None => false,
+            };
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// complement returns the first char from start onwards, wrapping around,
+/// that isn't in any of the ranges, or None if every char is
+fn complement(ranges: &[(u32, u32)], start: char) -> Option<char> {
+    let mut value = start as u32;
+    let mut wrapped = false;
+    loop {
+        if let Some(&(_, m)) = ranges.iter().find(|&&(n, m)| n <= value && value <= m) {
+            value = m.saturating_add(1);
+        } else if let Some(c) = ::std::char::from_u32(value) {
+            return Some(c);
+        } else if value < 0xe000 {
+            // skip the surrogates, which aren't chars
+            value = 0xe000;
+        } else if !wrapped {
+            wrapped = true;
+            value = 0;
+        } else {
+            return None;
+        }
+    }
+}
+
+/// TargetSize is a Generator that steers the value of its generator towards
+/// a size between min and max bytes inclusive. Many, Many1, SepBy, SepBy1
+/// and Range repeat their values until the size is reached, rather than up
+/// to their fixed maxima, and Choice favors the alternatives that recurse
+/// while there is size left and picks the alternative with the smallest
+/// derivation once there isn't. The size is a target rather than a
+/// guarantee: values can be larger if the grammar can't be closed within the
+/// remaining bytes and smaller if it has no repetition or recursion to grow
+#[derive(Debug)]
+pub struct TargetSize {
+    pub min: usize,
+    pub max: usize,
+    pub generator: Box<dyn Generator>,
+}
+
+impl Generator for TargetSize {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("generate TargetSize");
+        ctx.enter_size(self.min, self.max);
+        let value = ctx.generate(&*self.generator);
+        ctx.exit_size();
+        value
+    }
+
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("negate TargetSize");
+        ctx.enter_size(self.min, self.max);
+        let value = self.generator.negate_with(ctx);
+        ctx.exit_size();
+        value
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::TargetSize(self)
+    }
+}
+
+/// target_size is a helper to create a TargetSize Generator
+pub fn target_size(generator: impl Generator + 'static, min: usize, max: usize) -> impl Generator {
+    assert!(min <= max, "min must not be greater than max");
+    TargetSize {
+        min,
+        max,
+        generator: Box::new(generator),
+    }
+}
+
 #[macro_export]
 macro_rules! choice {
     ( $( $x:expr ),* ) => {
         choice(vec![
             $(Box::new($x)),*
-        ]);
+        ])
+    };
+}
+
+#[macro_export]
+macro_rules! weighted_choice {
+    ( $( $weight:expr => $x:expr ),* ) => {
+        weighted_choice(vec![
+            $(($weight, Box::new($x) as Box<dyn Generator>)),*
+        ])
     };
 }
 
@@ -498,7 +853,7 @@ macro_rules! seq {
     ( $( $x:expr ),* ) => {
         seq(vec![
             $(Box::new($x)),*
-        ]);
+        ])
     };
 }
 
@@ -507,16 +862,20 @@ macro_rules! join_with {
     ( $delimiter:expr, $( $x:expr ),* ) => {
         join_with(vec![
             $(Box::new($x)),*
-        ], $delimiter);
-    }
+        ], $delimiter)
+    };
 }
 
 #[cfg(test)]
 mod test {
     use regex::Regex;
 
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
     use super::*;
-    use value::byte;
+    use generate_from_bytes;
+    use value::{byte, ch, char_range, string};
 
     #[test]
     fn generate_choice() {
@@ -532,6 +891,45 @@ mod test {
         assert!(generated != vec![0x41] && generated != vec![0x42]);
     }
 
+    #[test]
+    fn generate_weighted_choice() {
+        let generator = weighted_choice!(3 => byte(0x41), 1 => byte(0x42), 0 => byte(0x43));
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1));
+        let a = (0..1000)
+            .map(|_| generator.generate_with(&mut ctx))
+            .filter(|v| {
+                assert_ne!(v, &vec![0x43]);
+                v == &vec![0x41]
+            })
+            .count();
+        assert!(a > 700 && a < 800);
+    }
+
+    #[test]
+    fn generate_weighted_choice_without_weights() {
+        let choices =
+            || -> Vec<Box<dyn Generator>> { vec![Box::new(byte(0x41)), Box::new(byte(0x42))] };
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1));
+        for weights in [vec![], vec![0, 0], vec![0]] {
+            let generator = WeightedChoice {
+                choices: choices(),
+                weights,
+            };
+            let values = (0..100)
+                .map(|_| generator.generate_with(&mut ctx))
+                .collect::<Vec<_>>();
+            assert!(values.contains(&vec![0x41]) && values.contains(&vec![0x42]));
+        }
+
+        let generator = WeightedChoice {
+            choices: choices(),
+            weights: vec![0, 1, 7],
+        };
+        for _ in 0..100 {
+            assert_eq!(generator.generate_with(&mut ctx), vec![0x42]);
+        }
+    }
+
     #[test]
     fn generate_many() {
         let generator = many(byte(0x41));
@@ -559,7 +957,7 @@ mod test {
         let generator = many1(byte(0x41));
         let generated = generator.generate();
         let generated_string = String::from_utf8_lossy(&generated);
-        assert!(generated.len() > 0 && generated.len() < MANY_MAX);
+        assert!(!generated.is_empty() && generated.len() < MANY_MAX);
         let r = Regex::new(r"\AA+\z").unwrap();
         assert!(r.is_match(&generated_string));
     }
@@ -580,7 +978,7 @@ mod test {
         let generator = optional(byte(0x41));
         let generated = generator.generate();
         assert!(generated.len() < 2);
-        if generated.len() > 0 {
+        if !generated.is_empty() {
             assert!(generated == vec![0x41]);
         }
     }
@@ -590,7 +988,7 @@ mod test {
         let generator = optional(byte(0x41));
         let generated = generator.negate();
         assert!(generated.len() < 2);
-        if generated.len() > 0 {
+        if !generated.is_empty() {
             assert_ne!(generated, vec![0x41]);
         }
     }
@@ -629,6 +1027,107 @@ mod test {
         assert_ne!(generated, vec![0x41, 0x41, 0x41, 0x41, 0x41]);
     }
 
+    #[test]
+    fn negate_sep_by() {
+        let generator = sep_by1(byte(0x41), byte(0x2c));
+        let generated = generator.negate();
+        let generated_string = String::from_utf8_lossy(&generated);
+        let r = Regex::new(r"\A[^A]*\z").unwrap();
+        assert!(r.is_match(&generated_string));
+    }
+
+    #[test]
+    fn generate_range() {
+        let generator = range(byte(0x41), 2, 4);
+        for _ in 0..20 {
+            let generated = generator.generate();
+            assert!(generated.len() >= 2 && generated.len() <= 4);
+        }
+    }
+
+    #[test]
+    fn range_up_to_usize_max() {
+        let generator = range(byte(0x41), 0, usize::MAX);
+        assert!(generator
+            .generate_with(&mut Context::from_bytes(&[]))
+            .is_empty());
+        assert!(generator.negate().len() < REPEAT_MAX);
+
+        let generator = range(byte(0x41), 2, usize::MAX);
+        assert!(generator.negate().len() < 2);
+    }
+
+    #[test]
+    fn target_size_steers_repetitions() {
+        let generator = target_size(sep_by(many1(byte(0x41)), byte(0x2c)), 4000, 4200);
+        for seed in 0..10 {
+            let mut ctx = Context::from_rng(SmallRng::seed_from_u64(seed));
+            let generated = generator.generate_with(&mut ctx);
+            assert!(generated.len() >= 4000 && generated.len() <= 4200);
+            assert!(generated.contains(&0x2c));
+        }
+    }
+
+    /// Unreported is a Generator that doesn't report what it produces
+    #[derive(Debug)]
+    struct Unreported;
+
+    impl Generator for Unreported {
+        fn generate_with(&self, _ctx: &mut Context) -> Vec<u8> {
+            vec![0x41]
+        }
+
+        fn negate_with(&self, _ctx: &mut Context) -> Vec<u8> {
+            vec![0x42]
+        }
+    }
+
+    #[test]
+    fn target_size_counts_unreported_bytes() {
+        let generator = target_size(many(Unreported), 1, 10);
+        for seed in 0..10 {
+            let mut ctx = Context::from_rng(SmallRng::seed_from_u64(seed));
+            let generated = generator.generate_with(&mut ctx);
+            assert!(!generated.is_empty() && generated.len() <= 10);
+        }
+    }
+
+    #[test]
+    fn target_size_steers_recursion() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(
+            &rules,
+            "value",
+            choice!(
+                many1(byte(0x31)),
+                seq!(
+                    byte(0x5b),
+                    sep_by(rule("value", rules.clone()), byte(0x2c)),
+                    byte(0x5d)
+                )
+            ),
+        );
+        register_rule(
+            &rules,
+            "list",
+            choice!(
+                byte(0x78),
+                seq!(byte(0x78), byte(0x2c), rule("list", rules.clone()))
+            ),
+        );
+
+        for seed in 0..10 {
+            let mut ctx = Context::from_rng(SmallRng::seed_from_u64(seed));
+            let value = target_size(rule("value", rules.clone()), 1000, 1100);
+            let generated = value.generate_with(&mut ctx);
+            assert!(generated.len() >= 1000 && generated.len() <= 1100);
+
+            let list = target_size(rule("list", rules.clone()), 50, 55);
+            let generated = list.generate_with(&mut ctx);
+            assert!(generated.len() >= 50 && generated.len() <= 55);
+        }
+    }
+
     #[test]
     fn generate_not() {
         let generator = not(byte(0x41));
@@ -636,10 +1135,65 @@ mod test {
         assert_ne!(generated, vec![0x41]);
     }
 
+    #[test]
+    fn not_is_the_complement_of_a_char_set() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(&rules, "QUOTE", string("\""));
+        let quoted = seq!(ch('"'), many(not(string("\""))), ch('"'));
+        let rule_quoted = seq!(ch('"'), many(not(rule("QUOTE", rules.clone()))), ch('"'));
+        let letters = not(choice!(ch('_'), char_range('a', 'z')));
+
+        for seed in 0..100 {
+            let mut ctx = Context::from_rng(SmallRng::seed_from_u64(seed));
+            for generator in &[&quoted, &rule_quoted] {
+                let generated = String::from_utf8(generator.generate_with(&mut ctx)).unwrap();
+                assert!(generated.len() >= 2);
+                assert!(!generated[1..generated.len() - 1].contains('"'));
+            }
+
+            let generated = String::from_utf8(letters.generate_with(&mut ctx)).unwrap();
+            assert_eq!(generated.chars().count(), 1);
+            assert!(!generated.contains(|c: char| c == '_' || c.is_ascii_lowercase()));
+        }
+
+        assert_eq!(complement(&[(0, 0x60)], '\0'), Some('a'));
+        assert_eq!(complement(&[(0, 0xd7ff)], 'a'), Some('\u{e000}'));
+        assert_eq!(complement(&[(0x61, 0x10_ffff)], 'z'), Some('\0'));
+        assert_eq!(complement(&[(0, 0x10_ffff)], 'a'), None);
+    }
+
     #[test]
     fn negate_not() {
         let generator = not(byte(0x41));
         let generated = generator.negate();
         assert!(generated == vec![0x41]);
     }
+
+    fn parenthesized() -> Arc<RwLock<Rules>> {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        let expr = choice!(
+            seq!(byte(0x28), rule("expr", rules.clone()), byte(0x29)),
+            many1(byte(0x78))
+        );
+        register_rule(&rules, "expr", expr);
+        rules
+    }
+
+    #[test]
+    fn generate_from_bytes_is_deterministic() {
+        let rules = parenthesized();
+        let generator = rule("expr", rules);
+        let data = [0x00, 0x00, 0x01, 0x03, 0xff];
+        let generated = generate_from_bytes(&generator, &data);
+        assert_eq!(generated, b"((xxxx))".to_vec());
+        assert_eq!(generate_from_bytes(&generator, &data), generated);
+    }
+
+    #[test]
+    fn exhausted_bytes_generate_minimal_derivation() {
+        let rules = parenthesized();
+        let generator = rule("expr", rules);
+        assert_eq!(generate_from_bytes(&generator, &[]), b"x".to_vec());
+        assert_eq!(generate_from_bytes(&generator, &[0x00]), b"(x)".to_vec());
+    }
 }
@@ -0,0 +1,577 @@
+use rand::thread_rng;
+use rand::{Rng, RngCore};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use super::{Defects, Derivation, Generator, Negation, Node, Rules};
+use derivation::Recorder;
+
+const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// The depth of nested rules beyond which Choice is no longer steered
+/// towards recursion when the size is being steered. Deeper values are grown
+/// by repetitions instead, which keeps the stack from overflowing
+const GROWTH_DEPTH_MAX: usize = 32;
+
+/// Context is the source of every decision made while generating a value.
+/// By default it is backed by a random number generator but it can also
+/// consume a byte slice, such as the input handed to a fuzz target by
+/// libFuzzer, so that each Choice index, repetition count and Optional flag
+/// is read from the input instead. Generation from a byte slice is
+/// deterministic and once the bytes run out every decision falls back to its
+/// smallest value and Choice picks the alternative with the smallest
+/// derivation, so generation always terminates with a minimal value.
+///
+/// A Context can also record the Derivation of each value it generates,
+/// inject Defects into otherwise valid values and, when backed by a random
+/// number generator, steer Choice away from the alternatives it has already
+/// picked.
+///
+/// While a TargetSize Generator is being generated the Context tracks how
+/// much of its size is left, which steers repetitions and Choice towards
+/// values of that size instead of the fixed maxima of each combinator.
+pub struct Context<'a> {
+    entropy: Entropy<'a>,
+    costs: Costs,
+    recorder: Option<Recorder>,
+    novelty: Option<HashMap<(usize, usize), usize>>,
+    produced: usize,
+    targets: Vec<Target>,
+    depth: usize,
+    negations: Vec<Negation>,
+    defects: Option<Defects>,
+}
+
+/// Target is the number of bytes a part of a value is steered towards. A
+/// share is the part of a target given to one value of a repetition
+struct Target {
+    start: usize,
+    goal: usize,
+    share: bool,
+}
+
+enum Entropy<'a> {
+    Rng(Box<dyn RngCore + 'a>),
+    Bytes { data: &'a [u8], position: usize },
+}
+
+impl Context<'static> {
+    /// new creates a Context backed by the thread local random number
+    /// generator. This is what Generator::generate and Generator::negate use
+    pub fn new() -> Context<'static> {
+        Context::from_rng(thread_rng())
+    }
+}
+
+impl Default for Context<'static> {
+    fn default() -> Context<'static> {
+        Context::new()
+    }
+}
+
+impl<'a> Context<'a> {
+    /// from_rng creates a Context backed by the specified random number
+    /// generator. Using a seeded generator makes generation reproducible
+    pub fn from_rng<R: RngCore + 'a>(rng: R) -> Context<'a> {
+        Context {
+            entropy: Entropy::Rng(Box::new(rng)),
+            costs: Costs::default(),
+            recorder: None,
+            novelty: None,
+            produced: 0,
+            targets: vec![],
+            depth: 0,
+            negations: Negation::ALL.to_vec(),
+            defects: None,
+        }
+    }
+
+    /// from_bytes creates a Context that reads every decision from data
+    pub fn from_bytes(data: &'a [u8]) -> Context<'a> {
+        Context {
+            entropy: Entropy::Bytes { data, position: 0 },
+            costs: Costs::default(),
+            recorder: None,
+            novelty: None,
+            produced: 0,
+            targets: vec![],
+            depth: 0,
+            negations: Negation::ALL.to_vec(),
+            defects: None,
+        }
+    }
+
+    /// recording enables recording the Derivation of generated values. See
+    /// take_derivations
+    pub fn recording(mut self) -> Context<'a> {
+        self.recorder = Some(Recorder::default());
+        self
+    }
+
+    /// seeking_novelty makes alternatives of a Choice less likely the more
+    /// often they have been picked by this Context, so that generating many
+    /// values explores more of the grammar. It has no effect on a Context
+    /// reading its decisions from bytes
+    pub fn seeking_novelty(mut self) -> Context<'a> {
+        self.novelty = Some(HashMap::new());
+        self
+    }
+
+    /// negations selects the strategies literals pick from when they are
+    /// negated. By default every Negation is used
+    pub fn negations(mut self, negations: &[Negation]) -> Context<'a> {
+        assert!(!negations.is_empty(), "at least one negation is required");
+        self.negations = negations.to_vec();
+        self
+    }
+
+    /// negation returns one of the selected strategies for negating a
+    /// literal
+    pub fn negation(&mut self) -> Negation {
+        if self.negations.len() == 1 {
+            return self.negations[0];
+        }
+        let index = self.below(self.negations.len());
+        self.negations[index]
+    }
+
+    /// defects enables injecting defects into generated values, negating the
+    /// Generators combinators generate with the probabilities of defects
+    pub fn defects(mut self, defects: Defects) -> Context<'a> {
+        self.defects = Some(defects);
+        self
+    }
+
+    /// generate returns a value of generator or, with the probability set
+    /// by defects, a defect in the form of its negation. Combinators generate
+    /// the Generators they are made of with it
+    pub fn generate(&mut self, generator: &dyn Generator) -> Vec<u8> {
+        let probability = match self.defects {
+            Some(ref defects) => defects.probability(generator.node()),
+            None => 0.0,
+        };
+        if probability == 0.0 || !self.probability(probability) {
+            return generator.generate_with(self);
+        }
+
+        let node = generator.node();
+        trace!("inject defect {}", node.kind());
+        let start = self.recorder.as_ref().map_or(0, |r| r.position());
+        let value = generator.negate_with(self);
+        if let Some(ref mut recorder) = self.recorder {
+            let kind = match node {
+                Node::Rule(rule) => rule.name.clone(),
+                node => node.kind().to_owned(),
+            };
+            recorder.defect(kind, start);
+        }
+        value
+    }
+
+    /// take_derivations returns the Derivations of the rules expanded at the
+    /// top level since recording was enabled or this was last called. It is
+    /// empty if recording isn't enabled
+    pub fn take_derivations(&mut self) -> Vec<Derivation> {
+        match self.recorder {
+            Some(ref mut recorder) => recorder.take(),
+            None => vec![],
+        }
+    }
+
+    /// enter_rule marks the start of the expansion of a named rule. It must
+    /// be paired with a call to exit_rule once the rule has been generated
+    pub fn enter_rule(&mut self, name: &str, negated: bool) {
+        self.depth += 1;
+        let consumed = self.consumed();
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.enter(name, negated, consumed);
+        }
+    }
+
+    /// exit_rule marks the end of the expansion of the innermost rule which
+    /// produced len bytes
+    pub fn exit_rule(&mut self, len: usize) {
+        self.depth -= 1;
+        let consumed = self.consumed();
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.exit(len, consumed);
+        }
+    }
+
+    /// produced must be called by Generators that emit bytes of their own,
+    /// rather than combining the values of other Generators, so that the
+    /// output spans of a Derivation are accurate
+    pub fn produced(&mut self, len: usize) {
+        self.produced += len;
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.produced(len);
+        }
+    }
+
+    /// produced_len returns the number of bytes reported with produced so far
+    pub(crate) fn produced_len(&self) -> usize {
+        self.produced
+    }
+
+    /// enter_size starts steering the bytes produced until the matching call
+    /// to exit_size towards a size between min and max inclusive. The goal is
+    /// picked from the lower half of the range to leave room for closing the
+    /// structures that are still open once it has been reached
+    pub fn enter_size(&mut self, min: usize, max: usize) {
+        assert!(min <= max, "empty size {}..={}", min, max);
+        let goal = self.range(min, min + (max - min) / 2 + 1);
+        self.targets.push(Target {
+            start: self.produced,
+            goal,
+            share: false,
+        });
+    }
+
+    /// enter_share steers the bytes produced until the matching call to
+    /// exit_size towards a random share of at most half of the remaining
+    /// size. It is used by repetitions for each of their values
+    pub(crate) fn enter_share(&mut self) {
+        let remaining = self.remaining_size().unwrap_or(0).max(1);
+        let goal = self.range(1, remaining.div_ceil(2) + 1);
+        self.targets.push(Target {
+            start: self.produced,
+            goal,
+            share: true,
+        });
+    }
+
+    /// exit_size stops steering towards the size of the innermost enter_size
+    pub fn exit_size(&mut self) {
+        self.targets
+            .pop()
+            .expect("exit_size without matching enter_size");
+    }
+
+    /// remaining_size returns the number of bytes left before the innermost
+    /// size, or any size enclosing it, is reached. It is None when the size
+    /// isn't being steered
+    pub fn remaining_size(&self) -> Option<usize> {
+        self.targets
+            .iter()
+            .map(|t| t.goal.saturating_sub(self.produced - t.start))
+            .min()
+    }
+
+    /// is_exhausted returns true once a byte backed Context has consumed all
+    /// of its input. A random number generator is never exhausted
+    pub fn is_exhausted(&self) -> bool {
+        match self.entropy {
+            Entropy::Rng(_) => false,
+            Entropy::Bytes { data, position } => position >= data.len(),
+        }
+    }
+
+    /// consumed returns the number of bytes of input read so far. It is
+    /// always 0 for a Context backed by a random number generator
+    pub fn consumed(&self) -> usize {
+        match self.entropy {
+            Entropy::Rng(_) => 0,
+            Entropy::Bytes { position, .. } => position,
+        }
+    }
+
+    /// range returns a value between low inclusive and high exclusive. When
+    /// reading from bytes only as many bytes as are needed to represent the
+    /// size of the range are consumed
+    pub fn range(&mut self, low: usize, high: usize) -> usize {
+        assert!(low < high, "empty range {}..{}", low, high);
+        match self.entropy {
+            Entropy::Rng(ref mut rng) => rng.gen_range(low, high),
+            Entropy::Bytes {
+                data,
+                ref mut position,
+            } => {
+                let size = (high - low) as u64;
+                let mut value = 0u64;
+                let mut span = 0u64;
+                while span < size - 1 && *position < data.len() {
+                    value = (value << 8) | u64::from(data[*position]);
+                    span = (span << 8) | 0xff;
+                    *position += 1;
+                }
+                low + (value % size) as usize
+            }
+        }
+    }
+
+    /// below returns a value between 0 inclusive and n exclusive
+    pub fn below(&mut self, n: usize) -> usize {
+        self.range(0, n)
+    }
+
+    /// choose returns the index of one of n alternatives of the decision
+    /// identified by key. It is the same as below unless the Context is
+    /// seeking novelty, in which case each alternative is weighted by the
+    /// inverse of the number of times it has been chosen
+    pub fn choose(&mut self, key: usize, n: usize) -> usize {
+        let index = match (self.novelty.as_ref(), &mut self.entropy) {
+            (Some(seen), &mut Entropy::Rng(ref mut rng)) => {
+                let weights = (0..n)
+                    .map(|i| 1.0 / (1 + seen.get(&(key, i)).cloned().unwrap_or(0)) as f64)
+                    .collect::<Vec<_>>();
+                let mut target = rng.gen::<f64>() * weights.iter().sum::<f64>();
+                weights
+                    .iter()
+                    .position(|w| {
+                        target -= w;
+                        target < 0.0
+                    })
+                    .unwrap_or(n - 1)
+            }
+            _ => return self.below(n),
+        };
+
+        if let Some(ref mut seen) = self.novelty {
+            *seen.entry((key, index)).or_insert(0) += 1;
+        }
+        index
+    }
+
+    /// flip returns true or false with equal probability. An exhausted
+    /// Context always returns false
+    pub fn flip(&mut self) -> bool {
+        self.range(0, 2) == 1
+    }
+
+    /// probability returns true with a probability of p. An exhausted
+    /// Context always returns false
+    pub fn probability(&mut self, p: f64) -> bool {
+        match self.entropy {
+            Entropy::Rng(ref mut rng) => rng.gen_bool(p),
+            Entropy::Bytes { .. } => {
+                if self.is_exhausted() {
+                    return false;
+                }
+                (self.range(0, 256) as f64) < p * 256.0
+            }
+        }
+    }
+
+    /// byte returns any byte value
+    pub fn byte(&mut self) -> u8 {
+        self.range(0, 256) as u8
+    }
+
+    /// char returns any valid unicode scalar value
+    pub fn char(&mut self) -> char {
+        // skip over the surrogate range which is not valid in a char
+        let mut value = self.range(0, 0x10_f800) as u32;
+        if value >= 0xd800 {
+            value += 0x800;
+        }
+        ::std::char::from_u32(value).unwrap()
+    }
+
+    /// alphanumeric returns one of A-Z, a-z or 0-9
+    pub fn alphanumeric(&mut self) -> char {
+        ALPHANUMERIC[self.below(ALPHANUMERIC.len())] as char
+    }
+
+    /// min_cost returns the number of Generators that make up the smallest
+    /// derivation of generator, or usize::MAX if it never terminates. The
+    /// costs of named rules are computed once per Context
+    pub fn min_cost(&mut self, generator: &dyn Generator) -> usize {
+        self.costs.cost(generator)
+    }
+
+    /// choose_growing returns the index of one of the Generators in choices
+    /// that doesn't have the smallest derivation, so that recursion continues
+    /// until the size is reached. It returns None when the size isn't being
+    /// steered, when it has been reached, when every Generator has a
+    /// derivation of the same size, within a share of a repetition, where the
+    /// repetition grows the value instead and choices are left alone, or when
+    /// rules are nested too deeply
+    pub fn choose_growing(&mut self, choices: &[Box<dyn Generator>]) -> Option<usize> {
+        if self.remaining_size()? == 0
+            || self.targets.last()?.share
+            || self.depth >= GROWTH_DEPTH_MAX
+        {
+            return None;
+        }
+
+        let costs = choices
+            .iter()
+            .map(|c| self.min_cost(&**c))
+            .collect::<Vec<_>>();
+        let minimal = *costs.iter().min()?;
+        let growing = (0..choices.len())
+            .filter(|i| costs[*i] > minimal)
+            .collect::<Vec<_>>();
+        if growing.is_empty() {
+            return None;
+        }
+        Some(growing[self.below(growing.len())])
+    }
+
+    /// choose_minimal returns the index of the Generator in choices with the
+    /// smallest derivation
+    pub fn choose_minimal(&mut self, choices: &[Box<dyn Generator>]) -> usize {
+        (0..choices.len())
+            .min_by_key(|i| self.min_cost(&*choices[*i]))
+            .expect("no choices specified")
+    }
+}
+
+/// Costs memoizes the cost of the smallest derivation of each rule of a set
+/// of Rules
+#[derive(Default)]
+struct Costs {
+    rules: HashMap<usize, HashMap<String, usize>>,
+}
+
+impl Costs {
+    fn cost(&mut self, generator: &dyn Generator) -> usize {
+        let tables = &mut self.rules;
+        node_cost(generator, &mut |rule: &str, rules: &Arc<RwLock<Rules>>| {
+            tables
+                .entry(rule_set_key(rules))
+                .or_insert_with(|| rule_costs(&rules.read().unwrap()))
+                .get(rule)
+                .cloned()
+                .unwrap_or(usize::MAX)
+        })
+    }
+}
+
+fn rule_set_key(rules: &Arc<RwLock<Rules>>) -> usize {
+    &**rules as *const RwLock<Rules> as usize
+}
+
+/// compute the cost of each rule by iterating until a fixed point is reached.
+/// Rules that reference each other start out as never terminating and are
+/// lowered as terminating derivations are discovered
+fn rule_costs(rules: &Rules) -> HashMap<String, usize> {
+    let mut table: HashMap<String, usize> = rules
+        .keys()
+        .map(|name| (name.clone(), usize::MAX))
+        .collect();
+
+    loop {
+        let mut changed = false;
+        for (name, generator) in rules.iter() {
+            let cost = node_cost(&**generator, &mut |rule: &str, _: &Arc<RwLock<Rules>>| {
+                table.get(rule).cloned().unwrap_or(usize::MAX)
+            });
+            if cost < table[name] {
+                table.insert(name.clone(), cost);
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return table;
+        }
+    }
+}
+
+fn node_cost(
+    generator: &dyn Generator,
+    rule_cost: &mut dyn FnMut(&str, &Arc<RwLock<Rules>>) -> usize,
+) -> usize {
+    let cost = match generator.node() {
+        Node::CharLiteral(_)
+        | Node::StringLiteral(_)
+        | Node::ByteLiteral(_)
+        | Node::CharRange(_)
+        | Node::Any(_)
+        | Node::Dictionary(_)
+        | Node::Many(_)
+        | Node::Optional(_)
+        | Node::SepBy(_)
+        | Node::Other => 0,
+        Node::Mix(g) => node_cost(&*g.generator, rule_cost),
+        Node::Choice(g) => g
+            .choices
+            .iter()
+            .map(|c| node_cost(&**c, rule_cost))
+            .min()
+            .unwrap_or(usize::MAX),
+        Node::WeightedChoice(g) => g
+            .choices
+            .iter()
+            .map(|c| node_cost(&**c, rule_cost))
+            .min()
+            .unwrap_or(usize::MAX),
+        Node::Many1(g) => node_cost(&*g.generator, rule_cost),
+        Node::SepBy1(g) => node_cost(&*g.generator, rule_cost),
+        Node::Not(g) => node_cost(&*g.generator, rule_cost),
+        Node::TargetSize(g) => node_cost(&*g.generator, rule_cost),
+        Node::Rule(g) => return rule_cost(&g.name, &g.rules),
+        Node::Sequence(g) => g.generators.iter().fold(0usize, |sum, c| {
+            sum.saturating_add(node_cost(&**c, rule_cost))
+        }),
+        Node::JoinWith(g) => {
+            let delimiters = g.generators.len().saturating_sub(1);
+            let delimiter = node_cost(&*g.delimiter, rule_cost).saturating_mul(delimiters);
+            g.generators.iter().fold(delimiter, |sum, c| {
+                sum.saturating_add(node_cost(&**c, rule_cost))
+            })
+        }
+        Node::RepeatN(g) => {
+            if g.n == 0 {
+                0
+            } else {
+                node_cost(&*g.generator, rule_cost).saturating_mul(g.n)
+            }
+        }
+        Node::Range(g) => {
+            if g.n == 0 {
+                0
+            } else {
+                node_cost(&*g.generator, rule_cost).saturating_mul(g.n)
+            }
+        }
+    };
+
+    cost.saturating_add(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn range_from_bytes() {
+        let mut ctx = Context::from_bytes(&[3, 0x02, 0x01]);
+        assert_eq!(ctx.range(0, 2), 1);
+        assert_eq!(ctx.consumed(), 1);
+        assert_eq!(ctx.range(0, 300), 0x0201 - 300);
+        assert_eq!(ctx.consumed(), 3);
+        assert!(ctx.is_exhausted());
+        assert_eq!(ctx.range(5, 10), 5);
+        assert!(!ctx.flip());
+        assert!(!ctx.probability(1.0));
+    }
+
+    #[test]
+    fn range_of_one_consumes_nothing() {
+        let mut ctx = Context::from_bytes(&[0xff]);
+        assert_eq!(ctx.range(4, 5), 4);
+        assert_eq!(ctx.consumed(), 0);
+    }
+
+    #[test]
+    fn novelty_prefers_unseen_alternatives() {
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1)).seeking_novelty();
+        let mut counts = [0; 3];
+        for _ in 0..300 {
+            counts[ctx.choose(0, 3)] += 1;
+        }
+        assert!(counts.iter().all(|c| *c >= 90), "{:?}", counts);
+        assert_eq!(Context::from_bytes(&[2]).seeking_novelty().choose(0, 3), 2);
+    }
+
+    #[test]
+    fn char_skips_surrogates() {
+        let mut ctx = Context::from_bytes(&[0x00, 0xd8, 0x00]);
+        assert_eq!(ctx.char(), '\u{e000}');
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::ops;
+
+use super::Node;
+
+/// Defects configures the injection of defects while generating a valid
+/// value: each Generator a combinator generates is negated instead with a
+/// probability that can be set for each rule, for each kind of Generator
+/// and for everything else. Small probabilities produce values that are
+/// mostly valid with a defect here and there, which reach much deeper into
+/// a parser than values that are invalid from the start. Defects are enabled
+/// with Context::defects and are recorded in the Derivation of the rule
+/// they were injected in
+///
+/// ```
+/// # use synfuzz::Defects;
+/// let defects = Defects::new(0.001)
+///     .rule("identifier", 0.05)
+///     .kind("StringLiteral", 0.02);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Defects {
+    probability: f64,
+    rules: HashMap<String, f64>,
+    kinds: HashMap<String, f64>,
+}
+
+/// Defect is a defect that was injected into a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Defect {
+    /// The name of the rule or the kind of the Generator that was negated
+    pub kind: String,
+    /// The span of the negated value in the generated value
+    pub output: ops::Range<usize>,
+}
+
+impl Defects {
+    /// new creates Defects that negate any Generator with probability
+    pub fn new(probability: f64) -> Defects {
+        Defects {
+            probability: checked(probability),
+            ..Defects::default()
+        }
+    }
+
+    /// rule sets the probability of negating the rule named name
+    pub fn rule<S>(mut self, name: S, probability: f64) -> Defects
+    where
+        S: Into<String>,
+    {
+        self.rules.insert(name.into(), checked(probability));
+        self
+    }
+
+    /// kind sets the probability of negating the Generators of a kind, which
+    /// is the name of their type such as StringLiteral or Many. See
+    /// Node::kind
+    pub fn kind<S>(mut self, kind: S, probability: f64) -> Defects
+    where
+        S: Into<String>,
+    {
+        self.kinds.insert(kind.into(), checked(probability));
+        self
+    }
+
+    /// probability returns the probability of negating the Generator viewed
+    /// by node
+    pub(crate) fn probability(&self, node: Node) -> f64 {
+        if let Node::Rule(rule) = node {
+            if let Some(p) = self.rules.get(&rule.name) {
+                return *p;
+            }
+        }
+
+        self.kinds
+            .get(node.kind())
+            .cloned()
+            .unwrap_or(self.probability)
+    }
+}
+
+fn checked(probability: f64) -> f64 {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "probability must be between 0 and 1"
+    );
+    probability
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::sync::{Arc, RwLock};
+    use {byte, register_rule, repeat_n, rule, Context, Generator};
+
+    fn digits() -> Arc<RwLock<::Rules>> {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(&rules, "digits", repeat_n(rule("digit", rules.clone()), 3));
+        register_rule(&rules, "digit", byte(0x31));
+        rules
+    }
+
+    #[test]
+    fn injects_defects_into_rules() {
+        let defects = Defects::new(0.0).rule("digit", 0.5);
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1))
+            .recording()
+            .defects(defects);
+        let digits = rule("digits", digits());
+
+        let mut injected = 0;
+        for _ in 0..20 {
+            let value = digits.generate_with(&mut ctx);
+            let derivation = ctx.take_derivations().pop().unwrap();
+            for defect in &derivation.defects {
+                assert_eq!(defect.kind, "digit");
+                assert_ne!(value[defect.output.clone()].to_vec(), b"1".to_vec());
+                injected += 1;
+            }
+            let negated = derivation.children.iter().filter(|c| c.negated).count();
+            assert_eq!(negated, derivation.defects.len());
+        }
+        assert!(injected > 0 && injected < 60);
+    }
+
+    #[test]
+    fn kinds_and_rules_override_the_default() {
+        let defects = Defects::new(1.0)
+            .kind("Rule", 0.0)
+            .kind("RepeatN", 0.0)
+            .kind("ByteLiteral", 0.0);
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1)).defects(defects);
+        assert_eq!(rule("digits", digits()).generate_with(&mut ctx), b"111");
+
+        let defects = Defects::new(0.0).kind("Rule", 1.0).rule("digit", 0.0);
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1)).defects(defects);
+        assert_eq!(rule("digits", digits()).generate_with(&mut ctx), b"111");
+    }
+
+    #[test]
+    fn injects_defects_into_rule_bodies() {
+        let defects = Defects::new(0.0).kind("ByteLiteral", 1.0);
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1)).defects(defects);
+        let digit = rule("digit", digits());
+        for _ in 0..20 {
+            assert_ne!(digit.generate_with(&mut ctx), b"1");
+        }
+    }
+}
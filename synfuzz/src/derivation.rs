@@ -0,0 +1,178 @@
+use std::fmt;
+use std::ops;
+
+use super::Defect;
+
+/// Derivation is a node in the tree of named rules that were expanded to
+/// produce a value. Each node records the span of the generated value the
+/// rule produced and, when generating from a byte slice, the span of the
+/// input that was consumed to make its decisions. Replacing the input span
+/// of a node and generating again regenerates only that part of the value,
+/// which is what the grammar aware mutations of the Mutator are built on.
+/// The defects injected directly within the rule are recorded along with
+/// the spans of their values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Derivation {
+    pub rule: String,
+    pub output: ops::Range<usize>,
+    pub input: ops::Range<usize>,
+    pub negated: bool,
+    pub defects: Vec<Defect>,
+    pub children: Vec<Derivation>,
+}
+
+impl Derivation {
+    /// iter walks the Derivation and all of its descendants in pre-order
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { stack: vec![self] }
+    }
+
+    /// depth returns the number of nested rule expansions, counting this one
+    pub fn depth(&self) -> usize {
+        1 + self.children.iter().map(|c| c.depth()).max().unwrap_or(0)
+    }
+}
+
+/// Display writes the Derivation as an indented tree with one rule per line
+/// followed by its output and input spans. Negated rules are marked with a !
+/// and defects are written below their rule as defect followed by the rule
+/// or kind that was negated and its output span
+impl fmt::Display for Derivation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl Derivation {
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        writeln!(
+            f,
+            "{:indent$}{}{} {:?} {:?}",
+            "",
+            if self.negated { "!" } else { "" },
+            self.rule,
+            self.output,
+            self.input,
+            indent = indent
+        )?;
+        for defect in &self.defects {
+            writeln!(
+                f,
+                "{:indent$}defect {} {:?}",
+                "",
+                defect.kind,
+                defect.output,
+                indent = indent + 2
+            )?;
+        }
+        for child in &self.children {
+            child.write(f, indent + 2)?;
+        }
+        Ok(())
+    }
+}
+
+/// Iter is a pre-order iterator over a Derivation
+pub struct Iter<'a> {
+    stack: Vec<&'a Derivation>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Derivation;
+
+    fn next(&mut self) -> Option<&'a Derivation> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
+}
+
+/// Recorder builds Derivations as rules are entered and exited during
+/// generation
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    stack: Vec<Derivation>,
+    roots: Vec<Derivation>,
+    position: usize,
+}
+
+impl Recorder {
+    pub(crate) fn enter(&mut self, rule: &str, negated: bool, consumed: usize) {
+        self.stack.push(Derivation {
+            rule: rule.to_owned(),
+            output: self.position..self.position,
+            input: consumed..consumed,
+            negated,
+            defects: vec![],
+            children: vec![],
+        });
+    }
+
+    pub(crate) fn exit(&mut self, len: usize, consumed: usize) {
+        let mut node = self.stack.pop().expect("exit without matching enter");
+        // Generators that don't report their output are accounted for here
+        self.position = node.output.start + len;
+        node.output.end = self.position;
+        node.input.end = consumed;
+
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.roots.push(node),
+        }
+    }
+
+    pub(crate) fn produced(&mut self, len: usize) {
+        self.position += len;
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// defect records a defect that produced the output since start in the
+    /// innermost rule. Defects outside of any rule are not recorded
+    pub(crate) fn defect(&mut self, kind: String, start: usize) {
+        let output = start..self.position;
+        if let Some(node) = self.stack.last_mut() {
+            node.defects.push(Defect { kind, output });
+        }
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<Derivation> {
+        self.position = 0;
+        self.stack.clear();
+        ::std::mem::take(&mut self.roots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_nested_rules() {
+        let mut recorder = Recorder::default();
+        recorder.enter("expr", false, 0);
+        recorder.produced(1);
+        recorder.enter("number", false, 1);
+        recorder.produced(2);
+        recorder.exit(2, 3);
+        recorder.produced(1);
+        recorder.defect(String::from("ByteLiteral"), 3);
+        recorder.exit(4, 3);
+
+        let roots = recorder.take();
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0].output, 0..4);
+        assert_eq!(roots[0].children[0].output, 1..3);
+        assert_eq!(roots[0].children[0].input, 1..3);
+        assert_eq!(roots[0].depth(), 2);
+        let names = roots[0].iter().map(|d| d.rule.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["expr", "number"]);
+        assert_eq!(roots[0].defects[0].output, 3..4);
+        assert_eq!(
+            roots[0].to_string(),
+            "expr 0..4 0..3\n  defect ByteLiteral 3..4\n  number 1..3 1..3\n"
+        );
+    }
+}
@@ -0,0 +1,441 @@
+use super::{Context, Generator, Node};
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// The maximum length of a value produced by the negation of a Dictionary
+const DICTIONARY_NEGATE_MAX: usize = 32;
+
+/// Dictionary is a Generator that will return one of its entries, picked at
+/// random, for each call of the generate method. It is the building block
+/// for the curated "interesting values" sets as well as for user supplied
+/// dictionary files
+#[derive(Debug)]
+pub struct Dictionary {
+    pub entries: Vec<Vec<u8>>,
+}
+
+impl Generator for Dictionary {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("generate Dictionary");
+        // an empty dictionary, such as one loaded from a file of comments,
+        // has nothing to pick from
+        if self.entries.is_empty() {
+            return vec![];
+        }
+
+        let entry = &self.entries[ctx.below(self.entries.len())];
+        ctx.produced(entry.len());
+        entry.clone()
+    }
+
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("negate Dictionary");
+        // random bytes that are not an entry of the dictionary
+        let random = |ctx: &mut Context| {
+            let len = ctx.range(0, DICTIONARY_NEGATE_MAX);
+            (0..len).map(|_| ctx.byte()).collect::<Vec<u8>>()
+        };
+
+        let mut generated = random(ctx);
+        while self.entries.contains(&generated) {
+            if ctx.is_exhausted() {
+                generated.push(0);
+            } else {
+                generated = random(ctx);
+            }
+        }
+
+        ctx.produced(generated.len());
+        generated
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::Dictionary(self)
+    }
+}
+
+/// dictionary is a helper to create a Dictionary Generator from anything
+/// that can be turned into a list of byte strings
+pub fn dictionary<I, T>(entries: I) -> Dictionary
+where
+    I: IntoIterator<Item = T>,
+    T: Into<Vec<u8>>,
+{
+    Dictionary {
+        entries: entries.into_iter().map(Into::into).collect(),
+    }
+}
+
+/// load_dictionary reads a dictionary file from disk. See parse_dictionary
+/// for the accepted format
+pub fn load_dictionary<P: AsRef<Path>>(path: P) -> io::Result<Dictionary> {
+    let mut f = File::open(path)?;
+    let mut buf = String::new();
+    f.read_to_string(&mut buf)?;
+    parse_dictionary(&buf)
+}
+
+/// parse_dictionary parses the contents of a dictionary file. Both the AFL
+/// dictionary format (`name="value"` or `"value"` with `\\`, `\"` and `\xNN`
+/// escapes) and plain files with one raw token per line are accepted. Blank
+/// lines and lines starting with `#` are ignored
+pub fn parse_dictionary(source: &str) -> io::Result<Dictionary> {
+    let mut entries = vec![];
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let quoted = match line.find('"') {
+            Some(start) if line.ends_with('"') && line.len() > start + 1 => {
+                &line[start + 1..line.len() - 1]
+            }
+            _ => {
+                entries.push(line.as_bytes().to_owned());
+                continue;
+            }
+        };
+
+        match unescape(quoted) {
+            Some(entry) => entries.push(entry),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid escape sequence on line {}", number + 1),
+                ))
+            }
+        }
+    }
+
+    Ok(Dictionary { entries })
+}
+
+fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+
+        match bytes.next()? {
+            b'\\' => out.push(b'\\'),
+            b'"' => out.push(b'"'),
+            b'x' => {
+                let hi = (bytes.next()? as char).to_digit(16)?;
+                let lo = (bytes.next()? as char).to_digit(16)?;
+                out.push((hi * 16 + lo) as u8);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(out)
+}
+
+/// write_dictionary writes the entries of a Dictionary in the AFL dictionary
+/// format, which is also understood by libFuzzer's `-dict` option. Entries
+/// made up only of alphanumeric characters and underscores are named after
+/// their value, all others are numbered
+pub fn write_dictionary<W: Write>(dictionary: &Dictionary, mut out: W) -> io::Result<()> {
+    for (i, entry) in dictionary.entries.iter().enumerate() {
+        if !entry.is_empty()
+            && entry
+                .iter()
+                .all(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        {
+            write!(out, "kw_{}=\"", String::from_utf8_lossy(entry))?;
+        } else {
+            write!(out, "token_{}=\"", i)?;
+        }
+
+        for b in entry {
+            match *b {
+                b'"' => write!(out, "\\\"")?,
+                b'\\' => write!(out, "\\\\")?,
+                0x20..=0x7e => out.write_all(&[*b])?,
+                _ => write!(out, "\\x{:02x}", b)?,
+            }
+        }
+
+        writeln!(out, "\"")?;
+    }
+
+    Ok(())
+}
+
+/// interesting_integers is a Dictionary of decimal integers that commonly
+/// trigger boundary conditions: 0, ±1, powers of two and the minimum and
+/// maximum of each fixed width integer type along with their neighbors
+pub fn interesting_integers() -> Dictionary {
+    let mut values: Vec<i128> = vec![0, 1, -1];
+
+    for shift in 1..=64 {
+        let power = 1i128 << shift;
+        values.push(power);
+        values.push(-power);
+    }
+
+    for bits in &[8u32, 16, 32, 64] {
+        let max = (1i128 << (bits - 1)) - 1;
+        let min = -(1i128 << (bits - 1));
+        let umax = (1i128 << bits) - 1;
+        for value in &[max, min, umax] {
+            values.push(value - 1);
+            values.push(*value);
+            values.push(value + 1);
+        }
+    }
+
+    values.sort();
+    values.dedup();
+    dictionary(values.iter().map(|v| v.to_string()))
+}
+
+/// long_strings is a Dictionary of runs of a single character at sizes that
+/// are likely to overflow fixed size buffers
+pub fn long_strings() -> Dictionary {
+    dictionary(
+        [255usize, 256, 257, 1024, 4096, 65535, 65536, 65537]
+            .iter()
+            .map(|len| vec![b'A'; *len]),
+    )
+}
+
+/// format_strings is a Dictionary of printf style format specifiers
+pub fn format_strings() -> Dictionary {
+    dictionary(vec![
+        "%s",
+        "%n",
+        "%x",
+        "%d",
+        "%p",
+        "%s%s%s%s%s%s%s%s",
+        "%n%n%n%n%n%n%n%n",
+        "%x%x%x%x%x%x%x%x",
+        "%99999999s",
+        "%.1024d",
+        "%*s",
+        "{}",
+        "{0}",
+        "${}",
+    ])
+}
+
+/// path_traversals is a Dictionary of relative and encoded path traversal
+/// sequences
+pub fn path_traversals() -> Dictionary {
+    dictionary(vec![
+        "../",
+        "..\\",
+        "../../../../../../../../etc/passwd",
+        "..\\..\\..\\..\\..\\..\\windows\\win.ini",
+        "..%2f",
+        "..%252f",
+        "%2e%2e%2f",
+        "..%c0%af",
+        "/dev/null",
+        "C:\\",
+        "\\\\?\\C:\\",
+        "file:///etc/passwd",
+    ])
+}
+
+/// unicode_specials is a Dictionary of byte sequences that tend to confuse
+/// text handling: NULs, byte order marks, confusable and directional
+/// characters, and malformed UTF-8
+pub fn unicode_specials() -> Dictionary {
+    dictionary(vec![
+        vec![0x00],
+        b"\xef\xbb\xbf".to_vec(),
+        b"\xfe\xff".to_vec(),
+        b"\xff\xfe".to_vec(),
+        // RIGHT-TO-LEFT OVERRIDE
+        "\u{202e}".into(),
+        // ZERO WIDTH SPACE and ZERO WIDTH JOINER
+        "\u{200b}".into(),
+        "\u{200d}".into(),
+        // confusables for 'a', 'o' and '/'
+        "\u{0430}".into(),
+        "\u{03bf}".into(),
+        "\u{2215}".into(),
+        // combining characters and characters outside the BMP
+        "e\u{0301}".into(),
+        "\u{1f4a9}".into(),
+        "\u{fffd}".into(),
+        // overlong encoding of '/', lone continuation and truncated sequence
+        b"\xc0\xaf".to_vec(),
+        b"\x80".to_vec(),
+        b"\xe2\x82".to_vec(),
+        // UTF-16 surrogate encoded as UTF-8
+        b"\xed\xa0\x80".to_vec(),
+    ])
+}
+
+/// interesting is a Dictionary of all of the curated interesting values
+pub fn interesting() -> Dictionary {
+    let entries = vec![
+        interesting_integers(),
+        long_strings(),
+        format_strings(),
+        path_traversals(),
+        unicode_specials(),
+    ]
+    .into_iter()
+    .flat_map(|d| d.entries)
+    .collect();
+
+    Dictionary { entries }
+}
+
+/// Mix is a Generator that will, with the specified probability, replace the
+/// value of its generator with a token from its dictionary. Wrapping the
+/// token level rules of an otherwise valid grammar with Mix produces output
+/// that is mostly grammar valid with interesting values mixed in
+#[derive(Debug)]
+pub struct Mix {
+    pub generator: Box<dyn Generator>,
+    pub dictionary: Box<dyn Generator>,
+    pub probability: f64,
+}
+
+impl Generator for Mix {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("generate Mix");
+        if ctx.probability(self.probability) {
+            self.dictionary.generate_with(ctx)
+        } else {
+            ctx.generate(&*self.generator)
+        }
+    }
+
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        trace!("negate Mix");
+        if ctx.probability(self.probability) {
+            self.dictionary.generate_with(ctx)
+        } else {
+            self.generator.negate_with(ctx)
+        }
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::Mix(self)
+    }
+}
+
+/// mix is a helper to create a Mix Generator. The probability must be
+/// between 0.0 and 1.0
+pub fn mix(
+    generator: impl Generator + 'static,
+    dictionary: impl Generator + 'static,
+    probability: f64,
+) -> impl Generator {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "probability must be between 0.0 and 1.0"
+    );
+    Mix {
+        generator: Box::new(generator),
+        dictionary: Box::new(dictionary),
+        probability,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use value::string;
+
+    #[test]
+    fn generate_dictionary() {
+        let generator = dictionary(vec!["foo", "bar"]);
+        let generated = generator.generate();
+        assert!(generated == b"foo" || generated == b"bar");
+    }
+
+    #[test]
+    fn negate_dictionary() {
+        let generator = dictionary(vec!["foo", "bar"]);
+        let generated = generator.negate();
+        assert!(generated != b"foo" && generated != b"bar");
+        assert!(generated.len() < DICTIONARY_NEGATE_MAX);
+    }
+
+    #[test]
+    fn generate_empty_dictionary() {
+        let generator = parse_dictionary("# only a comment\n").unwrap();
+        assert!(generator.generate().is_empty());
+    }
+
+    #[test]
+    fn parse_afl_dictionary() {
+        let source = r#"
+# a comment
+kw_if="if"
+"\x00\xff"
+quote="\"\\"
+plain token
+"#;
+        let generator = parse_dictionary(source).unwrap();
+        assert_eq!(
+            generator.entries,
+            vec![
+                b"if".to_vec(),
+                vec![0x00, 0xff],
+                b"\"\\".to_vec(),
+                b"plain token".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_dictionary() {
+        assert!(parse_dictionary(r#"bad="\q""#).is_err());
+        assert!(parse_dictionary(r#"bad="\x4""#).is_err());
+    }
+
+    #[test]
+    fn write_afl_dictionary() {
+        let generator = dictionary(vec![&b"if"[..], b"\"\\", &[0x00, 0x0a]]);
+        let mut out = vec![];
+        write_dictionary(&generator, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "kw_if=\"if\"\ntoken_1=\"\\\"\\\\\"\ntoken_2=\"\\x00\\x0a\"\n"
+        );
+
+        let parsed = parse_dictionary(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(parsed.entries, generator.entries);
+    }
+
+    #[test]
+    fn interesting_integer_boundaries() {
+        let generator = interesting_integers();
+        for value in &[
+            "0",
+            "-1",
+            "2147483647",
+            "2147483648",
+            "-2147483649",
+            "255",
+            "256",
+        ] {
+            assert!(generator.entries.contains(&value.as_bytes().to_vec()));
+        }
+    }
+
+    #[test]
+    fn generate_mix() {
+        let always = mix(string("a"), dictionary(vec!["b"]), 1.0);
+        assert_eq!(always.generate(), b"b");
+        let never = mix(string("a"), dictionary(vec!["b"]), 0.0);
+        assert_eq!(never.generate(), b"a");
+    }
+}
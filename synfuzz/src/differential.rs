@@ -0,0 +1,340 @@
+use rand::rngs::SmallRng;
+use rand::{FromEntropy, Rng, SeedableRng};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use super::{Derivation, Executor, Outcome, Rule, Rules};
+use runner::{generate_seeded, hash};
+
+/// ClosureFn is an in-process implementation that returns its output for an
+/// input
+pub type ClosureFn = Box<dyn FnMut(&[u8]) -> Vec<u8>>;
+
+/// Normalize is applied to outputs before they are compared
+type Normalize = Box<dyn Fn(&[u8]) -> Vec<u8>>;
+
+/// Implementation is one of the two implementations compared by a
+/// Differential test
+pub enum Implementation {
+    /// A program run by an Executor. Its output is what it writes to stdout
+    Process(Executor),
+    /// A function that returns its output for an input. It always exits
+    /// successfully
+    Closure(ClosureFn),
+}
+
+impl Implementation {
+    /// closure creates an Implementation from a function
+    pub fn closure<F>(f: F) -> Implementation
+    where
+        F: FnMut(&[u8]) -> Vec<u8> + 'static,
+    {
+        Implementation::Closure(Box::new(f))
+    }
+
+    fn observe(&mut self, input: &[u8]) -> io::Result<Observation> {
+        match *self {
+            Implementation::Process(ref executor) => {
+                let execution = executor.execute(input)?;
+                Ok(Observation {
+                    outcome: execution.outcome,
+                    output: execution.stdout,
+                })
+            }
+            Implementation::Closure(ref mut f) => Ok(Observation {
+                outcome: Outcome::Exited(0),
+                output: f(input),
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Implementation::Process(ref executor) => write!(f, "Process({:?})", executor),
+            Implementation::Closure(_) => write!(f, "Closure"),
+        }
+    }
+}
+
+/// Comparison is what must match for two implementations to agree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// How the implementations exited
+    Outcome,
+    /// The output of the implementations
+    Output,
+    /// Both how the implementations exited and their output
+    Both,
+}
+
+/// Observation is how an implementation behaved for an input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Observation {
+    pub outcome: Outcome,
+    pub output: Vec<u8>,
+}
+
+/// Disagreement is an input that two implementations disagree on
+#[derive(Debug, Clone)]
+pub struct Disagreement {
+    pub seed: u64,
+    pub negated: bool,
+    pub input: Vec<u8>,
+    pub derivation: Derivation,
+    pub left: Observation,
+    pub right: Observation,
+    pub path: Option<PathBuf>,
+}
+
+/// Differential feeds the same generated inputs to two implementations of
+/// the same thing, such as a parser and its reference implementation, and
+/// records the inputs they disagree on. Outputs are compared after being
+/// passed through the normalize function, which can be used to ignore
+/// differences that don't matter such as formatting
+pub struct Differential {
+    start: Rule,
+    left: Implementation,
+    right: Implementation,
+    comparison: Comparison,
+    normalize: Option<Normalize>,
+    output: Option<PathBuf>,
+    rng: SmallRng,
+    negate: f64,
+}
+
+impl Differential {
+    /// new creates a Differential test of left and right on values of the
+    /// rule named start that compares both outcome and output
+    pub fn new<S>(
+        rules: Arc<RwLock<Rules>>,
+        start: S,
+        left: Implementation,
+        right: Implementation,
+    ) -> Differential
+    where
+        S: Into<String>,
+    {
+        Differential {
+            start: Rule {
+                rules,
+                name: start.into(),
+            },
+            left,
+            right,
+            comparison: Comparison::Both,
+            normalize: None,
+            output: None,
+            rng: SmallRng::from_entropy(),
+            negate: 0.0,
+        }
+    }
+
+    /// comparison sets what must match for the implementations to agree
+    pub fn comparison(mut self, comparison: Comparison) -> Differential {
+        self.comparison = comparison;
+        self
+    }
+
+    /// normalize sets the function outputs are passed through before they
+    /// are compared
+    pub fn normalize<F>(mut self, normalize: F) -> Differential
+    where
+        F: Fn(&[u8]) -> Vec<u8> + 'static,
+    {
+        self.normalize = Some(Box::new(normalize));
+        self
+    }
+
+    /// output saves the inputs the implementations disagree on, along with
+    /// a .txt file describing the disagreement, to the directory
+    pub fn output<P>(mut self, output: P) -> Differential
+    where
+        P: Into<PathBuf>,
+    {
+        self.output = Some(output.into());
+        self
+    }
+
+    /// seed makes the sequence of generated inputs reproducible
+    pub fn seed(mut self, seed: u64) -> Differential {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// negate sets the probability that an input is generated from the
+    /// negation of the start rule
+    pub fn negate(mut self, probability: f64) -> Differential {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "probability must be between 0 and 1"
+        );
+        self.negate = probability;
+        self
+    }
+
+    /// run_once generates a single input and runs both implementations on
+    /// it, returning the Disagreement if they don't agree
+    pub fn run_once(&mut self) -> io::Result<Option<Disagreement>> {
+        let seed = self.rng.gen();
+        let negated = self.rng.gen_bool(self.negate);
+        let (input, derivation) = generate_seeded(&self.start, seed, negated);
+
+        let mut left = self.left.observe(&input)?;
+        let mut right = self.right.observe(&input)?;
+        if let Some(ref normalize) = self.normalize {
+            left.output = normalize(&left.output);
+            right.output = normalize(&right.output);
+        }
+
+        let agree = match self.comparison {
+            Comparison::Outcome => left.outcome == right.outcome,
+            Comparison::Output => left.output == right.output,
+            Comparison::Both => left == right,
+        };
+        if agree {
+            return Ok(None);
+        }
+
+        let disagreement = Disagreement {
+            path: self
+                .output
+                .as_ref()
+                .map(|output| output.join(format!("diff-{:016x}", hash(&input)))),
+            seed,
+            negated,
+            input,
+            derivation,
+            left,
+            right,
+        };
+        if let Some(ref path) = disagreement.path {
+            if !path.exists() {
+                self.save(path, &disagreement)?;
+            }
+        }
+
+        Ok(Some(disagreement))
+    }
+
+    /// run generates iterations inputs and returns the Disagreements
+    pub fn run(&mut self, iterations: usize) -> io::Result<Vec<Disagreement>> {
+        let mut disagreements = vec![];
+        for _ in 0..iterations {
+            if let Some(disagreement) = self.run_once()? {
+                disagreements.push(disagreement);
+            }
+        }
+        Ok(disagreements)
+    }
+
+    fn save(&self, path: &Path, disagreement: &Disagreement) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, &disagreement.input)?;
+        let report = format!(
+            "rule: {}\nseed: {}\nsource: {}\n\nleft: {:?}\n{}\n\nright: {:?}\n{}\n\nderivation:\n{}",
+            self.start.name,
+            disagreement.seed,
+            if disagreement.negated {
+                "negate"
+            } else {
+                "generate"
+            },
+            disagreement.left.outcome,
+            String::from_utf8_lossy(&disagreement.left.output),
+            disagreement.right.outcome,
+            String::from_utf8_lossy(&disagreement.right.output),
+            disagreement.derivation
+        );
+        fs::write(path.with_extension("txt"), report)
+    }
+}
+
+impl fmt::Debug for Differential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Differential")
+            .field("start", &self.start.name)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .field("comparison", &self.comparison)
+            .field("output", &self.output)
+            .field("negate", &self.negate)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use {ch, choice, register_rule};
+
+    fn letters() -> Arc<RwLock<Rules>> {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(&rules, "letter", choice!(ch('a'), ch('B')));
+        rules
+    }
+
+    #[test]
+    fn closures_disagree() {
+        let mut differential = Differential::new(
+            letters(),
+            "letter",
+            Implementation::closure(|input| input.to_vec()),
+            Implementation::closure(|input| input.to_ascii_uppercase()),
+        )
+        .seed(1);
+
+        let disagreements = differential.run(20).unwrap();
+        assert!(!disagreements.is_empty());
+        for disagreement in disagreements {
+            assert_eq!(disagreement.input, b"a".to_vec());
+            assert_eq!(disagreement.right.output, b"A".to_vec());
+            assert!(!disagreement.negated);
+            assert_eq!(disagreement.derivation.rule, "letter");
+        }
+    }
+
+    #[test]
+    fn normalized_outputs_agree() {
+        let mut differential = Differential::new(
+            letters(),
+            "letter",
+            Implementation::closure(|input| input.to_vec()),
+            Implementation::closure(|input| input.to_ascii_uppercase()),
+        )
+        .normalize(|output| output.to_ascii_lowercase())
+        .seed(1);
+
+        assert!(differential.run(20).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn processes_disagree_on_outcome() {
+        let mut differential = Differential::new(
+            letters(),
+            "letter",
+            Implementation::Process(Executor::new("cat")),
+            Implementation::Process(
+                Executor::new("/bin/sh").args(vec!["-c", "if grep -q a; then exit 1; fi"]),
+            ),
+        )
+        .comparison(Comparison::Outcome)
+        .seed(1);
+
+        let disagreements = differential.run(10).unwrap();
+        assert!(!disagreements.is_empty());
+        for disagreement in disagreements {
+            assert_eq!(disagreement.input, b"a".to_vec());
+            assert_eq!(disagreement.right.outcome, Outcome::Exited(1));
+        }
+    }
+}
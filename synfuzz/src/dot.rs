@@ -0,0 +1,206 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::sync::{Arc, RwLock};
+
+use super::{Derivation, Generator, Node, Rules};
+
+/// The number of bytes of a value written in the label of a Derivation node
+const EXCERPT_MAX: usize = 24;
+
+/// write_rules_dot writes the graph of which rules reference which in the
+/// Graphviz DOT format. Rules that are referenced but don't exist are drawn
+/// dashed
+pub fn write_rules_dot<W: Write>(rules: &Arc<RwLock<Rules>>, mut out: W) -> io::Result<()> {
+    let rules = rules.read().unwrap();
+    let mut names = rules.keys().collect::<Vec<_>>();
+    names.sort();
+
+    writeln!(out, "digraph rules {{")?;
+    writeln!(out, "  node [shape=box];")?;
+    let mut missing = BTreeSet::new();
+    for name in &names {
+        writeln!(out, "  {};", quote(name))?;
+        let mut referenced = BTreeSet::new();
+        references(&*rules[*name], &mut referenced);
+        for reference in referenced {
+            if !rules.contains_key(reference) {
+                missing.insert(reference);
+            }
+            writeln!(out, "  {} -> {};", quote(name), quote(reference))?;
+        }
+    }
+    for name in missing {
+        writeln!(out, "  {} [style=dashed];", quote(name))?;
+    }
+    writeln!(out, "}}")
+}
+
+/// references collects the names of the rules referenced by generator
+fn references<'a>(generator: &'a dyn Generator, names: &mut BTreeSet<&'a str>) {
+    let node = generator.node();
+    if let Node::Rule(rule) = node {
+        names.insert(&rule.name);
+    }
+    for child in node.children() {
+        references(child, names);
+    }
+}
+
+/// write_derivation_dot writes the Derivations of value, as returned by
+/// Context::take_derivations, as a tree in the Graphviz DOT format. Each rule
+/// is labelled with its output and input spans and the start of the output
+/// it produced. Negated rules and injected defects are drawn in red
+pub fn write_derivation_dot<W: Write>(
+    derivations: &[Derivation],
+    value: &[u8],
+    mut out: W,
+) -> io::Result<()> {
+    writeln!(out, "digraph derivation {{")?;
+    writeln!(out, "  node [shape=box];")?;
+    let mut next = 0;
+    for derivation in derivations {
+        write_derivation(derivation, value, &mut next, &mut out)?;
+    }
+    writeln!(out, "}}")
+}
+
+/// write_derivation writes the node of derivation and its descendants,
+/// numbering them from next, and returns the id of its node
+fn write_derivation<W: Write>(
+    derivation: &Derivation,
+    value: &[u8],
+    next: &mut usize,
+    out: &mut W,
+) -> io::Result<usize> {
+    let id = *next;
+    *next += 1;
+    writeln!(
+        out,
+        "  n{} [label=\"{}{}\\n{:?} {:?}\\n{}\"{}];",
+        id,
+        if derivation.negated { "!" } else { "" },
+        escape(&derivation.rule),
+        derivation.output,
+        derivation.input,
+        excerpt(value, &derivation.output),
+        if derivation.negated {
+            ", color=red"
+        } else {
+            ""
+        }
+    )?;
+
+    for defect in &derivation.defects {
+        let defect_id = *next;
+        *next += 1;
+        writeln!(
+            out,
+            "  n{} [label=\"defect {}\\n{:?}\\n{}\", shape=note, color=red];",
+            defect_id,
+            escape(&defect.kind),
+            defect.output,
+            excerpt(value, &defect.output)
+        )?;
+        writeln!(out, "  n{} -> n{} [style=dashed];", id, defect_id)?;
+    }
+    for child in &derivation.children {
+        let child_id = write_derivation(child, value, next, out)?;
+        writeln!(out, "  n{} -> n{};", id, child_id)?;
+    }
+
+    Ok(id)
+}
+
+/// quote returns s as a DOT string
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+/// escape escapes the characters of s that are special in a DOT string
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// excerpt returns the start of the bytes of value in span as an escaped
+/// DOT string, writing bytes that aren't printable ASCII as \xNN
+fn excerpt(value: &[u8], span: &::std::ops::Range<usize>) -> String {
+    let end = span.end.min(value.len());
+    let start = span.start.min(end);
+    let bytes = &value[start..end];
+    let mut excerpt = String::new();
+    for b in bytes.iter().take(EXCERPT_MAX) {
+        match *b {
+            b'"' => excerpt.push_str("\\\""),
+            b'\\' => excerpt.push_str("\\\\"),
+            0x20..=0x7e => excerpt.push(*b as char),
+            _ => excerpt.push_str(&format!("\\\\x{:02x}", b)),
+        }
+    }
+    if bytes.len() > EXCERPT_MAX {
+        excerpt.push_str("...");
+    }
+    excerpt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+    use {byte, choice, register_rule, rule, seq, string, Context};
+
+    #[test]
+    fn writes_rule_graph() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(
+            &rules,
+            "expr",
+            choice!(
+                rule("number", rules.clone()),
+                seq!(
+                    rule("expr", rules.clone()),
+                    string("+"),
+                    rule("term", rules.clone())
+                )
+            ),
+        );
+        register_rule(&rules, "number", byte(0x31));
+
+        let mut out = vec![];
+        write_rules_dot(&rules, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph rules {\n  node [shape=box];\n  \"expr\";\n  \"expr\" -> \"expr\";\n  \
+             \"expr\" -> \"number\";\n  \"expr\" -> \"term\";\n  \"number\";\n  \
+             \"term\" [style=dashed];\n}\n"
+        );
+    }
+
+    #[test]
+    fn writes_derivation_tree() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(
+            &rules,
+            "pair",
+            seq!(
+                rule("key", rules.clone()),
+                byte(0),
+                rule("key", rules.clone())
+            ),
+        );
+        register_rule(&rules, "key", string("k\"v"));
+
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1)).recording();
+        let value = rule("pair", rules).generate_with(&mut ctx);
+        let mut out = vec![];
+        write_derivation_dot(&ctx.take_derivations(), &value, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph derivation {\n  node [shape=box];\n  \
+             n0 [label=\"pair\\n0..7 0..0\\nk\\\"v\\\\x00k\\\"v\"];\n  \
+             n1 [label=\"key\\n0..3 0..0\\nk\\\"v\"];\n  n0 -> n1;\n  \
+             n2 [label=\"key\\n4..7 0..0\\nk\\\"v\"];\n  n0 -> n2;\n}\n"
+        );
+    }
+}
@@ -0,0 +1,727 @@
+use std::char;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use super::{RuleSet, Rules, Tree};
+
+/// ParseError is returned when a grammar written in the synfuzz grammar
+/// notation is invalid. The line and column, counted in chars, start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {} column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// LoadError is returned when a grammar file can't be read or parsed
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "{}", e),
+            LoadError::Parse(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            LoadError::Io(ref e) => Some(e),
+            LoadError::Parse(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(other: io::Error) -> Self {
+        LoadError::Io(other)
+    }
+}
+
+impl From<ParseError> for LoadError {
+    fn from(other: ParseError) -> Self {
+        LoadError::Parse(other)
+    }
+}
+
+/// load_rules reads a grammar written in the synfuzz grammar notation from
+/// disk and builds its Rules. See parse_rule_set for the notation
+pub fn load_rules<P: AsRef<Path>>(path: P) -> Result<Arc<RwLock<Rules>>, LoadError> {
+    let mut f = File::open(path)?;
+    let mut buf = String::new();
+    f.read_to_string(&mut buf)?;
+    Ok(parse_rules(&buf)?)
+}
+
+/// parse_rules parses a grammar written in the synfuzz grammar notation and
+/// builds its Rules. See parse_rule_set for the notation
+pub fn parse_rules(source: &str) -> Result<Arc<RwLock<Rules>>, ParseError> {
+    Ok(parse_rule_set(source)?.to_rules())
+}
+
+/// parse_rule_set parses a grammar written in the synfuzz grammar notation,
+/// which is the notation RuleSet and Tree are displayed in. A grammar is a
+/// list of rules written as `name = expression ;` and `#` starts a comment
+/// that runs to the end of the line. Expressions are made of:
+///
+/// * `'c'` a char, `"string"` a string, `0x41` a byte, `'a'..'z'` a range
+///   of chars and `.` any char. Chars and strings take the `\n`, `\r`, `\t`,
+///   `\0`, `\\`, `\'`, `\"`, `\xNN` and `\u{NNNN}` escapes
+/// * `name` a reference to the rule name
+/// * `a b` a sequence and `()` the empty sequence
+/// * `a | b` a choice and `a @3 | b @1` a choice weighted by the numbers
+///   after the @, which default to 1 if only some alternatives have them
+/// * `a*`, `a+` and `a?` zero or more, one or more and an optional a,
+///   `a{n}` n times a and `a{n,m}` between n and m times a. `*`, `+`,
+///   `sep_by` and `sep_by1` repeat at most 4 times unless they are steered
+///   by `target_size`, which may be spelled out as `a*{..4}`
+/// * `!a` the negation of a, which is any other single char when a is one
+/// * `join_with(delimiter; a, b)`, `sep_by(a, separator)`,
+///   `sep_by1(a, separator)`, `dictionary("entry", "\xff")`,
+///   `mix(a, dictionary, probability)` and `target_size(a, min, max)`
+///
+/// Postfix operators bind tighter than !, which binds tighter than
+/// sequences, which bind tighter than choices. Parentheses group
+///
+/// ```
+/// # use synfuzz::*;
+/// let rules = parse_rules(
+///     "expression = number (0x20 operator 0x20 expression)? | '(' expression ')' ;
+///      number = '1'..'9' '0'..'9'{0,3} ;
+///      operator = '+' @4 | '-' @4 | \"**\" @1 ;",
+/// )
+/// .unwrap();
+/// let value = rule("expression", rules).generate();
+/// ```
+pub fn parse_rule_set(source: &str) -> Result<RuleSet, ParseError> {
+    let mut parser = Parser {
+        source,
+        position: 0,
+        references: vec![],
+    };
+    let mut rules = BTreeMap::new();
+
+    loop {
+        parser.skip_space();
+        if parser.peek().is_none() {
+            break;
+        }
+
+        let start = parser.position;
+        let name = match parser.identifier() {
+            Some(name) => name,
+            None => return Err(parser.error("expected a rule name")),
+        };
+        parser.expect('=')?;
+        let tree = parser.choice()?;
+        parser.expect(';')?;
+
+        if rules.insert(name.clone(), tree).is_some() {
+            return Err(parser.error_at(start, &format!("rule '{}' is defined twice", name)));
+        }
+    }
+
+    for (name, position) in &parser.references {
+        if !rules.contains_key(name) {
+            return Err(parser.error_at(*position, &format!("rule '{}' is not defined", name)));
+        }
+    }
+
+    Ok(RuleSet { rules })
+}
+
+/// Parser is a recursive descent parser of the synfuzz grammar notation
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+    /// The names of the rules referenced and where
+    references: Vec<(String, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    /// bump_if consumes the next char if it is one of chars
+    fn bump_if(&mut self, chars: &[char]) -> Option<char> {
+        match self.peek() {
+            Some(c) if chars.contains(&c) => self.bump(),
+            _ => None,
+        }
+    }
+
+    /// skip_space skips whitespace and comments
+    fn skip_space(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('#') => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// eat consumes c, after any whitespace, if it is next
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        let found = match self.peek() {
+            Some(c) => format!("'{}'", c.escape_default()),
+            None => String::from("the end of the grammar"),
+        };
+        self.error_at(self.position, &format!("{}, found {}", message, found))
+    }
+
+    /// error_at returns an error with message at the line and column of
+    /// position
+    fn error_at(&self, position: usize, message: &str) -> ParseError {
+        let before = &self.source[..position];
+        ParseError {
+            line: before.matches('\n').count() + 1,
+            column: before.chars().rev().take_while(|c| *c != '\n').count() + 1,
+            message: message.to_owned(),
+        }
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        self.skip_space();
+        let len = self
+            .rest()
+            .char_indices()
+            .take_while(|&(i, c)| c == '_' || c.is_alphabetic() || (i > 0 && c.is_alphanumeric()))
+            .map(|(_, c)| c.len_utf8())
+            .sum::<usize>();
+        if len == 0 {
+            return None;
+        }
+        let identifier = self.rest()[..len].to_owned();
+        self.position += len;
+        Some(identifier)
+    }
+
+    fn integer(&mut self) -> Result<usize, ParseError> {
+        self.skip_space();
+        let len = self.rest().chars().take_while(char::is_ascii_digit).count();
+        let integer = self.rest()[..len]
+            .parse()
+            .map_err(|_| self.error("expected a number"))?;
+        self.position += len;
+        Ok(integer)
+    }
+
+    fn probability(&mut self) -> Result<f64, ParseError> {
+        self.skip_space();
+        let len = self
+            .rest()
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == 'e' || *c == '-')
+            .count();
+        match self.rest()[..len].parse::<f64>() {
+            Ok(p) if (0.0..=1.0).contains(&p) => {
+                self.position += len;
+                Ok(p)
+            }
+            _ => Err(self.error("expected a probability between 0 and 1")),
+        }
+    }
+
+    /// choice parses alternatives separated by |, each optionally weighted
+    fn choice(&mut self) -> Result<Tree, ParseError> {
+        let mut choices = vec![];
+        let mut weights = vec![];
+        loop {
+            choices.push(self.sequence()?);
+            weights.push(if self.eat('@') {
+                Some(self.integer()?)
+            } else {
+                None
+            });
+            if !self.eat('|') {
+                break;
+            }
+        }
+
+        if weights.iter().all(Option::is_none) {
+            return Ok(if choices.len() == 1 {
+                choices.pop().unwrap()
+            } else {
+                Tree::Choice(choices)
+            });
+        }
+
+        let weights = weights
+            .into_iter()
+            .map(|w| w.unwrap_or(1))
+            .collect::<Vec<_>>();
+        if weights.iter().all(|w| *w == 0) {
+            return Err(self.error("at least one weight must be greater than 0"));
+        }
+        Ok(Tree::WeightedChoice { choices, weights })
+    }
+
+    fn sequence(&mut self) -> Result<Tree, ParseError> {
+        let mut generators = vec![self.unary()?];
+        loop {
+            self.skip_space();
+            match self.peek() {
+                Some(c) if c == '\'' || c == '"' || c == '.' || c == '(' || c == '!' => {}
+                Some(c) if c == '_' || c.is_alphanumeric() => {}
+                _ => break,
+            }
+            generators.push(self.unary()?);
+        }
+
+        Ok(if generators.len() == 1 {
+            generators.pop().unwrap()
+        } else {
+            Tree::Sequence(generators)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Tree, ParseError> {
+        if self.eat('!') {
+            return Ok(Tree::Not(Box::new(self.unary()?)));
+        }
+
+        let mut tree = self.atom()?;
+        loop {
+            self.skip_space();
+            let operator = match self.bump_if(&['*', '+', '?', '{']) {
+                Some(operator) => operator,
+                None => return Ok(tree),
+            };
+            if operator == '{' && self.eat('.') {
+                self.expect('.')?;
+                self.maximum(&tree)?;
+                self.expect('}')?;
+                continue;
+            }
+            let generator = Box::new(tree);
+            tree = match operator {
+                '*' => Tree::Many(generator),
+                '+' => Tree::Many1(generator),
+                '?' => Tree::Optional(generator),
+                _ => {
+                    let n = self.integer()?;
+                    let tree = if self.eat(',') {
+                        self.skip_space();
+                        let start = self.position;
+                        let m = self.integer()?;
+                        if m < n {
+                            return Err(
+                                self.error_at(start, "the maximum is less than the minimum")
+                            );
+                        }
+                        Tree::Range { n, m, generator }
+                    } else {
+                        Tree::RepeatN { n, generator }
+                    };
+                    self.expect('}')?;
+                    tree
+                }
+            };
+        }
+    }
+
+    /// maximum parses the maximum number of repetitions written after a
+    /// repetition, which must be the one it has
+    fn maximum(&mut self, tree: &Tree) -> Result<(), ParseError> {
+        self.skip_space();
+        let start = self.position;
+        let m = self.integer()?;
+        let max = match tree.maximum() {
+            Some(max) => max,
+            None => {
+                return Err(self.error_at(
+                    start,
+                    "only *, +, sep_by and sep_by1 have a maximum number of repetitions",
+                ))
+            }
+        };
+        if m != max {
+            return Err(self.error_at(
+                start,
+                &format!("the maximum number of repetitions is {}", max),
+            ));
+        }
+        Ok(())
+    }
+
+    fn atom(&mut self) -> Result<Tree, ParseError> {
+        self.skip_space();
+        let start = self.position;
+        match self.peek() {
+            Some('\'') => {
+                let n = self.char_literal()?;
+                self.skip_space();
+                if self.rest().starts_with("..") {
+                    self.position += 2;
+                    self.skip_space();
+                    let m = self.char_literal()?;
+                    if m < n {
+                        return Err(self.error_at(start, "the range ends before it starts"));
+                    }
+                    return Ok(Tree::CharRange { n, m });
+                }
+                Ok(Tree::CharLiteral(n))
+            }
+            Some('"') => {
+                let bytes = self.quoted('"')?;
+                match String::from_utf8(bytes) {
+                    Ok(s) => Ok(Tree::StringLiteral(s)),
+                    Err(_) => Err(self.error_at(start, "strings must be valid UTF-8")),
+                }
+            }
+            Some('.') => {
+                self.bump();
+                Ok(Tree::Any)
+            }
+            Some('(') => {
+                self.bump();
+                if self.eat(')') {
+                    return Ok(Tree::Sequence(vec![]));
+                }
+                let tree = self.choice()?;
+                self.expect(')')?;
+                Ok(tree)
+            }
+            Some('0') if self.rest().starts_with("0x") => {
+                let digits = self.rest().get(2..4).unwrap_or("");
+                match u8::from_str_radix(digits, 16) {
+                    Ok(byte) if digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+                        self.position += 4;
+                        Ok(Tree::ByteLiteral(byte))
+                    }
+                    _ => Err(self.error("expected a byte such as 0x0a")),
+                }
+            }
+            _ => {
+                let name = match self.identifier() {
+                    Some(name) => name,
+                    None => return Err(self.error("expected an expression")),
+                };
+                if self.peek() == Some('(') {
+                    if let Some(tree) = self.call(&name, start)? {
+                        return Ok(tree);
+                    }
+                }
+                self.references.push((name.clone(), start));
+                Ok(Tree::Rule(name))
+            }
+        }
+    }
+
+    /// call parses the arguments of a call to name if it is a Generator
+    /// written as a call. A rule with the same name can still be referenced
+    /// by writing a space before the parenthesis
+    fn call(&mut self, name: &str, start: usize) -> Result<Option<Tree>, ParseError> {
+        let tree = match name {
+            "dictionary" => {
+                self.bump();
+                let mut entries = vec![];
+                if !self.eat(')') {
+                    loop {
+                        self.skip_space();
+                        entries.push(self.quoted('"')?);
+                        if !self.eat(',') {
+                            break;
+                        }
+                    }
+                    self.expect(')')?;
+                }
+                return Ok(Some(Tree::Dictionary(entries)));
+            }
+            "mix" => {
+                self.bump();
+                let generator = Box::new(self.choice()?);
+                self.expect(',')?;
+                let dictionary = Box::new(self.choice()?);
+                self.expect(',')?;
+                let probability = self.probability()?;
+                Tree::Mix {
+                    generator,
+                    dictionary,
+                    probability,
+                }
+            }
+            "join_with" => {
+                self.bump();
+                let delimiter = Box::new(self.choice()?);
+                self.expect(';')?;
+                let mut generators = vec![self.choice()?];
+                while self.eat(',') {
+                    generators.push(self.choice()?);
+                }
+                // the empty list is written as ()
+                if generators == [Tree::Sequence(vec![])] {
+                    generators.clear();
+                }
+                Tree::JoinWith {
+                    generators,
+                    delimiter,
+                }
+            }
+            "sep_by" | "sep_by1" => {
+                self.bump();
+                let generator = Box::new(self.choice()?);
+                self.expect(',')?;
+                let separator = Box::new(self.choice()?);
+                if name == "sep_by" {
+                    Tree::SepBy {
+                        generator,
+                        separator,
+                    }
+                } else {
+                    Tree::SepBy1 {
+                        generator,
+                        separator,
+                    }
+                }
+            }
+            "target_size" => {
+                self.bump();
+                let generator = Box::new(self.choice()?);
+                self.expect(',')?;
+                let min = self.integer()?;
+                self.expect(',')?;
+                let max = self.integer()?;
+                if max < min {
+                    return Err(self.error_at(start, "the maximum is less than the minimum"));
+                }
+                Tree::TargetSize {
+                    min,
+                    max,
+                    generator,
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        self.expect(')')?;
+        Ok(Some(tree))
+    }
+
+    fn char_literal(&mut self) -> Result<char, ParseError> {
+        let start = self.position;
+        let bytes = self.quoted('\'')?;
+        let s = String::from_utf8(bytes).unwrap_or_default();
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(self.error_at(start, "expected a single char")),
+        }
+    }
+
+    /// quoted parses a literal between quotes with its escapes resolved.
+    /// \xNN escapes can produce bytes that aren't valid UTF-8
+    fn quoted(&mut self, quote: char) -> Result<Vec<u8>, ParseError> {
+        if self.peek() != Some(quote) {
+            return Err(self.error(&format!("expected {}", quote)));
+        }
+        self.bump();
+
+        let mut bytes = vec![];
+        loop {
+            let escape = self.position;
+            let c = match self.bump() {
+                Some(c) if c == quote => return Ok(bytes),
+                Some('\\') => match self.bump() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some(c) if c == '\\' || c == '\'' || c == '"' => c,
+                    Some('x') => {
+                        let digits = self.rest().get(..2).unwrap_or("");
+                        match u8::from_str_radix(digits, 16) {
+                            Ok(byte) if digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+                                self.position += 2;
+                                bytes.push(byte);
+                                continue;
+                            }
+                            _ => return Err(self.error_at(escape, "invalid \\x escape")),
+                        }
+                    }
+                    Some('u') => {
+                        let rest = self.rest();
+                        let c = rest
+                            .strip_prefix('{')
+                            .and_then(|r| r.find('}').map(|end| &r[..end]))
+                            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                            .and_then(char::from_u32);
+                        match c {
+                            Some(c) => {
+                                self.position += rest.find('}').unwrap() + 1;
+                                c
+                            }
+                            None => return Err(self.error_at(escape, "invalid \\u escape")),
+                        }
+                    }
+                    _ => return Err(self.error_at(escape, "invalid escape")),
+                },
+                Some(c) => c,
+                None => return Err(self.error_at(escape, "unterminated literal")),
+            };
+
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use {rule, Context, Generator};
+
+    const EXPRESSION: &str = "
+        # the expression grammar of examples/expression.rs
+        expression = join_with(' '; number, operator, expression | number)
+                   | '(' expression ')' ;
+        number = '1'..'9' '0'..'9'+ ;
+        operator = '*' | '/' | '+' @2 | '-' @2 ;
+    ";
+
+    #[test]
+    fn parses_grammars() {
+        let rule_set = parse_rule_set(EXPRESSION).unwrap();
+        assert_eq!(
+            rule_set.to_string(),
+            "expression = join_with(' '; number, operator, expression | number) \
+             | '(' expression ')' ;\n\
+             number = '1'..'9' '0'..'9'+{..4} ;\n\
+             operator = '*' @1 | '/' @1 | '+' @2 | '-' @2 ;\n"
+        );
+
+        let rules = rule_set.to_rules();
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1)).recording();
+        for _ in 0..20 {
+            let value = rule("expression", rules.clone()).generate_with(&mut ctx);
+            assert!(!value.is_empty());
+            assert_eq!(ctx.take_derivations()[0].rule, "expression");
+        }
+    }
+
+    #[test]
+    fn round_trips_every_generator() {
+        let source = "all = !('a' | \"b\\n\\u{301}\" 0x00)*{..4} . 'a'..'z'+{..4} x? x{2} \
+                      x{0,3} dictionary(\"\\xff\", \"\\\"\") mix(x, dictionary(), 0.25) \
+                      join_with(0x20; ()) sep_by(x, \",\"){..4} sep_by1(x, \",\"){..4} \
+                      target_size(x @3 | () @1, 1, 10) () ;\n\
+                      x = \"x\" ;\n";
+        let rule_set = parse_rule_set(source).unwrap();
+        assert_eq!(rule_set.to_string(), source);
+        assert_eq!(parse_rule_set(&rule_set.to_string()).unwrap(), rule_set);
+
+        // the maximum is optional
+        assert_eq!(
+            parse_rule_set("a = 'a'* sep_by1('a', ',')+ ;").unwrap(),
+            parse_rule_set("a = 'a'*{..4} sep_by1('a', ','){ .. 4 }+{..4} ;").unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(
+            parse_rule_set("a = 'a' ;\nb = 'b' c ;").unwrap_err(),
+            ParseError {
+                line: 2,
+                column: 9,
+                message: String::from("rule 'c' is not defined"),
+            }
+        );
+        match load_rules("does-not-exist.synfuzz") {
+            Err(LoadError::Io(ref e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("expected an I/O error, got {:?}", other.map(|_| ())),
+        }
+
+        let error = |source: &str| parse_rule_set(source).unwrap_err().to_string();
+        assert_eq!(
+            error("a = 'a' ;\nb = 'b' c ;"),
+            "line 2 column 9: rule 'c' is not defined"
+        );
+        assert_eq!(
+            error("a = 'a' | ;"),
+            "line 1 column 11: expected an expression, found ';'"
+        );
+        assert_eq!(
+            error("a = 'a'{3,1} ;"),
+            "line 1 column 11: the maximum is less than the minimum"
+        );
+        assert_eq!(
+            error("a = 'a'*{..9} ;"),
+            "line 1 column 12: the maximum number of repetitions is 4"
+        );
+        assert_eq!(
+            error("a = 'a'?{..4} ;"),
+            "line 1 column 12: only *, +, sep_by and sep_by1 have a maximum number of repetitions"
+        );
+        assert_eq!(error("a = \"\\q\" ;"), "line 1 column 6: invalid escape");
+        assert_eq!(
+            error("a = 'a' ;\na = 'b' ;"),
+            "line 2 column 1: rule 'a' is defined twice"
+        );
+        assert_eq!(
+            error("a = \"a\""),
+            "line 1 column 8: expected ';', found the end of the grammar"
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use rand;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::DirBuilderExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The argument that is replaced by the path of the input file
+const FILE_PLACEHOLDER: &str = "@@";
+/// How long to wait between checks of whether the target has exited or is
+/// accepting connections
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// How long a target that served a connection is given to exit or crash
+/// after closing it before it is stopped
+const SERVE_GRACE: Duration = Duration::from_millis(50);
+
+/// Used to give the directory of every input file written by this process a
+/// unique name
+static INPUT_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Delivery is how an input is handed to the target program
+#[derive(Debug, Clone)]
+pub enum Delivery {
+    /// Write the input to the standard input of the target
+    Stdin,
+    /// Write the input to a temporary file and replace every @@ argument
+    /// with its path, or pass it as the last argument if there is no @@
+    File,
+    /// Connect to the address once the target is listening and send the
+    /// input over the connection
+    Tcp(SocketAddr),
+    /// Connect to the unix domain socket at the path once the target is
+    /// listening and send the input over the connection
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Outcome is how an execution of the target ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The target exited with the status code
+    Exited(i32),
+    /// The target was terminated by the signal
+    Signaled(i32),
+    /// The target didn't exit before the timeout and was killed
+    Timeout,
+    /// The target closed the connection the input was sent over and was
+    /// killed while still running, as servers keep listening for more
+    Served,
+}
+
+impl Outcome {
+    /// is_failure returns true if the target didn't exit successfully or
+    /// serve a connection
+    pub fn is_failure(&self) -> bool {
+        !matches!(*self, Outcome::Exited(0) | Outcome::Served)
+    }
+
+    /// kind returns a short name for the outcome that is used to name saved
+    /// inputs
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Outcome::Exited(0) => "ok",
+            Outcome::Exited(_) => "exit",
+            Outcome::Signaled(_) => "crash",
+            Outcome::Timeout => "timeout",
+            Outcome::Served => "served",
+        }
+    }
+
+    fn from_status(status: ExitStatus) -> Outcome {
+        #[cfg(unix)]
+        {
+            if let Some(signal) = status.signal() {
+                return Outcome::Signaled(signal);
+            }
+        }
+
+        Outcome::Exited(status.code().unwrap_or(-1))
+    }
+}
+
+/// Execution is the result of running the target on a single input
+#[derive(Debug, Clone)]
+pub struct Execution {
+    pub outcome: Outcome,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub duration: Duration,
+}
+
+/// Executor runs a local program once for every input it is given, the way
+/// AFL runs a target without a fork server. The input is delivered on
+/// standard input by default, or as a file when an argument is @@
+#[derive(Debug, Clone)]
+pub struct Executor {
+    program: PathBuf,
+    args: Vec<String>,
+    delivery: Delivery,
+    timeout: Duration,
+}
+
+impl Executor {
+    /// new creates an Executor for the program with no arguments that reads
+    /// its input from standard input and is killed after a second
+    pub fn new<P>(program: P) -> Executor
+    where
+        P: Into<PathBuf>,
+    {
+        Executor {
+            program: program.into(),
+            args: vec![],
+            delivery: Delivery::Stdin,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// arg adds an argument to the command line of the target. An argument
+    /// of @@ switches delivery to File
+    pub fn arg<S>(mut self, arg: S) -> Executor
+    where
+        S: Into<String>,
+    {
+        let arg = arg.into();
+        if arg == FILE_PLACEHOLDER {
+            self.delivery = Delivery::File;
+        }
+        self.args.push(arg);
+        self
+    }
+
+    /// args adds several arguments to the command line of the target
+    pub fn args<I, S>(self, args: I) -> Executor
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        args.into_iter()
+            .fold(self, |executor, arg| executor.arg(arg))
+    }
+
+    /// delivery sets how inputs are handed to the target
+    pub fn delivery(mut self, delivery: Delivery) -> Executor {
+        self.delivery = delivery;
+        self
+    }
+
+    /// timeout sets how long the target may run, including the time it
+    /// takes to start listening when inputs are delivered over a socket
+    pub fn timeout(mut self, timeout: Duration) -> Executor {
+        self.timeout = timeout;
+        self
+    }
+
+    /// execute runs the target on input and waits for it to exit or time
+    /// out
+    pub fn execute(&self, input: &[u8]) -> io::Result<Execution> {
+        let file = match self.delivery {
+            Delivery::File => Some(InputFile::new(input)?),
+            _ => None,
+        };
+
+        let mut command = Command::new(&self.program);
+        for arg in &self.args {
+            match file {
+                Some(ref file) if arg == FILE_PLACEHOLDER => command.arg(&file.path),
+                _ => command.arg(arg),
+            };
+        }
+        if let Some(ref file) = file {
+            if !self.args.iter().any(|arg| arg == FILE_PLACEHOLDER) {
+                command.arg(&file.path);
+            }
+        }
+        command
+            .stdin(match self.delivery {
+                Delivery::Stdin => Stdio::piped(),
+                _ => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let started = Instant::now();
+        let deadline = started + self.timeout;
+        let mut child = command.spawn()?;
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+
+        let writer = child.stdin.take().map(|mut stdin| {
+            let input = input.to_vec();
+            // a target that exits without reading all of its input is not an
+            // error so the result is ignored
+            thread::spawn(move || {
+                let _ = stdin.write_all(&input);
+            })
+        });
+
+        let outcome = match self.send(&mut child, input, deadline) {
+            Ok(Some(outcome)) => outcome,
+            Ok(None) => wait(&mut child, deadline)?,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+        let duration = started.elapsed();
+
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+
+        Ok(Execution {
+            outcome,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+            duration,
+        })
+    }
+
+    /// send delivers input over a socket and returns the outcome once the
+    /// target closes the connection, or if it exits or times out before
+    /// accepting it
+    fn send(
+        &self,
+        child: &mut Child,
+        input: &[u8],
+        deadline: Instant,
+    ) -> io::Result<Option<Outcome>> {
+        loop {
+            let result = match self.delivery {
+                Delivery::Stdin | Delivery::File => return Ok(None),
+                Delivery::Tcp(address) => TcpStream::connect(address).and_then(|mut stream| {
+                    stream.write_all(input)?;
+                    stream.shutdown(Shutdown::Write)?;
+                    stream.set_read_timeout(Some(remaining(deadline)))?;
+                    Ok(Box::new(stream) as Box<dyn Read>)
+                }),
+                #[cfg(unix)]
+                Delivery::Unix(ref path) => UnixStream::connect(path).and_then(|mut stream| {
+                    stream.write_all(input)?;
+                    stream.shutdown(Shutdown::Write)?;
+                    stream.set_read_timeout(Some(remaining(deadline)))?;
+                    Ok(Box::new(stream) as Box<dyn Read>)
+                }),
+            };
+
+            match result {
+                Ok(stream) => return serve(child, stream, deadline).map(Some),
+                Err(e) => match e.kind() {
+                    io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound => {}
+                    _ => return Err(e),
+                },
+            }
+
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(Outcome::from_status(status)));
+            }
+
+            if Instant::now() >= deadline {
+                return kill(child, Outcome::Timeout).map(Some);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// wait for child to exit, killing it once the deadline passes
+fn wait(child: &mut Child, deadline: Instant) -> io::Result<Outcome> {
+    match wait_until(child, deadline)? {
+        Some(status) => Ok(Outcome::from_status(status)),
+        None => kill(child, Outcome::Timeout),
+    }
+}
+
+/// serve waits for the target to close the connection the input was sent
+/// over, which it is expected to do once it is done with the input. A
+/// target that is still running shortly after is stopped and Served, while
+/// one that keeps the connection open past the deadline times out
+fn serve(child: &mut Child, mut stream: Box<dyn Read>, deadline: Instant) -> io::Result<Outcome> {
+    let mut buf = [0; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) if Instant::now() < deadline => {}
+            Ok(_) => return wait(child, deadline),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return wait(child, deadline)
+            }
+            // a reset connection is closed too
+            Err(_) => break,
+        }
+    }
+
+    match wait_until(child, deadline.min(Instant::now() + SERVE_GRACE))? {
+        Some(status) => Ok(Outcome::from_status(status)),
+        None => kill(child, Outcome::Served),
+    }
+}
+
+/// wait_until waits for child to exit until the deadline passes, returning
+/// None if it is still running
+fn wait_until(child: &mut Child, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn kill(child: &mut Child, outcome: Outcome) -> io::Result<Outcome> {
+    child.kill()?;
+    child.wait()?;
+    Ok(outcome)
+}
+
+/// remaining returns the time left until the deadline, which is never zero
+/// so that it can be used as a socket timeout
+fn remaining(deadline: Instant) -> Duration {
+    deadline
+        .saturating_duration_since(Instant::now())
+        .max(POLL_INTERVAL)
+}
+
+/// read everything from a pipe of the child on another thread so that a
+/// target writing more than the pipe can hold doesn't block
+fn drain<R>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut output = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        output
+    })
+}
+
+/// InputFile is a temporary file holding an input that is removed once the
+/// execution is done. It is created in a new directory that only the current
+/// user can access, so another user can't plant a symlink where it is written
+struct InputFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl InputFile {
+    fn new(input: &[u8]) -> io::Result<InputFile> {
+        let dir = private_dir()?;
+        let path = dir.join("input");
+        let file = OpenOptions::new().write(true).create_new(true).open(&path);
+        // removes the directory again if the file can't be written
+        let input_file = InputFile { dir, path };
+        file?.write_all(input)?;
+        Ok(input_file)
+    }
+}
+
+impl Drop for InputFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// private_dir creates a directory in the temporary directory that only the
+/// current user can access. The name is unpredictable and creating it fails
+/// rather than reusing a directory that already exists
+fn private_dir() -> io::Result<PathBuf> {
+    loop {
+        let dir = env::temp_dir().join(format!(
+            "synfuzz-{}-{}-{:016x}",
+            process::id(),
+            INPUT_FILES.fetch_add(1, Ordering::Relaxed),
+            rand::random::<u64>()
+        ));
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        builder.mode(0o700);
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Executor {
+        Executor::new("/bin/sh").args(vec!["-c", script])
+    }
+
+    #[test]
+    fn stdin_delivery() {
+        let execution = sh("cat").execute(b"hello").unwrap();
+        assert_eq!(execution.outcome, Outcome::Exited(0));
+        assert_eq!(execution.stdout, b"hello".to_vec());
+    }
+
+    #[test]
+    fn file_delivery() {
+        let executor = sh("cat \"$0\"").arg("@@");
+        let execution = executor.execute(b"hello").unwrap();
+        assert_eq!(execution.outcome, Outcome::Exited(0));
+        assert_eq!(execution.stdout, b"hello".to_vec());
+
+        let executor = sh("cat \"$0\"").delivery(Delivery::File);
+        let execution = executor.execute(b"hello").unwrap();
+        assert_eq!(execution.stdout, b"hello".to_vec());
+    }
+
+    #[test]
+    fn input_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = InputFile::new(b"hello").unwrap();
+        let dir = file.dir.clone();
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert_eq!(fs::read(&file.path).unwrap(), b"hello".to_vec());
+
+        drop(file);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn failures() {
+        let exit = sh("exit 3").execute(b"").unwrap();
+        assert_eq!(exit.outcome, Outcome::Exited(3));
+        assert!(exit.outcome.is_failure());
+
+        let crash = sh("kill -SEGV $$").execute(b"").unwrap();
+        assert_eq!(crash.outcome, Outcome::Signaled(11));
+
+        let timeout = sh("exec sleep 5")
+            .timeout(Duration::from_millis(50))
+            .execute(b"")
+            .unwrap();
+        assert_eq!(timeout.outcome, Outcome::Timeout);
+    }
+
+    #[test]
+    fn serving_is_not_a_failure() {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut server = Command::new("sleep").arg("5").spawn().unwrap();
+        let outcome = serve(&mut server, Box::new(io::empty()), deadline).unwrap();
+        assert_eq!(outcome, Outcome::Served);
+        assert!(!outcome.is_failure());
+
+        let mut crash = Command::new("/bin/sh")
+            .args(["-c", "kill -SEGV $$"])
+            .spawn()
+            .unwrap();
+        let outcome = serve(&mut crash, Box::new(io::empty()), deadline).unwrap();
+        assert_eq!(outcome, Outcome::Signaled(11));
+    }
+}
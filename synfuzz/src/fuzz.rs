@@ -0,0 +1,614 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::any::Any;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Derivation, Rule, Rules};
+use parallel::worker_seed;
+use runner::{generate_seeded, hash};
+use stats::Statistics;
+
+/// How often the fuzz loop checks for hung inputs and reports progress
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(10);
+/// How often progress is logged
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// How many inputs a worker runs between merging its Statistics
+const MERGE_INTERVAL: usize = 64;
+
+/// Fuzzer is an in-process fuzz loop. Each worker thread generates inputs
+/// from the start rule and hands them to its own clone of the target, which
+/// fails by panicking or by running longer than the timeout. Failing inputs
+/// are saved along with their seed and Derivation
+///
+/// ```ignore
+/// let report = Fuzzer::new(rules, "json")
+///     .threads(4)
+///     .duration(Duration::from_secs(60))
+///     .output("crashes")
+///     .run(|input| {
+///         let _ = parse(input);
+///     });
+/// println!("{}", report.stats);
+/// ```
+#[derive(Debug)]
+pub struct Fuzzer {
+    start: Rule,
+    threads: usize,
+    timeout: Duration,
+    iterations: Option<usize>,
+    duration: Option<Duration>,
+    keep_going: bool,
+    output: Option<PathBuf>,
+    seed: u64,
+}
+
+/// Problem is how the target failed on an input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The target panicked with the message
+    Panic(String),
+    /// The target ran for longer than the timeout. Targets that never return
+    /// are left running on their worker thread
+    Timeout(Duration),
+}
+
+/// Finding is an input the target failed on
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub seed: u64,
+    pub input: Vec<u8>,
+    pub derivation: Derivation,
+    pub problem: Problem,
+    pub path: Option<PathBuf>,
+}
+
+/// Stats describes the progress of a fuzz loop
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub executions: usize,
+    pub findings: usize,
+    pub elapsed: Duration,
+    /// The Statistics of the generated inputs, which count the expansions
+    /// of every rule of the grammar
+    pub statistics: Statistics,
+}
+
+impl Stats {
+    /// executions_per_second returns the throughput of the fuzz loop
+    pub fn executions_per_second(&self) -> f64 {
+        self.progress().executions_per_second()
+    }
+
+    /// covered returns the number of rules of the grammar that have been
+    /// expanded
+    pub fn covered(&self) -> usize {
+        self.statistics
+            .expansions()
+            .values()
+            .filter(|&&count| count > 0)
+            .count()
+    }
+
+    /// coverage returns the fraction of the rules of the grammar that have
+    /// been expanded
+    pub fn coverage(&self) -> f64 {
+        let rules = self.statistics.expansions().len();
+        if rules == 0 {
+            0.0
+        } else {
+            self.covered() as f64 / rules as f64
+        }
+    }
+}
+
+impl Stats {
+    fn progress(&self) -> Progress {
+        Progress {
+            executions: self.executions,
+            findings: self.findings,
+            elapsed: self.elapsed,
+            covered: self.covered(),
+            rules: self.statistics.expansions().len(),
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.progress().fmt(f)
+    }
+}
+
+/// Progress is the counters of Stats, which are logged while the fuzz loop
+/// runs without copying its Statistics
+struct Progress {
+    executions: usize,
+    findings: usize,
+    elapsed: Duration,
+    covered: usize,
+    rules: usize,
+}
+
+impl Progress {
+    fn executions_per_second(&self) -> f64 {
+        let elapsed = self.elapsed.as_secs_f64();
+        if elapsed == 0.0 {
+            0.0
+        } else {
+            self.executions as f64 / elapsed
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} executions, {:.0}/s, {} findings, {}/{} rules covered ({:.1}%)",
+            self.executions,
+            self.executions_per_second(),
+            self.findings,
+            self.covered,
+            self.rules,
+            if self.rules == 0 {
+                0.0
+            } else {
+                self.covered as f64 * 100.0 / self.rules as f64
+            }
+        )
+    }
+}
+
+/// Report is the result of a fuzz loop
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub stats: Stats,
+    pub findings: Vec<Finding>,
+}
+
+/// Running is the input a worker is currently running
+struct Running {
+    started: Instant,
+    seed: u64,
+    input: Vec<u8>,
+    derivation: Derivation,
+}
+
+/// Slot is what the watchdog knows about a worker
+#[derive(Default)]
+struct Slot {
+    running: Option<Running>,
+    hung: bool,
+}
+
+/// Shared is the state shared by the workers and the watchdog
+struct Shared {
+    start: Rule,
+    timeout: Duration,
+    output: Option<PathBuf>,
+    keep_going: bool,
+    iterations: Option<usize>,
+    stop: AtomicBool,
+    executions: AtomicUsize,
+    statistics: Mutex<Statistics>,
+    findings: Mutex<Vec<Finding>>,
+    slots: Vec<Mutex<Slot>>,
+}
+
+impl Shared {
+    /// claim reserves the next execution, returning false once the fuzz
+    /// loop should stop
+    fn claim(&self) -> bool {
+        if self.stop.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let execution = self.executions.fetch_add(1, Ordering::SeqCst);
+        match self.iterations {
+            Some(iterations) if execution >= iterations => {
+                self.executions.fetch_sub(1, Ordering::SeqCst);
+                self.stop.store(true, Ordering::SeqCst);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn merge(&self, statistics: &mut Statistics) {
+        self.statistics.lock().unwrap().merge(mem::take(statistics));
+    }
+
+    fn report(&self, seed: u64, input: Vec<u8>, derivation: Derivation, problem: Problem) {
+        let path = self.output.as_ref().map(|output| {
+            let kind = match problem {
+                Problem::Panic(_) => "panic",
+                Problem::Timeout(_) => "timeout",
+            };
+            output.join(format!("{}-{:016x}", kind, hash(&input)))
+        });
+        let finding = Finding {
+            seed,
+            input,
+            derivation,
+            problem,
+            path,
+        };
+
+        if let Err(e) = self.save(&finding) {
+            warn!("unable to save finding: {}", e);
+        }
+        self.findings.lock().unwrap().push(finding);
+        if !self.keep_going {
+            self.stop.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// save writes the input of finding along with a .txt file describing
+    /// how to reproduce it, unless the same input has already been saved
+    fn save(&self, finding: &Finding) -> io::Result<()> {
+        let path = match finding.path {
+            Some(ref path) if !path.exists() => path,
+            _ => return Ok(()),
+        };
+
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, &finding.input)?;
+        let report = format!(
+            "rule: {}\nseed: {}\nproblem: {:?}\n\nderivation:\n{}",
+            self.start.name, finding.seed, finding.problem, finding.derivation
+        );
+        fs::write(path.with_extension("txt"), report)
+    }
+}
+
+impl Fuzzer {
+    /// new creates a Fuzzer that generates from the rule named start on a
+    /// single thread until the target fails, allowing each input a second
+    pub fn new<S>(rules: Arc<RwLock<Rules>>, start: S) -> Fuzzer
+    where
+        S: Into<String>,
+    {
+        Fuzzer {
+            start: Rule {
+                rules,
+                name: start.into(),
+            },
+            threads: 1,
+            timeout: Duration::from_secs(1),
+            iterations: None,
+            duration: None,
+            keep_going: false,
+            output: None,
+            seed: rand::random(),
+        }
+    }
+
+    /// threads sets the number of worker threads
+    pub fn threads(mut self, threads: usize) -> Fuzzer {
+        assert!(threads > 0, "at least one thread is required");
+        self.threads = threads;
+        self
+    }
+
+    /// timeout sets how long the target may run on a single input
+    pub fn timeout(mut self, timeout: Duration) -> Fuzzer {
+        self.timeout = timeout;
+        self
+    }
+
+    /// iterations stops the fuzz loop after running this many inputs across
+    /// all of the threads
+    pub fn iterations(mut self, iterations: usize) -> Fuzzer {
+        self.iterations = Some(iterations);
+        self
+    }
+
+    /// duration stops the fuzz loop after it has run for this long
+    pub fn duration(mut self, duration: Duration) -> Fuzzer {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// keep_going continues after the target fails instead of stopping at
+    /// the first failure
+    pub fn keep_going(mut self, keep_going: bool) -> Fuzzer {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// output saves failing inputs, along with a .txt file describing how to
+    /// reproduce them, to the directory
+    pub fn output<P>(mut self, output: P) -> Fuzzer
+    where
+        P: Into<PathBuf>,
+    {
+        self.output = Some(output.into());
+        self
+    }
+
+    /// seed sets the seed the seeds of the workers are derived from. Each
+    /// worker generates the same sequence of inputs for the same seed
+    pub fn seed(mut self, seed: u64) -> Fuzzer {
+        self.seed = seed;
+        self
+    }
+
+    /// generate returns the input, and its Derivation, generated from the
+    /// seed of a Finding
+    pub fn generate(&self, seed: u64) -> (Vec<u8>, Derivation) {
+        generate_seeded(&self.start, seed, false)
+    }
+
+    /// run runs the fuzz loop until the target fails, unless keep_going is
+    /// set, or the iterations or duration limit is reached
+    pub fn run<F>(&self, target: F) -> Report
+    where
+        F: FnMut(&[u8]) + Clone + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            start: Rule {
+                rules: self.start.rules.clone(),
+                name: self.start.name.clone(),
+            },
+            timeout: self.timeout,
+            output: self.output.clone(),
+            keep_going: self.keep_going,
+            iterations: self.iterations,
+            stop: AtomicBool::new(false),
+            executions: AtomicUsize::new(0),
+            statistics: Mutex::new(Statistics::new().rules(&self.start.rules)),
+            findings: Mutex::new(vec![]),
+            slots: (0..self.threads).map(|_| Mutex::default()).collect(),
+        });
+
+        let started = Instant::now();
+        let mut workers = (0..self.threads)
+            .map(|worker| {
+                let shared = shared.clone();
+                let target = target.clone();
+                let seed = worker_seed(self.seed, worker);
+                Some(thread::spawn(move || work(&shared, worker, seed, target)))
+            })
+            .collect::<Vec<_>>();
+
+        let mut logged = started;
+        while workers.iter().any(|w| w.is_some()) {
+            thread::sleep(WATCHDOG_INTERVAL);
+            if self.duration.is_some_and(|d| started.elapsed() >= d) {
+                shared.stop.store(true, Ordering::SeqCst);
+            }
+
+            for (worker, handle) in workers.iter_mut().enumerate() {
+                if watch(&shared, worker) {
+                    // the worker is stuck in the target so it is abandoned
+                    *handle = None;
+                } else if handle.as_ref().is_some_and(|h| h.is_finished()) {
+                    if let Err(e) = handle.take().unwrap().join() {
+                        panic::resume_unwind(e);
+                    }
+                }
+            }
+
+            if logged.elapsed() >= PROGRESS_INTERVAL {
+                info!("{}", progress(&shared, started));
+                logged = Instant::now();
+            }
+        }
+
+        let stats = self.stats(&shared, started);
+        let findings = mem::take(&mut *shared.findings.lock().unwrap());
+        Report { stats, findings }
+    }
+
+    fn stats(&self, shared: &Shared, started: Instant) -> Stats {
+        Stats {
+            executions: shared.executions.load(Ordering::SeqCst),
+            findings: shared.findings.lock().unwrap().len(),
+            elapsed: started.elapsed(),
+            statistics: mem::take(&mut *shared.statistics.lock().unwrap()),
+        }
+    }
+}
+
+/// progress snapshots the counters of the fuzz loop
+fn progress(shared: &Shared, started: Instant) -> Progress {
+    let (covered, rules) = {
+        let statistics = shared.statistics.lock().unwrap();
+        let expansions = statistics.expansions();
+        (
+            expansions.values().filter(|&&count| count > 0).count(),
+            expansions.len(),
+        )
+    };
+    Progress {
+        executions: shared.executions.load(Ordering::SeqCst),
+        findings: shared.findings.lock().unwrap().len(),
+        elapsed: started.elapsed(),
+        covered,
+        rules,
+    }
+}
+
+/// work is the loop run by each worker thread
+fn work<F>(shared: &Shared, worker: usize, seed: u64, mut target: F)
+where
+    F: FnMut(&[u8]),
+{
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut statistics = Statistics::new();
+    let mut runs = 0;
+
+    while shared.claim() {
+        let seed = rng.gen();
+        let started = Instant::now();
+        let (input, derivation) = generate_seeded(&shared.start, seed, false);
+        statistics.record(
+            &input,
+            slice::from_ref(&derivation),
+            false,
+            started.elapsed(),
+        );
+
+        let started = Instant::now();
+        shared.slots[worker].lock().unwrap().running = Some(Running {
+            started,
+            seed,
+            input: input.clone(),
+            derivation: derivation.clone(),
+        });
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| target(&input)));
+        let elapsed = started.elapsed();
+
+        let hung = {
+            let mut slot = shared.slots[worker].lock().unwrap();
+            slot.running = None;
+            mem::replace(&mut slot.hung, false)
+        };
+
+        // a hung input has already been reported by the watchdog
+        if !hung {
+            match result {
+                Err(payload) => {
+                    shared.report(seed, input, derivation, Problem::Panic(message(&*payload)))
+                }
+                Ok(()) if elapsed > shared.timeout => {
+                    shared.report(seed, input, derivation, Problem::Timeout(elapsed))
+                }
+                Ok(()) => {}
+            }
+        }
+
+        runs += 1;
+        if runs % MERGE_INTERVAL == 0 {
+            shared.merge(&mut statistics);
+        }
+    }
+
+    shared.merge(&mut statistics);
+}
+
+/// watch reports the input of the worker once it has run for longer than
+/// the timeout and returns true if the worker should be abandoned because
+/// the fuzz loop is stopping while it is still hung
+fn watch(shared: &Shared, worker: usize) -> bool {
+    let mut slot = shared.slots[worker].lock().unwrap();
+    let overdue = match slot.running {
+        Some(ref running) if !slot.hung => running.started.elapsed() > shared.timeout,
+        _ => false,
+    };
+
+    if overdue {
+        slot.hung = true;
+        let running = slot.running.as_ref().unwrap();
+        let (seed, input, derivation) = (
+            running.seed,
+            running.input.clone(),
+            running.derivation.clone(),
+        );
+        let elapsed = running.started.elapsed();
+        drop(slot);
+        shared.report(seed, input, derivation, Problem::Timeout(elapsed));
+        return shared.stop.load(Ordering::SeqCst);
+    }
+
+    slot.hung && shared.stop.load(Ordering::SeqCst)
+}
+
+/// message returns the message of a panic payload
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use {byte, choice, register_rule, rule, seq};
+
+    fn bits() -> Arc<RwLock<Rules>> {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(
+            &rules,
+            "bits",
+            seq!(rule("bit", rules.clone()), rule("bit", rules.clone())),
+        );
+        register_rule(&rules, "bit", choice!(byte(0x30), byte(0x31)));
+        register_rule(&rules, "unused", byte(0x32));
+        rules
+    }
+
+    #[test]
+    fn runs_iterations_across_threads() {
+        let report = Fuzzer::new(bits(), "bits")
+            .threads(4)
+            .iterations(1000)
+            .run(|input| assert_eq!(input.len(), 2));
+
+        assert!(report.findings.is_empty());
+        assert_eq!(report.stats.executions, 1000);
+        let statistics = &report.stats.statistics;
+        assert_eq!(statistics.samples(), 1000);
+        assert_eq!(statistics.lengths().max, 2.0);
+        assert_eq!(statistics.expansions()["bits"], 1000);
+        assert_eq!(statistics.expansions()["bit"], 2000);
+        assert_eq!(statistics.expansions()["unused"], 0);
+        assert_eq!(report.stats.covered(), 2);
+    }
+
+    #[test]
+    fn catches_panics() {
+        let report = Fuzzer::new(bits(), "bits")
+            .threads(2)
+            .iterations(1000)
+            .keep_going(true)
+            .run(|input| {
+                if input == b"11" {
+                    panic!("found it");
+                }
+            });
+
+        assert!(!report.findings.is_empty());
+        let fuzzer = Fuzzer::new(bits(), "bits");
+        for finding in report.findings {
+            assert_eq!(finding.input, b"11".to_vec());
+            assert_eq!(finding.problem, Problem::Panic(String::from("found it")));
+            assert_eq!(fuzzer.generate(finding.seed).0, finding.input);
+        }
+    }
+
+    #[test]
+    fn stops_at_first_failure() {
+        let report = Fuzzer::new(bits(), "bits").run(|_| panic!("always"));
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.stats.executions, 1);
+    }
+
+    #[test]
+    fn abandons_hung_targets() {
+        let report = Fuzzer::new(bits(), "bits")
+            .timeout(Duration::from_millis(20))
+            .run(|_| thread::sleep(Duration::from_secs(60)));
+        assert_eq!(report.findings.len(), 1);
+        match report.findings[0].problem {
+            Problem::Timeout(elapsed) => assert!(elapsed > Duration::from_millis(20)),
+            ref problem => panic!("unexpected problem {:?}", problem),
+        }
+    }
+}
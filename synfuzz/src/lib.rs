@@ -1,23 +1,113 @@
 extern crate rand;
 
+#[cfg(feature = "serde")]
+extern crate bincode;
+#[cfg(feature = "proptest")]
+extern crate proptest;
+#[cfg(feature = "quickcheck")]
+extern crate quickcheck;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
 #[cfg(test)]
 extern crate regex;
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "quickcheck")]
+mod arbitrary;
+mod batch;
+mod codegen;
 mod combinator;
+mod context;
+mod defect;
+mod derivation;
+mod dictionary;
+mod differential;
+mod dot;
+mod dsl;
+mod executor;
+mod fuzz;
+mod mutator;
+mod negation;
+mod node;
+mod optimize;
+mod parallel;
+mod runner;
+mod stats;
+#[cfg(feature = "proptest")]
+mod strategy;
+mod tree;
 mod value;
 
+#[cfg(feature = "quickcheck")]
+pub use arbitrary::*;
+pub use batch::*;
 pub use combinator::*;
+pub use context::*;
+pub use defect::*;
+pub use derivation::{Derivation, Iter};
+pub use dictionary::*;
+pub use differential::*;
+pub use dot::*;
+pub use dsl::*;
+pub use executor::*;
+pub use fuzz::*;
+pub use mutator::*;
+pub use negation::Negation;
+pub use node::*;
+pub use optimize::*;
+pub use parallel::generate_parallel;
+pub use runner::*;
+pub use stats::*;
+#[cfg(feature = "proptest")]
+pub use strategy::*;
+pub use tree::*;
 pub use value::*;
 
 /// A trait for all Generators to implement. This allows pervasive use of
 /// impl trait throughout the implementations of the various Generators and
-/// allows not specifying concrete types.
-pub trait Generator: ::std::fmt::Debug {
+/// allows not specifying concrete types. Generators are Send and Sync so that
+/// a grammar can be shared by threads generating in parallel.
+///
+/// A Generator that emits bytes of its own, rather than combining the values
+/// of other Generators, must report them with Context::produced. TargetSize
+/// steers towards a size, and Derivation spans are measured, by the bytes
+/// reported. Repetitions count the bytes of a value that weren't reported so
+/// that they still end, but the value itself isn't steered.
+pub trait Generator: ::std::fmt::Debug + Send + Sync {
+    /// Generate a value from the specific implementation of the Generator
+    /// taking every decision from the specified Context
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8>;
+
+    /// Generate a value of the negation of the specified Generator taking
+    /// every decision from the specified Context
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8>;
+
     /// Generate a value from the specific implementation of the Generator
-    fn generate(&self) -> Vec<u8>;
+    fn generate(&self) -> Vec<u8> {
+        self.generate_with(&mut Context::new())
+    }
 
     /// Generate a value of the negation of the specified Generator
-    fn negate(&self) -> Vec<u8>;
+    fn negate(&self) -> Vec<u8> {
+        self.negate_with(&mut Context::new())
+    }
+
+    /// Return a view of the concrete Generator for code that needs to walk
+    /// the structure of a grammar
+    fn node(&self) -> Node<'_> {
+        Node::Other
+    }
+}
+
+/// generate_from_bytes generates a value from generator using data as the
+/// source of every decision, the way the arbitrary crate consumes the input
+/// of a fuzz target. The same data always produces the same value and once
+/// data runs out generation completes with the smallest possible derivation
+pub fn generate_from_bytes(generator: &dyn Generator, data: &[u8]) -> Vec<u8> {
+    generator.generate_with(&mut Context::from_bytes(data))
 }
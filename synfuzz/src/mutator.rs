@@ -0,0 +1,342 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use super::{Context, Derivation, Generator, Rule, Rules};
+
+/// The number of bytes of fresh input added beyond the size of the subtree
+/// being regenerated
+const REGENERATE_SLACK: usize = 8;
+
+/// Mutator implements grammar aware mutation and cross over for coverage
+/// guided fuzzers such as libFuzzer. Inputs are treated as the decisions
+/// consumed by generate_from_bytes rather than as the generated value, so the
+/// fuzz target calls Mutator::generate on the input it is handed and the
+/// custom mutator hooks (LLVMFuzzerCustomMutator and
+/// LLVMFuzzerCustomCrossOver, or the fuzz_mutator! and fuzz_crossover!
+/// macros of libfuzzer-sys) call mutate_in_place and crossover_into.
+///
+/// Every mutation decodes the input to its Derivation, picks a rule
+/// expansion and rewrites only the input bytes that expansion consumed, so
+/// the rest of the generated value is left intact and the result is always
+/// valid for the grammar.
+#[derive(Debug)]
+pub struct Mutator {
+    start: Rule,
+}
+
+impl Mutator {
+    /// new creates a Mutator that generates from the rule named start
+    pub fn new<S>(rules: Arc<RwLock<Rules>>, start: S) -> Mutator
+    where
+        S: Into<String>,
+    {
+        Mutator {
+            start: Rule {
+                rules,
+                name: start.into(),
+            },
+        }
+    }
+
+    /// generate decodes an input into the value that is passed to the code
+    /// being fuzzed
+    pub fn generate(&self, data: &[u8]) -> Vec<u8> {
+        self.start.generate_with(&mut Context::from_bytes(data))
+    }
+
+    /// derive decodes an input into its value and the Derivation of the start
+    /// rule
+    pub fn derive(&self, data: &[u8]) -> (Vec<u8>, Derivation) {
+        let mut ctx = Context::from_bytes(data).recording();
+        let value = self.start.generate_with(&mut ctx);
+        let derivation = ctx
+            .take_derivations()
+            .pop()
+            .expect("start rule was not recorded");
+        (value, derivation)
+    }
+
+    /// mutate returns a mutated copy of data no longer than max_size. The same
+    /// data and seed always produce the same mutation. One of the following is
+    /// applied to a randomly chosen rule expansion:
+    ///
+    /// * regenerate it from fresh random decisions
+    /// * replace it with another expansion of the same rule from data, which
+    ///   grows or shrinks recursive structures
+    /// * change a single decision within it
+    pub fn mutate(&self, data: &[u8], max_size: usize, seed: u32) -> Vec<u8> {
+        let mut rng = SmallRng::seed_from_u64(u64::from(seed));
+        let (_, derivation) = self.derive(data);
+        let nodes = derivation.iter().collect::<Vec<_>>();
+        let target = nodes[rng.gen_range(0, nodes.len())];
+
+        let mut replacement = match rng.gen_range(0, 3) {
+            0 => {
+                let len = rng.gen_range(0, target.input.len() * 2 + REGENERATE_SLACK);
+                random_bytes(&mut rng, len)
+            }
+            1 => {
+                let same = nodes
+                    .iter()
+                    .filter(|n| n.rule == target.rule && n.input != target.input)
+                    .collect::<Vec<_>>();
+                match rng.choose(&same) {
+                    Some(other) => data[other.input.clone()].to_vec(),
+                    None => random_bytes(&mut rng, REGENERATE_SLACK),
+                }
+            }
+            _ => {
+                let mut decisions = data[target.input.clone()].to_vec();
+                if decisions.is_empty() {
+                    decisions.push(rng.gen());
+                } else {
+                    let i = rng.gen_range(0, decisions.len());
+                    decisions[i] = rng.gen();
+                }
+                decisions
+            }
+        };
+
+        let mut mutated = data[..target.input.start].to_vec();
+        mutated.append(&mut replacement);
+        mutated.extend_from_slice(&data[target.input.end..]);
+        mutated.truncate(max_size);
+        mutated
+    }
+
+    /// crossover returns a copy of data1, no longer than max_size, in which
+    /// the expansion of a rule has been replaced by an expansion of the same
+    /// rule taken from data2. If the two inputs have no rule in common the
+    /// start of data1 is joined with the end of data2
+    pub fn crossover(&self, data1: &[u8], data2: &[u8], max_size: usize, seed: u32) -> Vec<u8> {
+        let mut rng = SmallRng::seed_from_u64(u64::from(seed));
+        let (_, first) = self.derive(data1);
+        let (_, second) = self.derive(data2);
+
+        let pairs = first
+            .iter()
+            .flat_map(|a| {
+                second
+                    .iter()
+                    .filter(move |b| a.rule == b.rule)
+                    .map(move |b| (a, b))
+            })
+            .collect::<Vec<_>>();
+
+        let mut crossed = match rng.choose(&pairs) {
+            Some(&(a, b)) => {
+                let mut crossed = data1[..a.input.start].to_vec();
+                crossed.extend_from_slice(&data2[b.input.clone()]);
+                crossed.extend_from_slice(&data1[a.input.end..]);
+                crossed
+            }
+            None => {
+                let head = rng.gen_range(0, data1.len() + 1);
+                let tail = rng.gen_range(0, data2.len() + 1);
+                let mut crossed = data1[..head].to_vec();
+                crossed.extend_from_slice(&data2[tail..]);
+                crossed
+            }
+        };
+
+        crossed.truncate(max_size);
+        crossed
+    }
+
+    /// shrink returns inputs that are likely to generate smaller values than
+    /// data while still being valid for the grammar, most aggressive first.
+    /// See the shrink function
+    pub fn shrink(&self, data: &[u8]) -> Vec<Vec<u8>> {
+        shrink(&self.start, data)
+    }
+
+    /// mutate_in_place mutates the first size bytes of data, which has room
+    /// for max_size bytes, and returns the new size. This matches the
+    /// signature of LLVMFuzzerCustomMutator
+    pub fn mutate_in_place(
+        &self,
+        data: &mut [u8],
+        size: usize,
+        max_size: usize,
+        seed: u32,
+    ) -> usize {
+        let max_size = max_size.min(data.len());
+        let size = size.min(data.len());
+        let mutated = self.mutate(&data[..size], max_size, seed);
+        data[..mutated.len()].copy_from_slice(&mutated);
+        mutated.len()
+    }
+
+    /// crossover_into writes the cross over of data1 and data2 into out and
+    /// returns its size. This matches the signature of
+    /// LLVMFuzzerCustomCrossOver
+    pub fn crossover_into(&self, data1: &[u8], data2: &[u8], out: &mut [u8], seed: u32) -> usize {
+        let crossed = self.crossover(data1, data2, out.len(), seed);
+        out[..crossed.len()].copy_from_slice(&crossed);
+        crossed.len()
+    }
+}
+
+/// shrink returns inputs that are likely to make generator produce smaller
+/// values than data does when generating with generate_from_bytes, most
+/// aggressive first. Each candidate is one of:
+///
+/// * data without the trailing bytes that generation never read
+/// * data with a rule expansion replaced by a nested expansion of the same
+///   rule
+/// * data with the decisions of a rule expansion removed
+/// * data with the decisions of a rule expansion set to zero, which picks the
+///   first alternative and the fewest repetitions
+/// * the first half of the decisions that were read
+/// * data with a single decision set to zero, halved or decremented
+pub fn shrink(generator: &dyn Generator, data: &[u8]) -> Vec<Vec<u8>> {
+    let mut ctx = Context::from_bytes(data).recording();
+    generator.generate_with(&mut ctx);
+    let consumed = ctx.consumed();
+    let derivations = ctx.take_derivations();
+
+    let mut candidates = vec![];
+    if consumed < data.len() {
+        candidates.push(data[..consumed].to_vec());
+    }
+
+    let splice = |span: &::std::ops::Range<usize>, replacement: &[u8]| {
+        let mut candidate = data[..span.start].to_vec();
+        candidate.extend_from_slice(replacement);
+        candidate.extend_from_slice(&data[span.end..]);
+        candidate
+    };
+
+    let nodes = derivations
+        .iter()
+        .flat_map(|d| d.iter())
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        for nested in node.iter().skip(1).filter(|n| n.rule == node.rule) {
+            candidates.push(splice(&node.input, &data[nested.input.clone()]));
+        }
+    }
+
+    for node in nodes.iter().filter(|n| !n.input.is_empty()) {
+        candidates.push(splice(&node.input, &[]));
+    }
+
+    for node in &nodes {
+        let zeroed = vec![0; node.input.len()];
+        if data[node.input.clone()] != zeroed[..] {
+            candidates.push(splice(&node.input, &zeroed));
+        }
+    }
+
+    if consumed > 1 {
+        candidates.push(data[..consumed / 2].to_vec());
+    }
+
+    for i in (0..consumed).filter(|i| data[*i] != 0) {
+        for smaller in &[0, data[i] / 2, data[i] - 1] {
+            candidates.push(splice(&(i..i + 1), &[*smaller]));
+        }
+    }
+
+    let mut seen = ::std::collections::HashSet::new();
+    candidates.retain(|c| c[..] != data[..] && seen.insert(c.clone()));
+    candidates
+}
+
+fn random_bytes<R: Rng>(rng: &mut R, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use {byte, choice, many1, register_rule, rule, seq};
+
+    fn mutator() -> Mutator {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        let expr = choice!(
+            seq!(byte(0x28), rule("expr", rules.clone()), byte(0x29)),
+            rule("number", rules.clone())
+        );
+        register_rule(&rules, "expr", expr);
+        register_rule(&rules, "number", many1(byte(0x31)));
+        Mutator::new(rules, "expr")
+    }
+
+    fn is_valid(value: &[u8]) -> bool {
+        let open = value.iter().take_while(|b| **b == 0x28).count();
+        let close = value.iter().rev().take_while(|b| **b == 0x29).count();
+        let middle = &value[open..value.len() - close];
+        open == close && !middle.is_empty() && middle.iter().all(|b| *b == 0x31)
+    }
+
+    #[test]
+    fn derive_records_input_spans() {
+        let mutator = mutator();
+        let (value, derivation) = mutator.derive(&[0x00, 0x01, 0x02]);
+        assert_eq!(value, b"(111)".to_vec());
+        assert_eq!(derivation.rule, "expr");
+        assert_eq!(derivation.output, 0..5);
+        let number = &derivation.children[0].children[0];
+        assert_eq!(number.rule, "number");
+        assert_eq!(number.output, 1..4);
+        assert_eq!(number.input, 2..3);
+    }
+
+    #[test]
+    fn mutations_stay_valid() {
+        let mutator = mutator();
+        let mut data = vec![0x00, 0x00, 0x01, 0x02];
+        for seed in 0..200 {
+            data = mutator.mutate(&data, 64, seed);
+            assert!(data.len() <= 64);
+            assert!(is_valid(&mutator.generate(&data)));
+        }
+    }
+
+    #[test]
+    fn mutate_is_deterministic() {
+        let mutator = mutator();
+        let data = [0x00, 0x00, 0x01, 0x02];
+        assert_eq!(mutator.mutate(&data, 64, 7), mutator.mutate(&data, 64, 7));
+    }
+
+    #[test]
+    fn crossover_splices_rules() {
+        let mutator = mutator();
+        let mut out = [0u8; 64];
+        for seed in 0..50 {
+            let size = mutator.crossover_into(&[0x00, 0x01, 0x02], &[0x01, 0x03], &mut out, seed);
+            assert!(is_valid(&mutator.generate(&out[..size])));
+        }
+    }
+
+    #[test]
+    fn shrink_hoists_nested_rules() {
+        let mutator = mutator();
+        let data = [0x00, 0x00, 0x01, 0x03, 0xff];
+        assert_eq!(mutator.generate(&data), b"((1111))".to_vec());
+
+        let candidates = mutator.shrink(&data);
+        assert_eq!(candidates[0], vec![0x00, 0x00, 0x01, 0x03]);
+        assert!(candidates.contains(&vec![0x01, 0x03, 0xff]));
+        for candidate in candidates {
+            assert!(is_valid(&mutator.generate(&candidate)));
+        }
+    }
+
+    #[test]
+    fn mutate_in_place_respects_max_size() {
+        let mutator = mutator();
+        let mut data = [0u8; 4];
+        for seed in 0..50 {
+            let size = mutator.mutate_in_place(&mut data, 4, 4, seed);
+            assert!(size <= 4);
+            let size = mutator.mutate_in_place(&mut data, 8, 8, seed);
+            assert!(size <= 4);
+        }
+    }
+}
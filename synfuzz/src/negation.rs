@@ -0,0 +1,236 @@
+use std::str::FromStr;
+
+use super::Context;
+
+/// The maximum length of a value produced by the Random Negation
+pub(crate) const STRING_MAX: usize = 32;
+
+/// Bytes inserted by the Invalid Negation: NUL and sequences that are never
+/// valid UTF-8 such as a stray continuation byte, an overlong encoding, the
+/// start of a truncated sequence and an encoded surrogate
+const INVALID: &[&[u8]] = &[
+    b"\x00",
+    b"\xff",
+    b"\xfe",
+    b"\x80",
+    b"\xc0\xaf",
+    b"\xe2\x82",
+    b"\xed\xa0\x80",
+];
+
+/// Negation is a strategy for negating a literal. The near miss strategies
+/// produce values that are almost the literal, such as retrun for return,
+/// which exercise the edge cases of a parser much better than an unrelated
+/// value. The strategies a Context picks from can be selected with
+/// Context::negations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Negation {
+    /// Insert, delete, substitute or transpose a single character
+    Edit,
+    /// Flip the case of a letter
+    CaseFlip,
+    /// Drop the end of the literal
+    Truncate,
+    /// Repeat the literal twice
+    Double,
+    /// Insert a NUL byte or bytes that aren't valid UTF-8
+    Invalid,
+    /// Generate a random alphanumeric string
+    Random,
+}
+
+impl Negation {
+    /// ALL is every Negation, which is what a Context picks from by default
+    pub const ALL: &'static [Negation] = &[
+        Negation::Edit,
+        Negation::CaseFlip,
+        Negation::Truncate,
+        Negation::Double,
+        Negation::Invalid,
+        Negation::Random,
+    ];
+}
+
+/// FromStr parses the kebab case name of a Negation, such as case-flip
+impl FromStr for Negation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Negation, String> {
+        match s {
+            "edit" => Ok(Negation::Edit),
+            "case-flip" => Ok(Negation::CaseFlip),
+            "truncate" => Ok(Negation::Truncate),
+            "double" => Ok(Negation::Double),
+            "invalid" => Ok(Negation::Invalid),
+            "random" => Ok(Negation::Random),
+            _ => Err(format!("unknown negation '{}'", s)),
+        }
+    }
+}
+
+/// negate_literal returns a value other than literal using one of the
+/// Negations of ctx
+pub(crate) fn negate_literal(literal: &str, ctx: &mut Context) -> Vec<u8> {
+    let negation = ctx.negation();
+    let chars = literal.chars().collect::<Vec<_>>();
+    let negated = match negation {
+        Negation::Edit => edit(&chars, ctx),
+        Negation::CaseFlip => case_flip(&chars, ctx),
+        Negation::Truncate if !chars.is_empty() => {
+            let keep = ctx.below(chars.len());
+            chars[..keep].iter().collect::<String>().into_bytes()
+        }
+        Negation::Double => literal.repeat(2).into_bytes(),
+        Negation::Invalid => {
+            let position = ctx.range(0, chars.len() + 1);
+            let mut negated = chars[..position].iter().collect::<String>().into_bytes();
+            negated.extend_from_slice(INVALID[ctx.below(INVALID.len())]);
+            negated.extend(chars[position..].iter().collect::<String>().into_bytes());
+            negated
+        }
+        Negation::Random => {
+            let len = ctx.range(0, STRING_MAX);
+            (0..len)
+                .map(|_| ctx.alphanumeric())
+                .collect::<String>()
+                .into_bytes()
+        }
+        Negation::Truncate => vec![],
+    };
+
+    if negated != literal.as_bytes() {
+        return negated;
+    }
+
+    // the strategy can't change every literal, such as doubling an empty one
+    // or flipping the case of one without letters, so fall back to appending
+    let mut negated = negated;
+    negated.push(if literal.ends_with('0') { b'1' } else { b'0' });
+    negated
+}
+
+/// edit inserts, deletes, substitutes or transposes a single character
+fn edit(chars: &[char], ctx: &mut Context) -> Vec<u8> {
+    let mut chars = chars.to_vec();
+    let operations = if chars.len() > 1 {
+        4
+    } else {
+        1 + 2 * chars.len()
+    };
+    match ctx.below(operations) {
+        0 => {
+            let position = ctx.range(0, chars.len() + 1);
+            let c = ctx.alphanumeric();
+            chars.insert(position, c);
+        }
+        1 => {
+            let position = ctx.below(chars.len());
+            chars.remove(position);
+        }
+        2 => {
+            let position = ctx.below(chars.len());
+            let mut c = ctx.alphanumeric();
+            if c == chars[position] {
+                c = if c == '0' { '1' } else { '0' };
+            }
+            chars[position] = c;
+        }
+        _ => {
+            let position = ctx.below(chars.len() - 1);
+            chars.swap(position, position + 1);
+        }
+    }
+
+    chars.into_iter().collect::<String>().into_bytes()
+}
+
+/// case_flip flips the case of one of the letters that have one
+fn case_flip(chars: &[char], ctx: &mut Context) -> Vec<u8> {
+    let letters = (0..chars.len())
+        .filter(|i| chars[*i].is_lowercase() || chars[*i].is_uppercase())
+        .collect::<Vec<_>>();
+    if letters.is_empty() {
+        return chars.iter().collect::<String>().into_bytes();
+    }
+
+    let flip = letters[ctx.below(letters.len())];
+    let mut negated = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if i != flip {
+            negated.push(*c);
+        } else if c.is_lowercase() {
+            negated.extend(c.to_uppercase());
+        } else {
+            negated.extend(c.to_lowercase());
+        }
+    }
+
+    negated.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn negations(negation: Negation, literal: &str) -> Vec<Vec<u8>> {
+        let mut ctx = Context::from_rng(SmallRng::seed_from_u64(1)).negations(&[negation]);
+        (0..50).map(|_| negate_literal(literal, &mut ctx)).collect()
+    }
+
+    #[test]
+    fn edits_are_near_misses() {
+        for negated in negations(Negation::Edit, "return") {
+            assert_ne!(negated, b"return".to_vec());
+            assert!((5..=7).contains(&negated.len()));
+        }
+        let transposed = negations(Negation::Edit, "return").into_iter().any(|n| {
+            let mut sorted = n.clone();
+            sorted.sort();
+            n.len() == 6 && sorted == b"enrrtu".to_vec()
+        });
+        assert!(transposed);
+    }
+
+    #[test]
+    fn case_flips_one_letter() {
+        for negated in negations(Negation::CaseFlip, "return") {
+            let negated = String::from_utf8(negated).unwrap();
+            assert_eq!(negated.to_lowercase(), "return");
+            assert_eq!(negated.chars().filter(|c| c.is_uppercase()).count(), 1);
+        }
+    }
+
+    #[test]
+    fn truncates_and_doubles() {
+        for negated in negations(Negation::Truncate, "return") {
+            assert!(b"return".starts_with(&negated) && negated.len() < 6);
+        }
+        assert_eq!(negations(Negation::Double, "if")[0], b"ifif".to_vec());
+    }
+
+    #[test]
+    fn inserts_invalid_bytes() {
+        for negated in negations(Negation::Invalid, "return") {
+            assert!(negated.contains(&0) || String::from_utf8(negated).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_negations() {
+        assert_eq!("case-flip".parse(), Ok(Negation::CaseFlip));
+        assert!("flip".parse::<Negation>().is_err());
+    }
+
+    #[test]
+    fn always_differs() {
+        for negation in Negation::ALL {
+            for literal in &["", "0", "1", "+", "ab"] {
+                for negated in negations(*negation, literal) {
+                    assert_ne!(negated, literal.as_bytes().to_vec(), "{:?}", negation);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use super::*;
+
+/// Node is a view of the concrete type behind a Generator trait object. It
+/// allows code that needs to understand the structure of a grammar, such as
+/// finding the smallest derivation of a rule, to walk a tree of Generators.
+/// Generators defined outside of synfuzz are represented as Other
+#[derive(Debug, Clone, Copy)]
+pub enum Node<'a> {
+    CharLiteral(&'a CharLiteral),
+    StringLiteral(&'a StringLiteral),
+    ByteLiteral(&'a ByteLiteral),
+    CharRange(&'a CharRange),
+    Any(&'a Any),
+    Dictionary(&'a Dictionary),
+    Mix(&'a Mix),
+    Choice(&'a Choice),
+    WeightedChoice(&'a WeightedChoice),
+    Many(&'a Many),
+    Many1(&'a Many1),
+    Optional(&'a Optional),
+    Rule(&'a Rule),
+    Sequence(&'a Sequence),
+    RepeatN(&'a RepeatN),
+    Range(&'a Range),
+    JoinWith(&'a JoinWith),
+    SepBy(&'a SepBy),
+    SepBy1(&'a SepBy1),
+    Not(&'a Not),
+    TargetSize(&'a TargetSize),
+    Other,
+}
+
+impl<'a> Node<'a> {
+    /// kind returns the name of the type of the Generator
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Node::CharLiteral(_) => "CharLiteral",
+            Node::StringLiteral(_) => "StringLiteral",
+            Node::ByteLiteral(_) => "ByteLiteral",
+            Node::CharRange(_) => "CharRange",
+            Node::Any(_) => "Any",
+            Node::Dictionary(_) => "Dictionary",
+            Node::Mix(_) => "Mix",
+            Node::Choice(_) => "Choice",
+            Node::WeightedChoice(_) => "WeightedChoice",
+            Node::Many(_) => "Many",
+            Node::Many1(_) => "Many1",
+            Node::Optional(_) => "Optional",
+            Node::Rule(_) => "Rule",
+            Node::Sequence(_) => "Sequence",
+            Node::RepeatN(_) => "RepeatN",
+            Node::Range(_) => "Range",
+            Node::JoinWith(_) => "JoinWith",
+            Node::SepBy(_) => "SepBy",
+            Node::SepBy1(_) => "SepBy1",
+            Node::Not(_) => "Not",
+            Node::TargetSize(_) => "TargetSize",
+            Node::Other => "Other",
+        }
+    }
+
+    /// children returns the Generators the Generator is built from, in the
+    /// order they are generated. Rules have no children since the rule they
+    /// name is only looked up when generating
+    pub fn children(&self) -> Vec<&'a dyn Generator> {
+        fn all(generators: &[Box<dyn Generator>]) -> Vec<&dyn Generator> {
+            generators.iter().map(|g| &**g).collect()
+        }
+
+        match *self {
+            Node::CharLiteral(_)
+            | Node::StringLiteral(_)
+            | Node::ByteLiteral(_)
+            | Node::CharRange(_)
+            | Node::Any(_)
+            | Node::Dictionary(_)
+            | Node::Rule(_)
+            | Node::Other => vec![],
+            Node::Mix(g) => vec![&*g.generator, &*g.dictionary],
+            Node::Choice(g) => all(&g.choices),
+            Node::WeightedChoice(g) => all(&g.choices),
+            Node::Many(g) => vec![&*g.generator],
+            Node::Many1(g) => vec![&*g.generator],
+            Node::Optional(g) => vec![&*g.generator],
+            Node::Sequence(g) => all(&g.generators),
+            Node::RepeatN(g) => vec![&*g.generator],
+            Node::Range(g) => vec![&*g.generator],
+            Node::JoinWith(g) => {
+                let mut children = all(&g.generators);
+                children.push(&*g.delimiter);
+                children
+            }
+            Node::SepBy(g) => vec![&*g.generator, &*g.separator],
+            Node::SepBy1(g) => vec![&*g.generator, &*g.separator],
+            Node::Not(g) => vec![&*g.generator],
+            Node::TargetSize(g) => vec![&*g.generator],
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use super::{RuleSet, Rules, Tree, UnsupportedGenerator};
+
+impl Tree {
+    /// optimize removes redundant structure from the Tree without changing
+    /// the distribution of the values it generates: nested Sequences are
+    /// flattened, as are JoinWiths nested in a JoinWith with the same
+    /// delimiter, Sequences, JoinWiths and Choices of a single Generator are
+    /// replaced by it, a Not of a Not by what it negates, and a Choice of
+    /// chars and char ranges that are all the same size and together cover a
+    /// contiguous range by a single CharRange. The decisions made to
+    /// generate a value can differ from those of the original Tree
+    pub fn optimize(self) -> Tree {
+        let optimized = match self {
+            Tree::Mix {
+                generator,
+                dictionary,
+                probability,
+            } => Tree::Mix {
+                generator: Box::new(generator.optimize()),
+                dictionary: Box::new(dictionary.optimize()),
+                probability,
+            },
+            Tree::Choice(choices) => Tree::Choice(optimize_all(choices)),
+            Tree::WeightedChoice { choices, weights } => Tree::WeightedChoice {
+                choices: optimize_all(choices),
+                weights,
+            },
+            Tree::Many(g) => Tree::Many(Box::new(g.optimize())),
+            Tree::Many1(g) => Tree::Many1(Box::new(g.optimize())),
+            Tree::Optional(g) => Tree::Optional(Box::new(g.optimize())),
+            Tree::Sequence(generators) => Tree::Sequence(optimize_all(generators)),
+            Tree::RepeatN { n, generator } => Tree::RepeatN {
+                n,
+                generator: Box::new(generator.optimize()),
+            },
+            Tree::Range { n, m, generator } => Tree::Range {
+                n,
+                m,
+                generator: Box::new(generator.optimize()),
+            },
+            Tree::JoinWith {
+                generators,
+                delimiter,
+            } => Tree::JoinWith {
+                generators: optimize_all(generators),
+                delimiter: Box::new(delimiter.optimize()),
+            },
+            Tree::SepBy {
+                generator,
+                separator,
+            } => Tree::SepBy {
+                generator: Box::new(generator.optimize()),
+                separator: Box::new(separator.optimize()),
+            },
+            Tree::SepBy1 {
+                generator,
+                separator,
+            } => Tree::SepBy1 {
+                generator: Box::new(generator.optimize()),
+                separator: Box::new(separator.optimize()),
+            },
+            Tree::Not(g) => Tree::Not(Box::new(g.optimize())),
+            Tree::TargetSize {
+                min,
+                max,
+                generator,
+            } => Tree::TargetSize {
+                min,
+                max,
+                generator: Box::new(generator.optimize()),
+            },
+            tree => tree,
+        };
+
+        simplify(optimized)
+    }
+
+    /// inline replaces the Rules that are in trivial by their Trees
+    fn inline(self, trivial: &BTreeMap<String, Tree>) -> Tree {
+        let inline_all = |trees: Vec<Tree>| {
+            trees
+                .into_iter()
+                .map(|t| t.inline(trivial))
+                .collect::<Vec<_>>()
+        };
+        let inline_box = |tree: Box<Tree>| Box::new(tree.inline(trivial));
+
+        match self {
+            Tree::Rule(name) => match trivial.get(&name) {
+                Some(tree) => tree.clone(),
+                None => Tree::Rule(name),
+            },
+            Tree::Mix {
+                generator,
+                dictionary,
+                probability,
+            } => Tree::Mix {
+                generator: inline_box(generator),
+                dictionary: inline_box(dictionary),
+                probability,
+            },
+            Tree::Choice(choices) => Tree::Choice(inline_all(choices)),
+            Tree::WeightedChoice { choices, weights } => Tree::WeightedChoice {
+                choices: inline_all(choices),
+                weights,
+            },
+            Tree::Many(g) => Tree::Many(inline_box(g)),
+            Tree::Many1(g) => Tree::Many1(inline_box(g)),
+            Tree::Optional(g) => Tree::Optional(inline_box(g)),
+            Tree::Sequence(generators) => Tree::Sequence(inline_all(generators)),
+            Tree::RepeatN { n, generator } => Tree::RepeatN {
+                n,
+                generator: inline_box(generator),
+            },
+            Tree::Range { n, m, generator } => Tree::Range {
+                n,
+                m,
+                generator: inline_box(generator),
+            },
+            Tree::JoinWith {
+                generators,
+                delimiter,
+            } => Tree::JoinWith {
+                generators: inline_all(generators),
+                delimiter: inline_box(delimiter),
+            },
+            Tree::SepBy {
+                generator,
+                separator,
+            } => Tree::SepBy {
+                generator: inline_box(generator),
+                separator: inline_box(separator),
+            },
+            Tree::SepBy1 {
+                generator,
+                separator,
+            } => Tree::SepBy1 {
+                generator: inline_box(generator),
+                separator: inline_box(separator),
+            },
+            Tree::Not(g) => Tree::Not(inline_box(g)),
+            Tree::TargetSize {
+                min,
+                max,
+                generator,
+            } => Tree::TargetSize {
+                min,
+                max,
+                generator: inline_box(generator),
+            },
+            tree => tree,
+        }
+    }
+}
+
+fn optimize_all(trees: Vec<Tree>) -> Vec<Tree> {
+    trees.into_iter().map(Tree::optimize).collect()
+}
+
+/// simplify rewrites a Tree whose children have already been optimized
+fn simplify(tree: Tree) -> Tree {
+    match tree {
+        Tree::Sequence(generators) => {
+            let mut flattened = vec![];
+            for generator in generators {
+                match generator {
+                    Tree::Sequence(nested) => flattened.extend(nested),
+                    generator => flattened.push(generator),
+                }
+            }
+            if flattened.len() == 1 {
+                flattened.pop().unwrap()
+            } else {
+                Tree::Sequence(flattened)
+            }
+        }
+        Tree::JoinWith {
+            generators,
+            delimiter,
+        } => {
+            // the delimiters of a nested JoinWith are generated in the same
+            // order as the ones that would join its Generators in the outer
+            // one, which also holds for their negations
+            let mut flattened = vec![];
+            for generator in generators {
+                match generator {
+                    Tree::JoinWith {
+                        generators: ref nested,
+                        delimiter: ref nested_delimiter,
+                    } if !nested.is_empty() && nested_delimiter == &delimiter => {
+                        flattened.extend(nested.iter().cloned())
+                    }
+                    generator => flattened.push(generator),
+                }
+            }
+            if flattened.len() == 1 {
+                flattened.pop().unwrap()
+            } else {
+                Tree::JoinWith {
+                    generators: flattened,
+                    delimiter,
+                }
+            }
+        }
+        Tree::Choice(mut choices) => {
+            if choices.len() == 1 {
+                return choices.pop().unwrap();
+            }
+            match merge_chars(&choices) {
+                Some((n, m)) => Tree::CharRange { n, m },
+                None => Tree::Choice(choices),
+            }
+        }
+        Tree::WeightedChoice {
+            mut choices,
+            weights,
+        } => {
+            if choices.len() == 1 {
+                return choices.pop().unwrap();
+            }
+            Tree::WeightedChoice { choices, weights }
+        }
+        Tree::Not(g) => match *g {
+            Tree::Not(negated) => *negated,
+            g => Tree::Not(Box::new(g)),
+        },
+        tree => tree,
+    }
+}
+
+/// merge_chars returns the range of chars covered by choices if every
+/// choice is a char or a range of chars, they are all the same size and
+/// they cover a contiguous range without overlapping. Picking a char from
+/// the whole range is then as likely to produce each char as picking one of
+/// the choices and then a char from it
+fn merge_chars(choices: &[Tree]) -> Option<(char, char)> {
+    if choices.is_empty() {
+        return None;
+    }
+
+    let mut ranges = choices
+        .iter()
+        .map(|choice| match *choice {
+            Tree::CharLiteral(ch) => Some((ch as u32, ch as u32)),
+            Tree::CharRange { n, m } if n <= m => Some((n as u32, m as u32)),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    ranges.sort();
+
+    let size = ranges[0].1 - ranges[0].0;
+    for pair in ranges.windows(2) {
+        if pair[1].1 - pair[1].0 != size || pair[1].0 != pair[0].1 + 1 {
+            return None;
+        }
+    }
+
+    // ranges that span the surrogates aren't merged since CharRange doesn't
+    // pick the chars around them uniformly
+    let (n, m) = (ranges[0].0, ranges[ranges.len() - 1].1);
+    if n <= 0xdfff && m >= 0xd800 {
+        return None;
+    }
+    Some((::std::char::from_u32(n)?, ::std::char::from_u32(m)?))
+}
+
+impl RuleSet {
+    /// optimize optimizes the Tree of every rule and inlines the rules that
+    /// are trivial: rules that are a literal, a char range or Any, and rules
+    /// that are only another rule, which are replaced by the rule they name.
+    /// The rules that were inlined are kept so they can still be generated
+    /// on their own, but no longer appear in the Derivations of the rules
+    /// that referenced them
+    pub fn optimize(self) -> RuleSet {
+        let rules = self
+            .rules
+            .into_iter()
+            .map(|(name, tree)| (name, tree.optimize()))
+            .collect::<BTreeMap<_, _>>();
+
+        let trivial = rules
+            .keys()
+            .filter_map(|name| {
+                let tree = resolve(&rules, name, &mut HashSet::new())?;
+                Some((name.clone(), tree))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let rules = rules
+            .into_iter()
+            .map(|(name, tree)| (name, tree.inline(&trivial).optimize()))
+            .collect();
+        RuleSet { rules }
+    }
+}
+
+/// resolve returns what a reference to the rule name can be replaced with if
+/// the rule is trivial: its Tree if it is a literal, a char range or Any,
+/// or what the rule it is an alias of resolves to
+fn resolve(rules: &BTreeMap<String, Tree>, name: &str, seen: &mut HashSet<String>) -> Option<Tree> {
+    if !seen.insert(name.to_owned()) {
+        return None;
+    }
+
+    match *rules.get(name)? {
+        ref tree @ Tree::CharLiteral(_)
+        | ref tree @ Tree::StringLiteral(_)
+        | ref tree @ Tree::ByteLiteral(_)
+        | ref tree @ Tree::CharRange { .. }
+        | ref tree @ Tree::Any => Some(tree.clone()),
+        Tree::Rule(ref target) => {
+            // references to missing rules and cycles of aliases are kept
+            if !rules.contains_key(target) || seen.contains(target) {
+                return None;
+            }
+            Some(resolve(rules, target, seen).unwrap_or_else(|| Tree::Rule(target.clone())))
+        }
+        _ => None,
+    }
+}
+
+/// optimize_rules optimizes rules in place with RuleSet::optimize. The
+/// Generators that reference rules keep working since the optimized rules
+/// are registered under the same names
+pub fn optimize_rules(rules: &Arc<RwLock<Rules>>) -> Result<(), UnsupportedGenerator> {
+    RuleSet::from_rules(rules)?.optimize().register(rules);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+    use {
+        byte, ch, char_range, choice, join_with, many1, not, optional, register_rule, rule, seq,
+        string, Context, Generator,
+    };
+
+    fn optimized(generator: impl Generator) -> String {
+        Tree::from_generator(&generator)
+            .unwrap()
+            .optimize()
+            .to_string()
+    }
+
+    #[test]
+    fn flattens_and_unwraps() {
+        assert_eq!(
+            optimized(seq!(ch('a'), seq!(ch('b'), seq!()), seq!(seq!(ch('c'))))),
+            "'a' 'b' 'c'"
+        );
+        assert_eq!(
+            optimized(join_with!(
+                byte(0x20),
+                join_with!(byte(0x20), string("a")),
+                join_with!(byte(0x20), string("b"), string("c")),
+                join_with!(byte(0x2c), string("d"), string("e"))
+            )),
+            "join_with(0x20; \"a\", \"b\", \"c\", join_with(0x2c; \"d\", \"e\"))"
+        );
+        assert_eq!(
+            optimized(choice!(optional(not(not(choice!(string("x"))))))),
+            "\"x\"?"
+        );
+    }
+
+    #[test]
+    fn merges_chars_into_ranges() {
+        let digits = choice!(
+            ch('3'),
+            ch('1'),
+            ch('2'),
+            ch('0'),
+            ch('4'),
+            ch('5'),
+            ch('6'),
+            ch('7'),
+            ch('8'),
+            ch('9')
+        );
+        assert_eq!(optimized(digits), "'0'..'9'");
+        assert_eq!(
+            optimized(choice!(char_range('a', 'm'), char_range('n', 'z'))),
+            "'a'..'z'"
+        );
+
+        // merging would change how likely each char is or add chars
+        assert_eq!(
+            optimized(choice!(char_range('a', 'z'), ch('_'))),
+            "'a'..'z' | '_'"
+        );
+        assert_eq!(optimized(choice!(ch('a'), ch('c'))), "'a' | 'c'");
+        assert_eq!(optimized(choice!(ch('a'), ch('a'))), "'a' | 'a'");
+    }
+
+    #[test]
+    fn inlines_trivial_rules() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(
+            &rules,
+            "list",
+            join_with!(
+                byte(0x20),
+                rule("item", rules.clone()),
+                rule("comma", rules.clone()),
+                rule("list", rules.clone())
+            ),
+        );
+        register_rule(&rules, "item", rule("number", rules.clone()));
+        register_rule(&rules, "number", many1(rule("digit", rules.clone())));
+        register_rule(&rules, "digit", rule("range", rules.clone()));
+        register_rule(&rules, "range", char_range('0', '9'));
+        register_rule(&rules, "comma", string(","));
+        register_rule(&rules, "loop", rule("loop", rules.clone()));
+
+        optimize_rules(&rules).unwrap();
+        assert_eq!(
+            RuleSet::from_rules(&rules).unwrap().to_string(),
+            "comma = \",\" ;\n\
+             digit = '0'..'9' ;\n\
+             item = number ;\n\
+             list = join_with(0x20; number, \",\", list) ;\n\
+             loop = loop ;\n\
+             number = '0'..'9'+{..4} ;\n\
+             range = '0'..'9' ;\n"
+        );
+    }
+
+    #[test]
+    fn generates_the_same_values() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(
+            &rules,
+            "value",
+            choice!(
+                seq!(
+                    rule("open", rules.clone()),
+                    rule("value", rules.clone()),
+                    ch(']')
+                ),
+                seq!(seq!(ch('x')), optional(not(not(string("y")))))
+            ),
+        );
+        register_rule(&rules, "open", ch('['));
+
+        let generate = |rules: Arc<RwLock<Rules>>| {
+            let mut ctx = Context::from_rng(SmallRng::seed_from_u64(5));
+            (0..50)
+                .map(|_| rule("value", rules.clone()).generate_with(&mut ctx))
+                .collect::<Vec<_>>()
+        };
+        let original = generate(rules.clone());
+        let optimized = RuleSet::from_rules(&rules).unwrap().optimize().to_rules();
+        assert_eq!(generate(optimized), original);
+    }
+}
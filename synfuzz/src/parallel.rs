@@ -0,0 +1,86 @@
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use std::thread;
+
+use super::{Context, Generator};
+
+/// generate_parallel generates count values of generator split across
+/// threads worker threads. Each worker has its own random number generator
+/// seeded from seed and its index, and generates every threads-th value, so
+/// the same seed and number of threads always produce the same values in the
+/// same order
+pub fn generate_parallel(
+    generator: &dyn Generator,
+    count: usize,
+    threads: usize,
+    seed: u64,
+) -> Vec<Vec<u8>> {
+    assert!(threads > 0, "at least one thread is required");
+    let workers = (0..threads.min(count)).collect::<Vec<_>>();
+
+    let mut batches = thread::scope(|scope| {
+        let handles = workers
+            .iter()
+            .map(|&worker| {
+                scope.spawn(move || {
+                    let mut ctx =
+                        Context::from_rng(SmallRng::seed_from_u64(worker_seed(seed, worker)));
+                    (worker..count)
+                        .step_by(threads)
+                        .map(|_| generator.generate_with(&mut ctx))
+                        .collect::<Vec<_>>()
+                        .into_iter()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    // interleave the batches back into the order of their indices
+    (0..count)
+        .map(|i| batches[i % threads].next().unwrap())
+        .collect()
+}
+
+/// worker_seed derives the seed of a worker from the seed of a run with
+/// SplitMix64 so that workers with adjacent indices get unrelated sequences
+pub(crate) fn worker_seed(seed: u64, worker: usize) -> u64 {
+    let mut z = seed.wrapping_add((worker as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+    use {byte, choice, many1, register_rule, rule, Rules};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn grammars_are_thread_safe() {
+        assert_send_sync::<Box<dyn Generator>>();
+        assert_send_sync::<Arc<RwLock<Rules>>>();
+    }
+
+    #[test]
+    fn parallel_generation_is_deterministic() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(&rules, "bits", many1(choice!(byte(0x30), byte(0x31))));
+        let bits = rule("bits", rules);
+
+        let values = generate_parallel(&bits, 100, 4, 7);
+        assert_eq!(values.len(), 100);
+        assert!(values.iter().all(|v| !v.is_empty()));
+        assert_eq!(values, generate_parallel(&bits, 100, 4, 7));
+        assert_ne!(values, generate_parallel(&bits, 100, 4, 8));
+        assert_eq!(generate_parallel(&bits, 3, 8, 7).len(), 3);
+    }
+}
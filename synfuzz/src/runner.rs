@@ -0,0 +1,192 @@
+use rand::rngs::SmallRng;
+use rand::{FromEntropy, Rng, SeedableRng};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use super::{Context, Derivation, Execution, Executor, Generator, Rule, Rules};
+
+/// Runner repeatedly generates inputs from a start rule, runs a target on
+/// them with an Executor and saves the inputs that make the target fail.
+/// Every input is generated from its own seed, so a failure can be
+/// reproduced with Runner::generate from the seed saved next to it
+#[derive(Debug)]
+pub struct Runner {
+    start: Rule,
+    executor: Executor,
+    output: PathBuf,
+    rng: SmallRng,
+    negate: f64,
+}
+
+/// Failure is an input that made the target fail
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub seed: u64,
+    pub negated: bool,
+    pub input: Vec<u8>,
+    pub derivation: Derivation,
+    pub execution: Execution,
+    pub path: PathBuf,
+}
+
+impl Runner {
+    /// new creates a Runner that generates from the rule named start and
+    /// saves failing inputs to the output directory
+    pub fn new<S, P>(rules: Arc<RwLock<Rules>>, start: S, executor: Executor, output: P) -> Runner
+    where
+        S: Into<String>,
+        P: Into<PathBuf>,
+    {
+        Runner {
+            start: Rule {
+                rules,
+                name: start.into(),
+            },
+            executor,
+            output: output.into(),
+            rng: SmallRng::from_entropy(),
+            negate: 0.0,
+        }
+    }
+
+    /// seed makes the sequence of generated inputs reproducible
+    pub fn seed(mut self, seed: u64) -> Runner {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// negate sets the probability that an input is generated from the
+    /// negation of the start rule
+    pub fn negate(mut self, probability: f64) -> Runner {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "probability must be between 0 and 1"
+        );
+        self.negate = probability;
+        self
+    }
+
+    /// generate returns the input, and its Derivation, generated from seed
+    pub fn generate(&self, seed: u64, negated: bool) -> (Vec<u8>, Derivation) {
+        generate_seeded(&self.start, seed, negated)
+    }
+
+    /// run_once generates a single input and runs the target on it,
+    /// returning the Failure if the target failed
+    pub fn run_once(&mut self) -> io::Result<Option<Failure>> {
+        let seed = self.rng.gen();
+        let negated = self.rng.gen_bool(self.negate);
+        let (input, derivation) = self.generate(seed, negated);
+        let execution = self.executor.execute(&input)?;
+        if !execution.outcome.is_failure() {
+            return Ok(None);
+        }
+
+        let path = self.output.join(format!(
+            "{}-{:016x}",
+            execution.outcome.kind(),
+            hash(&input)
+        ));
+        let failure = Failure {
+            seed,
+            negated,
+            input,
+            derivation,
+            execution,
+            path,
+        };
+        self.save(&failure)?;
+        Ok(Some(failure))
+    }
+
+    /// run generates and runs iterations inputs, returning the Failures
+    pub fn run(&mut self, iterations: usize) -> io::Result<Vec<Failure>> {
+        let mut failures = vec![];
+        for _ in 0..iterations {
+            if let Some(failure) = self.run_once()? {
+                failures.push(failure);
+            }
+        }
+        Ok(failures)
+    }
+
+    /// save writes the input of failure along with a .txt file describing how
+    /// to reproduce it, unless the same input has already been saved
+    fn save(&self, failure: &Failure) -> io::Result<()> {
+        if failure.path.exists() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.output)?;
+        fs::write(&failure.path, &failure.input)?;
+        let report = format!(
+            "rule: {}\nseed: {}\nnegated: {}\noutcome: {:?}\n\nderivation:\n{}\nstderr:\n{}",
+            self.start.name,
+            failure.seed,
+            failure.negated,
+            failure.execution.outcome,
+            failure.derivation,
+            String::from_utf8_lossy(&failure.execution.stderr)
+        );
+        fs::write(failure.path.with_extension("txt"), report)
+    }
+}
+
+/// generate_seeded generates from start, or its negation, with a random
+/// number generator seeded with seed and records the Derivation
+pub(crate) fn generate_seeded(start: &Rule, seed: u64, negated: bool) -> (Vec<u8>, Derivation) {
+    let mut ctx = Context::from_rng(SmallRng::seed_from_u64(seed)).recording();
+    let input = if negated {
+        start.negate_with(&mut ctx)
+    } else {
+        start.generate_with(&mut ctx)
+    };
+    let derivation = ctx
+        .take_derivations()
+        .pop()
+        .expect("start rule was not recorded");
+    (input, derivation)
+}
+
+/// hash returns the FNV-1a hash used to name saved inputs, which unlike
+/// DefaultHasher is the same across Rust releases
+pub fn hash(input: &[u8]) -> u64 {
+    input.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::env;
+    use std::process;
+    use {byte, choice, register_rule, Outcome};
+
+    #[test]
+    fn saves_crashing_inputs() {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(&rules, "bit", choice!(byte(0x30), byte(0x31)));
+        let executor =
+            Executor::new("/bin/sh").args(vec!["-c", "if grep -q 1; then kill -SEGV $$; fi"]);
+        let output = env::temp_dir().join(format!("synfuzz-runner-{}", process::id()));
+        let mut runner = Runner::new(rules, "bit", executor, &output).seed(1);
+
+        let failures = runner.run(20).unwrap();
+        assert!(!failures.is_empty());
+        for failure in &failures {
+            assert_eq!(failure.input, b"1".to_vec());
+            assert_eq!(failure.execution.outcome, Outcome::Signaled(11));
+            assert!(failure.path.ends_with("crash-af63ac4c86019afc"));
+            assert_eq!(fs::read(&failure.path).unwrap(), failure.input);
+            assert_eq!(runner.generate(failure.seed, false).0, failure.input);
+        }
+        let report = fs::read_to_string(failures[0].path.with_extension("txt")).unwrap();
+        assert!(report.contains(&format!("seed: {}", failures[0].seed)));
+        fs::remove_dir_all(&output).unwrap();
+    }
+}
@@ -0,0 +1,461 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use super::{Context, Derivation, Generator, Rules};
+
+/// Measurements below this are counted in a bucket per integer
+const LINEAR_BUCKETS: usize = 16;
+/// Measurements from LINEAR_BUCKETS up are counted in this many buckets per
+/// power of two
+const BUCKETS_PER_OCTAVE: usize = 4;
+/// The number of buckets of a Histogram, the last of which counts everything
+/// from 2^64 up
+const BUCKETS: usize = LINEAR_BUCKETS + 60 * BUCKETS_PER_OCTAVE;
+
+/// Statistics collects measurements of generated values to help tune the
+/// weights and limits of a grammar: the distribution of output lengths,
+/// derivation depths and generation times, how many times each rule was
+/// expanded and how many values were negated. It can be written as JSON with
+/// to_json or as a human readable summary with Display
+///
+/// Measurements are kept in fixed size histograms, so a Statistics doesn't
+/// grow with the number of values recorded. The minimum, maximum and mean
+/// are exact while the percentiles are estimated from the histograms, which
+/// is exact for integers below 16 and within about 10% above
+///
+/// ```
+/// # #[macro_use] extern crate synfuzz;
+/// # use std::collections::HashMap;
+/// # use std::sync::{Arc, RwLock};
+/// # use synfuzz::*;
+/// # fn main() {
+/// let rules = Arc::new(RwLock::new(HashMap::new()));
+/// register_rule(&rules, "bits", many1(rule("bit", rules.clone())));
+/// register_rule(&rules, "bit", choice!(byte(0x30), byte(0x31)));
+///
+/// let stats = Statistics::collect(&rule("bits", rules.clone()), 100, 0.0, 1).rules(&rules);
+/// println!("{}", stats);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+    negated: usize,
+    lengths: Histogram,
+    depths: Histogram,
+    times: Histogram,
+    expansions: BTreeMap<String, usize>,
+}
+
+/// Histogram is a streaming summary of a set of measurements: their count,
+/// sum, minimum and maximum, and how many fell in each bucket
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    /// Allocated with BUCKETS entries by the first measurement
+    buckets: Vec<u64>,
+}
+
+impl Histogram {
+    fn record(&mut self, value: f64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if self.count == 0 || value > self.max {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value;
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; BUCKETS];
+        }
+        self.buckets[bucket(value)] += 1;
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+    }
+
+    fn distribution(&self) -> Distribution {
+        if self.count == 0 {
+            return Distribution::default();
+        }
+
+        let percentile = |p: f64| {
+            let rank = ((self.count - 1) as f64 * p).round() as u64;
+            let mut seen = 0;
+            for (i, count) in self.buckets.iter().enumerate() {
+                seen += count;
+                if seen > rank {
+                    return bucket_value(i).max(self.min).min(self.max);
+                }
+            }
+            self.max
+        };
+        Distribution {
+            min: self.min,
+            max: self.max,
+            mean: self.sum / self.count as f64,
+            median: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+/// bucket returns the index of the bucket counting value
+fn bucket(value: f64) -> usize {
+    if value < LINEAR_BUCKETS as f64 {
+        return value.max(0.0) as usize;
+    }
+    let octaves = value.log2() - (LINEAR_BUCKETS as f64).log2();
+    (LINEAR_BUCKETS + (octaves * BUCKETS_PER_OCTAVE as f64) as usize).min(BUCKETS - 1)
+}
+
+/// bucket_value returns the value that represents the measurements counted
+/// in bucket i, which is the middle of its range
+fn bucket_value(i: usize) -> f64 {
+    if i < LINEAR_BUCKETS {
+        return i as f64;
+    }
+    let bound = |i: usize| {
+        let octaves = (i - LINEAR_BUCKETS) as f64 / BUCKETS_PER_OCTAVE as f64;
+        LINEAR_BUCKETS as f64 * octaves.exp2()
+    };
+    (bound(i) + bound(i + 1)) / 2.0
+}
+
+/// Distribution summarizes a set of measurements
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Distribution {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl Distribution {
+    fn write_json(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "{{\"min\":{},\"max\":{},\"mean\":{},\"median\":{},\"p90\":{},\"p99\":{}}}",
+            self.min, self.max, self.mean, self.median, self.p90, self.p99
+        );
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "min {:.0}, median {:.0}, mean {:.1}, p90 {:.0}, p99 {:.0}, max {:.0}",
+            self.min, self.median, self.mean, self.p90, self.p99, self.max
+        )
+    }
+}
+
+impl Statistics {
+    /// new creates an empty Statistics
+    pub fn new() -> Statistics {
+        Statistics::default()
+    }
+
+    /// collect generates count values of generator with a random number
+    /// generator seeded with seed, negating each with probability negate,
+    /// and returns their Statistics
+    pub fn collect(generator: &dyn Generator, count: usize, negate: f64, seed: u64) -> Statistics {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut stats = Statistics::new();
+        for _ in 0..count {
+            let negated = rng.gen_bool(negate);
+            let mut ctx = Context::from_rng(&mut rng).recording();
+            stats.sample(generator, &mut ctx, negated);
+        }
+        stats
+    }
+
+    /// rules includes every rule of the grammar in the expansion counts, so
+    /// that rules that were never expanded are reported with a count of 0
+    pub fn rules(mut self, rules: &Arc<RwLock<Rules>>) -> Statistics {
+        for name in rules.read().unwrap().keys() {
+            self.expansions.entry(name.clone()).or_insert(0);
+        }
+        self
+    }
+
+    /// sample generates a value of generator, or its negation, with ctx and
+    /// records it. ctx must be recording for rule expansions and depths to
+    /// be measured
+    pub fn sample(
+        &mut self,
+        generator: &dyn Generator,
+        ctx: &mut Context,
+        negated: bool,
+    ) -> Vec<u8> {
+        let started = Instant::now();
+        let value = if negated {
+            generator.negate_with(ctx)
+        } else {
+            generator.generate_with(ctx)
+        };
+        let elapsed = started.elapsed();
+        self.record(&value, &ctx.take_derivations(), negated, elapsed);
+        value
+    }
+
+    /// record adds a value, the Derivations of the rules expanded at its top
+    /// level and how long it took to generate
+    pub fn record(
+        &mut self,
+        value: &[u8],
+        derivations: &[Derivation],
+        negated: bool,
+        elapsed: Duration,
+    ) {
+        if negated {
+            self.negated += 1;
+        }
+        self.lengths.record(value.len() as f64);
+        self.depths
+            .record(derivations.iter().map(|d| d.depth()).max().unwrap_or(0) as f64);
+        self.times.record(elapsed.as_secs_f64() * 1e6);
+        for node in derivations.iter().flat_map(|d| d.iter()) {
+            *self.expansions.entry(node.rule.clone()).or_insert(0) += 1;
+        }
+    }
+
+    /// merge adds the values recorded by other
+    pub fn merge(&mut self, other: Statistics) {
+        self.negated += other.negated;
+        self.lengths.merge(&other.lengths);
+        self.depths.merge(&other.depths);
+        self.times.merge(&other.times);
+        for (rule, count) in other.expansions {
+            *self.expansions.entry(rule).or_insert(0) += count;
+        }
+    }
+
+    /// samples returns the number of values recorded
+    pub fn samples(&self) -> usize {
+        self.lengths.count
+    }
+
+    /// negated returns the number of values that were negated
+    pub fn negated(&self) -> usize {
+        self.negated
+    }
+
+    /// lengths returns the distribution of the lengths of the values in bytes
+    pub fn lengths(&self) -> Distribution {
+        self.lengths.distribution()
+    }
+
+    /// depths returns the distribution of the depths of the derivations of
+    /// the values
+    pub fn depths(&self) -> Distribution {
+        self.depths.distribution()
+    }
+
+    /// times returns the distribution of the time taken to generate the
+    /// values in microseconds
+    pub fn times(&self) -> Distribution {
+        self.times.distribution()
+    }
+
+    /// expansions returns the number of times each rule was expanded
+    pub fn expansions(&self) -> &BTreeMap<String, usize> {
+        &self.expansions
+    }
+
+    /// to_json returns the Statistics as a JSON object
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"samples\":{},\"negated\":{},\"length\":",
+            self.samples(),
+            self.negated
+        );
+        self.lengths().write_json(&mut out);
+        out.push_str(",\"depth\":");
+        self.depths().write_json(&mut out);
+        out.push_str(",\"time_us\":");
+        self.times().write_json(&mut out);
+        out.push_str(",\"expansions\":{");
+        for (i, (rule, count)) in self.expansions.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_json_string(&mut out, rule);
+            let _ = write!(out, ":{}", count);
+        }
+        out.push_str("}}");
+        out
+    }
+}
+
+/// Display writes a summary of the Statistics with the expansion counts of
+/// the rules from most to least expanded
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let samples = self.samples();
+        writeln!(
+            f,
+            "samples: {} ({} negated, {:.1}%)",
+            samples,
+            self.negated,
+            if samples == 0 {
+                0.0
+            } else {
+                self.negated as f64 * 100.0 / samples as f64
+            }
+        )?;
+        writeln!(f, "length:  {}", self.lengths())?;
+        writeln!(f, "depth:   {}", self.depths())?;
+        writeln!(f, "time us: {}", self.times())?;
+
+        let mut expansions = self.expansions.iter().collect::<Vec<_>>();
+        expansions.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let width = expansions.iter().map(|(r, _)| r.len()).max().unwrap_or(0);
+        writeln!(f, "expansions:")?;
+        for (rule, count) in expansions {
+            writeln!(
+                f,
+                "  {:width$}  {:>8}  {:.2}/sample",
+                rule,
+                count,
+                if samples == 0 {
+                    0.0
+                } else {
+                    *count as f64 / samples as f64
+                },
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use {byte, choice, many1, register_rule, rule};
+
+    fn bits() -> Arc<RwLock<Rules>> {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(&rules, "bits", many1(rule("bit", rules.clone())));
+        register_rule(&rules, "bit", choice!(byte(0x30), byte(0x31)));
+        register_rule(&rules, "unused", byte(0x32));
+        rules
+    }
+
+    #[test]
+    fn collects_statistics() {
+        let rules = bits();
+        let stats = Statistics::collect(&rule("bits", rules.clone()), 100, 0.0, 1).rules(&rules);
+        assert_eq!(stats.samples(), 100);
+        assert_eq!(stats.negated(), 0);
+        assert_eq!(stats.expansions()["bits"], 100);
+        assert_eq!(stats.expansions()["unused"], 0);
+
+        let lengths = stats.lengths();
+        assert!(lengths.min >= 1.0 && lengths.max <= 5.0);
+        assert!(lengths.min <= lengths.median && lengths.median <= lengths.max);
+        assert_eq!(
+            (lengths.mean * 100.0).round(),
+            stats.expansions()["bit"] as f64
+        );
+        assert_eq!(stats.depths().max, 2.0);
+    }
+
+    #[test]
+    fn estimates_percentiles() {
+        let mut histogram = Histogram::default();
+        for value in 0..1000 {
+            histogram.record(f64::from(value));
+        }
+        let mut other = Histogram::default();
+        other.record(1e30);
+        histogram.merge(&other);
+
+        let distribution = histogram.distribution();
+        assert_eq!((distribution.min, distribution.max), (0.0, 1e30));
+        assert!((distribution.median - 500.0).abs() < 50.0);
+        assert!((distribution.p90 - 900.0).abs() < 90.0);
+        assert_eq!(histogram.buckets.len(), BUCKETS);
+
+        let mut small = Histogram::default();
+        for value in &[1.0, 2.0, 2.0, 3.0] {
+            small.record(*value);
+        }
+        assert_eq!(small.distribution().median, 2.0);
+    }
+
+    #[test]
+    fn writes_json_and_summary() {
+        let mut stats = Statistics::new();
+        let derivation = Derivation {
+            rule: String::from("a\"b"),
+            output: 0..3,
+            input: 0..0,
+            negated: true,
+            defects: vec![],
+            children: vec![],
+        };
+        stats.record(b"abc", &[derivation], true, Duration::from_micros(2));
+        assert_eq!(
+            stats.to_json(),
+            "{\"samples\":1,\"negated\":1,\
+             \"length\":{\"min\":3,\"max\":3,\"mean\":3,\"median\":3,\"p90\":3,\"p99\":3},\
+             \"depth\":{\"min\":1,\"max\":1,\"mean\":1,\"median\":1,\"p90\":1,\"p99\":1},\
+             \"time_us\":{\"min\":2,\"max\":2,\"mean\":2,\"median\":2,\"p90\":2,\"p99\":2},\
+             \"expansions\":{\"a\\\"b\":1}}"
+        );
+
+        let summary = stats.to_string();
+        assert!(summary.starts_with("samples: 1 (1 negated, 100.0%)\n"));
+        assert!(summary.contains("  a\"b         1  1.00/sample\n"));
+    }
+}
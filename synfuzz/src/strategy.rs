@@ -0,0 +1,190 @@
+use proptest::collection::vec;
+use proptest::num::u8;
+use proptest::strategy::{Map, NewTree, Strategy, ValueTree};
+use proptest::test_runner::TestRunner;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use super::{generate_from_bytes, shrink, Generator, Rule, Rules};
+
+/// The number of random decisions drawn for each value by default
+const DEFAULT_DECISIONS: usize = 512;
+
+/// GeneratorStrategy is a proptest Strategy that produces values generated by
+/// a Generator. Values are generated from a buffer of random decisions with
+/// generate_from_bytes and shrinking rewrites those decisions rather than the
+/// value, so every simplified value is still valid for the grammar.
+///
+/// ```ignore
+/// proptest! {
+///     #[test]
+///     fn parses(expr in GeneratorStrategy::rule(rules.clone(), "expr").strings()) {
+///         parse(&expr).unwrap();
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct GeneratorStrategy {
+    generator: Arc<dyn Generator>,
+    decisions: usize,
+}
+
+impl GeneratorStrategy {
+    /// new creates a GeneratorStrategy that produces values of generator
+    pub fn new<G>(generator: G) -> GeneratorStrategy
+    where
+        G: Generator + 'static,
+    {
+        GeneratorStrategy {
+            generator: Arc::new(generator),
+            decisions: DEFAULT_DECISIONS,
+        }
+    }
+
+    /// rule creates a GeneratorStrategy that produces values of the rule
+    /// named name
+    pub fn rule<S>(rules: Arc<RwLock<Rules>>, name: S) -> GeneratorStrategy
+    where
+        S: Into<String>,
+    {
+        GeneratorStrategy::new(Rule {
+            rules,
+            name: name.into(),
+        })
+    }
+
+    /// decisions sets the number of random decisions drawn for each value.
+    /// Once they are used up generation completes with the smallest
+    /// derivation, so this bounds the size of the values that are produced
+    pub fn decisions(mut self, decisions: usize) -> GeneratorStrategy {
+        self.decisions = decisions;
+        self
+    }
+
+    /// strings produces the values as Strings, replacing invalid UTF-8 with
+    /// U+FFFD
+    pub fn strings(self) -> Map<GeneratorStrategy, fn(Vec<u8>) -> String> {
+        self.prop_map(lossy_string as fn(Vec<u8>) -> String)
+    }
+}
+
+impl Strategy for GeneratorStrategy {
+    type Tree = GeneratorValueTree;
+    type Value = Vec<u8>;
+
+    fn new_tree(&self, runner: &mut TestRunner) -> NewTree<Self> {
+        let decisions = vec(u8::ANY, self.decisions).new_tree(runner)?.current();
+        Ok(GeneratorValueTree::new(self.generator.clone(), decisions))
+    }
+}
+
+/// GeneratorValueTree is the ValueTree of a GeneratorStrategy. It simplifies
+/// by trying the candidates returned by shrink for the smallest set of
+/// decisions known to fail, in order, until none of them fail
+#[derive(Debug, Clone)]
+pub struct GeneratorValueTree {
+    generator: Arc<dyn Generator>,
+    accepted: Vec<u8>,
+    candidates: Vec<Vec<u8>>,
+    next: usize,
+    current: Vec<u8>,
+}
+
+impl GeneratorValueTree {
+    fn new(generator: Arc<dyn Generator>, decisions: Vec<u8>) -> GeneratorValueTree {
+        GeneratorValueTree {
+            generator,
+            accepted: decisions.clone(),
+            candidates: vec![],
+            next: 0,
+            current: decisions,
+        }
+    }
+
+    fn try_next(&mut self) -> bool {
+        match self.candidates.get(self.next) {
+            Some(candidate) => {
+                self.current = candidate.clone();
+                self.next += 1;
+                true
+            }
+            None => {
+                self.current = self.accepted.clone();
+                false
+            }
+        }
+    }
+}
+
+impl ValueTree for GeneratorValueTree {
+    type Value = Vec<u8>;
+
+    fn current(&self) -> Vec<u8> {
+        generate_from_bytes(&*self.generator, &self.current)
+    }
+
+    fn simplify(&mut self) -> bool {
+        // the current decisions still fail so continue from them
+        if self.current != self.accepted || self.next == 0 {
+            self.accepted = self.current.clone();
+            self.candidates = shrink(&*self.generator, &self.accepted);
+            self.next = 0;
+        }
+        self.try_next()
+    }
+
+    fn complicate(&mut self) -> bool {
+        self.try_next()
+    }
+}
+
+fn lossy_string(value: Vec<u8>) -> String {
+    String::from_utf8_lossy(&value).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::test_runner::{Config, TestError};
+    use {byte, choice, many1, seq};
+
+    fn digits() -> GeneratorStrategy {
+        GeneratorStrategy::new(choice!(
+            seq!(byte(0x28), many1(byte(0x31)), byte(0x29)),
+            many1(byte(0x31))
+        ))
+    }
+
+    #[test]
+    fn values_are_valid() {
+        let mut runner = TestRunner::default();
+        runner
+            .run(&digits(), |value| {
+                let digits = value.iter().filter(|b| **b == 0x31).count();
+                assert!(digits > 0);
+                assert!(digits == value.len() || digits + 2 == value.len());
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn shrinks_to_minimal_value() {
+        let mut runner = TestRunner::new(Config {
+            failure_persistence: None,
+            ..Config::default()
+        });
+        let result = runner.run(&digits().strings(), |value| {
+            assert!(value.matches('1').count() < 3);
+            Ok(())
+        });
+
+        match result {
+            Err(TestError::Fail(_, value)) => {
+                assert_eq!(value.matches('1').count(), 3);
+                assert!(value.len() <= 5);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
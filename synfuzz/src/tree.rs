@@ -0,0 +1,815 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use super::*;
+use combinator::{MANY_MAX, SEP_BY_MAX};
+
+/// Tree is an owned, plain data representation of a tree of Generators. The
+/// Generators defined by synfuzz can be converted to a Tree and built back
+/// from one, which makes it possible to inspect, transform and, with the
+/// serde feature, serialize a grammar. Rules are referenced by name and are
+/// resolved against a set of Rules when the Tree is built
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Tree {
+    CharLiteral(char),
+    StringLiteral(String),
+    ByteLiteral(u8),
+    CharRange {
+        n: char,
+        m: char,
+    },
+    Any,
+    Dictionary(Vec<Vec<u8>>),
+    Mix {
+        generator: Box<Tree>,
+        dictionary: Box<Tree>,
+        probability: f64,
+    },
+    Choice(Vec<Tree>),
+    WeightedChoice {
+        choices: Vec<Tree>,
+        weights: Vec<usize>,
+    },
+    Many(Box<Tree>),
+    Many1(Box<Tree>),
+    Optional(Box<Tree>),
+    Rule(String),
+    Sequence(Vec<Tree>),
+    RepeatN {
+        n: usize,
+        generator: Box<Tree>,
+    },
+    Range {
+        n: usize,
+        m: usize,
+        generator: Box<Tree>,
+    },
+    JoinWith {
+        generators: Vec<Tree>,
+        delimiter: Box<Tree>,
+    },
+    SepBy {
+        generator: Box<Tree>,
+        separator: Box<Tree>,
+    },
+    SepBy1 {
+        generator: Box<Tree>,
+        separator: Box<Tree>,
+    },
+    Not(Box<Tree>),
+    TargetSize {
+        min: usize,
+        max: usize,
+        generator: Box<Tree>,
+    },
+}
+
+/// UnsupportedGenerator is returned when converting a Generator that isn't
+/// defined by synfuzz to a Tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedGenerator {
+    pub generator: String,
+}
+
+impl fmt::Display for UnsupportedGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "generator {} is not defined by synfuzz and has no Tree",
+            self.generator
+        )
+    }
+}
+
+impl error::Error for UnsupportedGenerator {}
+
+/// InvalidTree is returned when a Tree breaks an invariant that the helpers
+/// creating its Generators assert, such as a Range that ends before it
+/// starts, which would otherwise panic when it is generated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTree {
+    pub message: String,
+}
+
+impl fmt::Display for InvalidTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for InvalidTree {}
+
+impl Tree {
+    /// from_generator converts a tree of Generators to a Tree
+    pub fn from_generator(generator: &dyn Generator) -> Result<Tree, UnsupportedGenerator> {
+        let boxed = |g: &dyn Generator| Tree::from_generator(g).map(Box::new);
+        let all = |gs: &[Box<dyn Generator>]| {
+            gs.iter()
+                .map(|g| Tree::from_generator(&**g))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(match generator.node() {
+            Node::CharLiteral(g) => Tree::CharLiteral(g.ch),
+            Node::StringLiteral(g) => Tree::StringLiteral(g.s.clone()),
+            Node::ByteLiteral(g) => Tree::ByteLiteral(g.byte),
+            Node::CharRange(g) => Tree::CharRange { n: g.n, m: g.m },
+            Node::Any(_) => Tree::Any,
+            Node::Dictionary(g) => Tree::Dictionary(g.entries.clone()),
+            Node::Mix(g) => Tree::Mix {
+                generator: boxed(&*g.generator)?,
+                dictionary: boxed(&*g.dictionary)?,
+                probability: g.probability,
+            },
+            Node::Choice(g) => Tree::Choice(all(&g.choices)?),
+            Node::WeightedChoice(g) => Tree::WeightedChoice {
+                choices: all(&g.choices)?,
+                weights: g.weights.clone(),
+            },
+            Node::Many(g) => Tree::Many(boxed(&*g.generator)?),
+            Node::Many1(g) => Tree::Many1(boxed(&*g.generator)?),
+            Node::Optional(g) => Tree::Optional(boxed(&*g.generator)?),
+            Node::Rule(g) => Tree::Rule(g.name.clone()),
+            Node::Sequence(g) => Tree::Sequence(all(&g.generators)?),
+            Node::RepeatN(g) => Tree::RepeatN {
+                n: g.n,
+                generator: boxed(&*g.generator)?,
+            },
+            Node::Range(g) => Tree::Range {
+                n: g.n,
+                m: g.m,
+                generator: boxed(&*g.generator)?,
+            },
+            Node::JoinWith(g) => Tree::JoinWith {
+                generators: all(&g.generators)?,
+                delimiter: boxed(&*g.delimiter)?,
+            },
+            Node::SepBy(g) => Tree::SepBy {
+                generator: boxed(&*g.generator)?,
+                separator: boxed(&*g.separator)?,
+            },
+            Node::SepBy1(g) => Tree::SepBy1 {
+                generator: boxed(&*g.generator)?,
+                separator: boxed(&*g.separator)?,
+            },
+            Node::Not(g) => Tree::Not(boxed(&*g.generator)?),
+            Node::TargetSize(g) => Tree::TargetSize {
+                min: g.min,
+                max: g.max,
+                generator: boxed(&*g.generator)?,
+            },
+            Node::Other => {
+                return Err(UnsupportedGenerator {
+                    generator: format!("{:?}", generator),
+                })
+            }
+        })
+    }
+
+    /// validate checks the invariants of the Tree and of every Tree it is
+    /// built from: ranges and sizes don't end before they start, Mix
+    /// probabilities are between 0 and 1, choices and dictionaries aren't
+    /// empty and a WeightedChoice has a weight for every choice
+    pub fn validate(&self) -> Result<(), InvalidTree> {
+        let invalid = |message: String| Err(InvalidTree { message });
+        match *self {
+            Tree::CharRange { n, m } if n > m => {
+                return invalid(format!("the range {} ends before it starts", self))
+            }
+            Tree::Range { n, m, .. } if n > m => {
+                return invalid(format!("the range {} ends before it starts", self))
+            }
+            Tree::TargetSize { min, max, .. } if min > max => {
+                return invalid(format!("the size of {} ends before it starts", self))
+            }
+            Tree::Mix { probability, .. } if !(0.0..=1.0).contains(&probability) => {
+                return invalid(format!(
+                    "the probability of {} is not between 0 and 1",
+                    self
+                ))
+            }
+            Tree::Choice(ref choices) if choices.is_empty() => {
+                return invalid(String::from("a choice has no choices"))
+            }
+            Tree::Dictionary(ref entries) if entries.is_empty() => {
+                return invalid(String::from("a dictionary has no entries"))
+            }
+            Tree::WeightedChoice {
+                ref choices,
+                ref weights,
+            } if choices.is_empty() || choices.len() != weights.len() => {
+                return invalid(format!(
+                    "a weighted choice has {} choices and {} weights",
+                    choices.len(),
+                    weights.len()
+                ))
+            }
+            _ => {}
+        }
+
+        self.children().into_iter().try_for_each(Tree::validate)
+    }
+
+    /// children returns the Trees the Tree is built from
+    fn children(&self) -> Vec<&Tree> {
+        match *self {
+            Tree::CharLiteral(_)
+            | Tree::StringLiteral(_)
+            | Tree::ByteLiteral(_)
+            | Tree::CharRange { .. }
+            | Tree::Any
+            | Tree::Dictionary(_)
+            | Tree::Rule(_) => vec![],
+            Tree::Mix {
+                ref generator,
+                ref dictionary,
+                ..
+            } => vec![&**generator, &**dictionary],
+            Tree::Choice(ref choices) | Tree::WeightedChoice { ref choices, .. } => {
+                choices.iter().collect()
+            }
+            Tree::Sequence(ref generators) => generators.iter().collect(),
+            Tree::Many(ref g) | Tree::Many1(ref g) | Tree::Optional(ref g) | Tree::Not(ref g) => {
+                vec![&**g]
+            }
+            Tree::RepeatN { ref generator, .. }
+            | Tree::Range { ref generator, .. }
+            | Tree::TargetSize { ref generator, .. } => vec![&**generator],
+            Tree::JoinWith {
+                ref generators,
+                ref delimiter,
+            } => generators.iter().chain(Some(&**delimiter)).collect(),
+            Tree::SepBy {
+                ref generator,
+                ref separator,
+            }
+            | Tree::SepBy1 {
+                ref generator,
+                ref separator,
+            } => vec![&**generator, &**separator],
+        }
+    }
+
+    /// build creates the tree of Generators described by the Tree. Rules are
+    /// looked up in rules when they are generated
+    pub fn build(&self, rules: &Arc<RwLock<Rules>>) -> Box<dyn Generator> {
+        let all = |trees: &[Tree]| trees.iter().map(|t| t.build(rules)).collect::<Vec<_>>();
+
+        match *self {
+            Tree::CharLiteral(ch) => Box::new(CharLiteral { ch }),
+            Tree::StringLiteral(ref s) => Box::new(StringLiteral { s: s.clone() }),
+            Tree::ByteLiteral(byte) => Box::new(ByteLiteral { byte }),
+            Tree::CharRange { n, m } => Box::new(CharRange { n, m }),
+            Tree::Any => Box::new(Any {}),
+            Tree::Dictionary(ref entries) => Box::new(Dictionary {
+                entries: entries.clone(),
+            }),
+            Tree::Mix {
+                ref generator,
+                ref dictionary,
+                probability,
+            } => Box::new(Mix {
+                generator: generator.build(rules),
+                dictionary: dictionary.build(rules),
+                probability,
+            }),
+            Tree::Choice(ref choices) => Box::new(Choice {
+                choices: all(choices),
+            }),
+            Tree::WeightedChoice {
+                ref choices,
+                ref weights,
+            } => Box::new(WeightedChoice {
+                choices: all(choices),
+                weights: weights.clone(),
+            }),
+            Tree::Many(ref g) => Box::new(Many {
+                generator: g.build(rules),
+            }),
+            Tree::Many1(ref g) => Box::new(Many1 {
+                generator: g.build(rules),
+            }),
+            Tree::Optional(ref g) => Box::new(Optional {
+                generator: g.build(rules),
+            }),
+            Tree::Rule(ref name) => Box::new(Rule {
+                rules: rules.clone(),
+                name: name.clone(),
+            }),
+            Tree::Sequence(ref generators) => Box::new(Sequence {
+                generators: all(generators),
+            }),
+            Tree::RepeatN { n, ref generator } => Box::new(RepeatN {
+                n,
+                generator: generator.build(rules),
+            }),
+            Tree::Range {
+                n,
+                m,
+                ref generator,
+            } => Box::new(Range {
+                n,
+                m,
+                generator: generator.build(rules),
+            }),
+            Tree::JoinWith {
+                ref generators,
+                ref delimiter,
+            } => Box::new(JoinWith {
+                generators: all(generators),
+                delimiter: delimiter.build(rules),
+            }),
+            Tree::SepBy {
+                ref generator,
+                ref separator,
+            } => Box::new(SepBy {
+                generator: generator.build(rules),
+                separator: separator.build(rules),
+            }),
+            Tree::SepBy1 {
+                ref generator,
+                ref separator,
+            } => Box::new(SepBy1 {
+                generator: generator.build(rules),
+                separator: separator.build(rules),
+            }),
+            Tree::Not(ref g) => Box::new(Not {
+                generator: g.build(rules),
+            }),
+            Tree::TargetSize {
+                min,
+                max,
+                ref generator,
+            } => Box::new(TargetSize {
+                min,
+                max,
+                generator: generator.build(rules),
+            }),
+        }
+    }
+}
+
+/// RuleSet is a set of Rules represented as Trees, ordered by name so that
+/// the same Rules always serialize the same way
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RuleSet {
+    pub rules: BTreeMap<String, Tree>,
+}
+
+impl RuleSet {
+    /// from_rules converts every rule of rules to a Tree
+    pub fn from_rules(rules: &Arc<RwLock<Rules>>) -> Result<RuleSet, UnsupportedGenerator> {
+        let rules = rules.read().unwrap();
+        let rules = rules
+            .iter()
+            .map(|(name, generator)| Ok((name.clone(), Tree::from_generator(&**generator)?)))
+            .collect::<Result<_, UnsupportedGenerator>>()?;
+        Ok(RuleSet { rules })
+    }
+
+    /// validate checks the Tree of every rule, see Tree::validate
+    pub fn validate(&self) -> Result<(), InvalidTree> {
+        for (name, tree) in &self.rules {
+            tree.validate().map_err(|e| InvalidTree {
+                message: format!("rule {}: {}", name, e.message),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// register builds every rule of the RuleSet and registers it in rules,
+    /// replacing rules with the same name
+    pub fn register(&self, rules: &Arc<RwLock<Rules>>) {
+        let built = self
+            .rules
+            .iter()
+            .map(|(name, tree)| (name.clone(), tree.build(rules)))
+            .collect::<Vec<_>>();
+        rules.write().unwrap().extend(built);
+    }
+
+    /// to_rules builds the RuleSet into a new set of Rules
+    pub fn to_rules(&self) -> Arc<RwLock<Rules>> {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        self.register(&rules);
+        rules
+    }
+}
+
+/// Display writes a Tree in an EBNF-like notation: literals are quoted, bytes
+/// are written in hex, alternatives are separated by | and sequences by
+/// spaces, with the alternatives of a WeightedChoice followed by @ and their
+/// weight. Many, Many1 and Optional are written as the postfix operators *, +
+/// and ?, RepeatN and Range as {n} and {n,m}, and Not as the prefix operator
+/// !. The remaining Generators are written as calls with their limits and
+/// probabilities as arguments, such as sep_by(item, ",") and
+/// mix(value, dictionary("0"), 0.1). Many, Many1, SepBy and SepBy1 are
+/// followed by the maximum number of times they repeat, such as item*{..4}
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, Precedence::Choice)
+    }
+}
+
+/// Precedence is how tightly a Tree binds when written, from loosest to
+/// tightest, which decides where parentheses are needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Choice,
+    Sequence,
+    Unary,
+}
+
+impl Tree {
+    /// maximum returns the maximum number of times Many, Many1, SepBy and
+    /// SepBy1 repeat when they aren't steered by TargetSize
+    pub(crate) fn maximum(&self) -> Option<usize> {
+        match *self {
+            Tree::Many(_) | Tree::Many1(_) => Some(MANY_MAX - 1),
+            Tree::SepBy { .. } | Tree::SepBy1 { .. } => Some(SEP_BY_MAX - 1),
+            _ => None,
+        }
+    }
+
+    fn precedence(&self) -> Precedence {
+        match *self {
+            Tree::Choice(ref choices) if choices.len() > 1 => Precedence::Choice,
+            Tree::WeightedChoice { .. } => Precedence::Choice,
+            Tree::Sequence(ref generators) if generators.len() > 1 => Precedence::Sequence,
+            _ => Precedence::Unary,
+        }
+    }
+
+    /// write writes the Tree in parentheses if it binds more loosely than
+    /// the context it is written in requires
+    fn write(&self, f: &mut fmt::Formatter, context: Precedence) -> fmt::Result {
+        if self.precedence() < context {
+            write!(f, "(")?;
+            self.write_bare(f)?;
+            write!(f, ")")
+        } else {
+            self.write_bare(f)
+        }
+    }
+
+    fn write_bare(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Tree::CharLiteral(ch) => write!(f, "{:?}", ch),
+            Tree::StringLiteral(ref s) => write!(f, "{:?}", s),
+            Tree::ByteLiteral(byte) => write!(f, "0x{:02x}", byte),
+            Tree::CharRange { n, m } => write!(f, "{:?}..{:?}", n, m),
+            Tree::Any => write!(f, "."),
+            Tree::Dictionary(ref entries) => {
+                write!(f, "dictionary(")?;
+                for (i, entry) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_bytes(f, entry)?;
+                }
+                write!(f, ")")
+            }
+            Tree::Mix {
+                ref generator,
+                ref dictionary,
+                probability,
+            } => write!(f, "mix({}, {}, {})", generator, dictionary, probability),
+            Tree::Choice(ref choices) => write_list(f, choices, " | ", Precedence::Sequence),
+            Tree::WeightedChoice {
+                ref choices,
+                ref weights,
+            } => {
+                if choices.is_empty() {
+                    return write!(f, "()");
+                }
+                for (i, choice) in choices.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    choice.write(f, Precedence::Sequence)?;
+                    write!(f, " @{}", weights.get(i).cloned().unwrap_or(0))?;
+                }
+                Ok(())
+            }
+            Tree::Many(ref g) => write_postfix(f, g, &format!("*{{..{}}}", MANY_MAX - 1)),
+            Tree::Many1(ref g) => write_postfix(f, g, &format!("+{{..{}}}", MANY_MAX - 1)),
+            Tree::Optional(ref g) => write_postfix(f, g, "?"),
+            Tree::Rule(ref name) => write!(f, "{}", name),
+            Tree::Sequence(ref generators) => write_list(f, generators, " ", Precedence::Unary),
+            Tree::RepeatN { n, ref generator } => {
+                write_postfix(f, generator, &format!("{{{}}}", n))
+            }
+            Tree::Range {
+                n,
+                m,
+                ref generator,
+            } => write_postfix(f, generator, &format!("{{{},{}}}", n, m)),
+            Tree::JoinWith {
+                ref generators,
+                ref delimiter,
+            } => {
+                write!(f, "join_with({}; ", delimiter)?;
+                write_list(f, generators, ", ", Precedence::Choice)?;
+                write!(f, ")")
+            }
+            Tree::SepBy {
+                ref generator,
+                ref separator,
+            } => write!(
+                f,
+                "sep_by({}, {}){{..{}}}",
+                generator,
+                separator,
+                SEP_BY_MAX - 1
+            ),
+            Tree::SepBy1 {
+                ref generator,
+                ref separator,
+            } => write!(
+                f,
+                "sep_by1({}, {}){{..{}}}",
+                generator,
+                separator,
+                SEP_BY_MAX - 1
+            ),
+            Tree::Not(ref g) => {
+                write!(f, "!")?;
+                g.write(f, Precedence::Unary)
+            }
+            Tree::TargetSize {
+                min,
+                max,
+                ref generator,
+            } => write!(f, "target_size({}, {}, {})", generator, min, max),
+        }
+    }
+}
+
+/// write_list writes trees separated by separator. An empty list is written
+/// as ()
+fn write_list(
+    f: &mut fmt::Formatter,
+    trees: &[Tree],
+    separator: &str,
+    context: Precedence,
+) -> fmt::Result {
+    if trees.is_empty() {
+        return write!(f, "()");
+    }
+    for (i, tree) in trees.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        tree.write(f, context)?;
+    }
+    Ok(())
+}
+
+fn write_postfix(f: &mut fmt::Formatter, tree: &Tree, operator: &str) -> fmt::Result {
+    // a postfix operator applied to a unary prefix or postfix expression is
+    // parenthesized to keep it readable, such as (!a)* or (a?)+
+    match *tree {
+        Tree::Not(_)
+        | Tree::Many(_)
+        | Tree::Many1(_)
+        | Tree::Optional(_)
+        | Tree::RepeatN { .. }
+        | Tree::Range { .. } => {
+            write!(f, "(")?;
+            tree.write_bare(f)?;
+            write!(f, "){}", operator)
+        }
+        _ => {
+            tree.write(f, Precedence::Unary)?;
+            write!(f, "{}", operator)
+        }
+    }
+}
+
+/// write_bytes writes bytes as a quoted string, escaping the bytes that
+/// aren't printable ASCII as \xNN
+fn write_bytes(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    write!(f, "\"")?;
+    for byte in bytes {
+        match *byte {
+            b'"' => write!(f, "\\\"")?,
+            b'\\' => write!(f, "\\\\")?,
+            b'\n' => write!(f, "\\n")?,
+            b'\r' => write!(f, "\\r")?,
+            b'\t' => write!(f, "\\t")?,
+            0x20..=0x7e => write!(f, "{}", *byte as char)?,
+            _ => write!(f, "\\x{:02x}", byte)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Display writes each rule of the RuleSet on its own line as name = tree ;
+/// in the notation of the Display of Tree, ordered by name. Many and Many1
+/// repeat at most 4 times and SepBy and SepBy1 at most 4 items
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, tree) in &self.rules {
+            writeln!(f, "{} = {} ;", name, tree)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl RuleSet {
+    /// to_json serializes the RuleSet to JSON
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// from_json deserializes a RuleSet from JSON and validates it
+    pub fn from_json(json: &str) -> serde_json::Result<RuleSet> {
+        let rule_set: RuleSet = serde_json::from_str(json)?;
+        rule_set
+            .validate()
+            .map_err(<serde_json::Error as serde::de::Error>::custom)?;
+        Ok(rule_set)
+    }
+
+    /// to_binary serializes the RuleSet to a compact binary form
+    pub fn to_binary(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    /// from_binary deserializes a RuleSet from the binary form of to_binary
+    /// and validates it
+    pub fn from_binary(data: &[u8]) -> bincode::Result<RuleSet> {
+        let rule_set: RuleSet = bincode::deserialize(data)?;
+        rule_set
+            .validate()
+            .map_err(|e| bincode::ErrorKind::Custom(e.to_string()))?;
+        Ok(rule_set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn json() -> Arc<RwLock<Rules>> {
+        let rules = Arc::new(RwLock::new(HashMap::new()));
+        register_rule(
+            &rules,
+            "value",
+            choice!(
+                rule("number", rules.clone()),
+                seq!(
+                    ch('['),
+                    sep_by(rule("value", rules.clone()), string(", ")),
+                    ch(']')
+                ),
+                mix(string("null"), dictionary(vec!["true", "false"]), 0.1)
+            ),
+        );
+        register_rule(
+            &rules,
+            "number",
+            seq!(
+                optional(byte(b'-')),
+                many1(char_range('0', '9')),
+                range(not(any()), 0, 1)
+            ),
+        );
+        rules
+    }
+
+    #[test]
+    fn round_trip_rules() {
+        let rules = json();
+        let rule_set = RuleSet::from_rules(&rules).unwrap();
+        let rebuilt = rule_set.to_rules();
+        assert_eq!(RuleSet::from_rules(&rebuilt).unwrap(), rule_set);
+
+        // the rebuilt rules make the same decisions as the originals
+        let generate = |rules: Arc<RwLock<Rules>>| {
+            let mut ctx = Context::from_rng(SmallRng::seed_from_u64(3));
+            (0..20)
+                .map(|_| rule("value", rules.clone()).generate_with(&mut ctx))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(generate(rebuilt), generate(rules));
+    }
+
+    #[test]
+    fn other_generators_are_unsupported() {
+        #[derive(Debug)]
+        struct Custom;
+        impl Generator for Custom {
+            fn generate_with(&self, _ctx: &mut Context) -> Vec<u8> {
+                vec![]
+            }
+            fn negate_with(&self, _ctx: &mut Context) -> Vec<u8> {
+                vec![]
+            }
+        }
+
+        let error = Tree::from_generator(&many(Custom)).unwrap_err();
+        assert_eq!(error.generator, "Custom");
+    }
+
+    #[test]
+    fn display_rules() {
+        let rule_set = RuleSet::from_rules(&json()).unwrap();
+        assert_eq!(
+            rule_set.to_string(),
+            "number = 0x2d? '0'..'9'+{..4} (!.){0,1} ;\n\
+             value = number | '[' sep_by(value, \", \"){..4} ']' \
+             | mix(\"null\", dictionary(\"true\", \"false\"), 0.1) ;\n"
+        );
+
+        let tree = Tree::from_generator(&seq!(
+            many(choice!(string("a\n"), seq!(ch('b'), ch('c')))),
+            repeat_n(optional(byte(0)), 2),
+            join_with!(ch(' '), dictionary(vec![&b"\xff"[..]]), seq!()),
+            target_size(choice!(), 1, 9)
+        ))
+        .unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "(\"a\\n\" | 'b' 'c')*{..4} (0x00?){2} join_with(' '; dictionary(\"\\xff\"), ()) \
+             target_size((), 1, 9)"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_grammar() {
+        let rule_set = RuleSet::from_rules(&json()).unwrap();
+        let json = rule_set.to_json().unwrap();
+        assert!(json.contains("\"number\":{\"Sequence\":["));
+        assert_eq!(RuleSet::from_json(&json).unwrap(), rule_set);
+
+        let binary = rule_set.to_binary().unwrap();
+        assert!(binary.len() < json.len());
+        assert_eq!(RuleSet::from_binary(&binary).unwrap(), rule_set);
+
+        let error =
+            RuleSet::from_json(r#"{"rules":{"a":{"CharRange":{"n":"z","m":"a"}}}}"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "rule a: the range 'z'..'a' ends before it starts"
+        );
+
+        let mut invalid = rule_set.clone();
+        invalid
+            .rules
+            .insert(String::from("empty"), Tree::Choice(vec![]));
+        assert!(RuleSet::from_binary(&invalid.to_binary().unwrap()).is_err());
+    }
+
+    #[test]
+    fn validate_trees() {
+        assert!(RuleSet::from_rules(&json()).unwrap().validate().is_ok());
+
+        let invalid = |tree: Tree| tree.validate().unwrap_err().message;
+        let byte = || Box::new(Tree::ByteLiteral(0));
+        assert_eq!(
+            invalid(Tree::Many(Box::new(Tree::Range {
+                n: 2,
+                m: 1,
+                generator: byte(),
+            }))),
+            "the range 0x00{2,1} ends before it starts"
+        );
+        assert_eq!(
+            invalid(Tree::TargetSize {
+                min: 2,
+                max: 1,
+                generator: byte(),
+            }),
+            "the size of target_size(0x00, 2, 1) ends before it starts"
+        );
+        assert_eq!(
+            invalid(Tree::Mix {
+                generator: byte(),
+                dictionary: Box::new(Tree::Dictionary(vec![vec![1]])),
+                probability: 1.5,
+            }),
+            "the probability of mix(0x00, dictionary(\"\\x01\"), 1.5) is not between 0 and 1"
+        );
+        assert_eq!(
+            invalid(Tree::Sequence(vec![Tree::Dictionary(vec![])])),
+            "a dictionary has no entries"
+        );
+        assert_eq!(
+            invalid(Tree::WeightedChoice {
+                choices: vec![Tree::Any],
+                weights: vec![1, 2],
+            }),
+            "a weighted choice has 1 choices and 2 weights"
+        );
+    }
+}
@@ -1,12 +1,5 @@
-use super::Generator;
-
-use rand::distributions::Alphanumeric;
-use rand::distributions::Standard;
-use rand::thread_rng;
-use rand::Rng;
-use std::iter;
-
-const STRING_MAX: usize = 32;
+use super::{Context, Generator, Node};
+use negation::negate_literal;
 
 /// CharLiteral is a Generator that will return the specified char for each
 /// call of the generate method
@@ -17,23 +10,27 @@ pub struct CharLiteral {
 
 /// ch is a helper to create a CharLiteral Generator
 pub fn ch(ch: char) -> impl Generator {
-    CharLiteral { ch: ch }
+    CharLiteral { ch }
 }
 
 impl Generator for CharLiteral {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        ctx.produced(self.ch.len_utf8());
         let mut s = String::with_capacity(4);
         s.push(self.ch);
         s.into_bytes()
     }
 
-    fn negate(&self) -> Vec<u8> {
-        iter::repeat(())
-            .map(|_| thread_rng().sample::<char, Standard>(Standard))
-            .filter(|x| *x != self.ch)
-            .take(1)
-            .collect::<String>()
-            .into_bytes()
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        let mut s = String::with_capacity(4);
+        s.push(self.ch);
+        let generated = negate_literal(&s, ctx);
+        ctx.produced(generated.len());
+        generated
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::CharLiteral(self)
     }
 }
 
@@ -45,24 +42,19 @@ pub struct StringLiteral {
 }
 
 impl Generator for StringLiteral {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        ctx.produced(self.s.len());
         Vec::from(self.s.as_bytes())
     }
 
-    fn negate(&self) -> Vec<u8> {
-        // TODO: Find a good way to get full unicode with good ut8 only
-        let mut rng = thread_rng();
-        let chars = rng.gen_range(0, STRING_MAX);
-        loop {
-            let generated = iter::repeat(())
-                .map::<char, _>(|()| rng.sample::<char, Alphanumeric>(Alphanumeric))
-                .take(chars)
-                .collect::<String>();
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        let generated = negate_literal(&self.s, ctx);
+        ctx.produced(generated.len());
+        generated
+    }
 
-            if generated != self.s {
-                return generated.as_bytes().to_owned();
-            }
-        }
+    fn node(&self) -> Node<'_> {
+        Node::StringLiteral(self)
     }
 }
 
@@ -82,22 +74,33 @@ pub struct ByteLiteral {
 }
 
 impl Generator for ByteLiteral {
-    fn generate(&self) -> Vec<u8> {
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        ctx.produced(1);
         vec![self.byte]
     }
 
-    fn negate(&self) -> Vec<u8> {
-        iter::repeat(())
-            .map(|_| thread_rng().sample(Standard))
-            .filter(|x| *x != self.byte)
-            .take(1)
-            .collect()
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        let mut b = ctx.byte();
+        while b == self.byte {
+            b = if ctx.is_exhausted() {
+                self.byte.wrapping_add(1)
+            } else {
+                ctx.byte()
+            };
+        }
+
+        ctx.produced(1);
+        vec![b]
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::ByteLiteral(self)
     }
 }
 
 /// byte is a helper to create a ByteLiteral Generator
 pub fn byte(byte: u8) -> impl Generator {
-    ByteLiteral { byte: byte }
+    ByteLiteral { byte }
 }
 
 /// CharRange is a generator that will return bytes that represent a char between
@@ -110,21 +113,50 @@ pub struct CharRange {
 }
 
 impl Generator for CharRange {
-    fn generate(&self) -> Vec<u8> {
-        let c = thread_rng().gen_range(self.n as u8, self.m as u8) as char;
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        let value = ctx.range(self.n as usize, self.m as usize + 1);
+        // a range spanning the surrogates can pick a value that isn't a char
+        let c = ::std::char::from_u32(value as u32).unwrap_or(self.n);
+        ctx.produced(c.len_utf8());
         let mut s = String::with_capacity(4);
         s.push(c);
         s.into_bytes()
     }
 
-    fn negate(&self) -> Vec<u8> {
-        unimplemented!()
+    fn negate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        // pick from the chars below n and above m as if they were contiguous
+        let below = self.n as usize;
+        let above = ::std::char::MAX as usize - self.m as usize;
+        if below + above == 0 {
+            return self.generate_with(ctx);
+        }
+
+        let value = ctx.range(0, below + above);
+        let value = if value < below {
+            value
+        } else {
+            self.m as usize + 1 + value - below
+        };
+        // skip over the surrogates to the nearest char outside the range
+        let c = ::std::char::from_u32(value as u32).unwrap_or(if below > 0 {
+            '\0'
+        } else {
+            ::std::char::MAX
+        });
+        ctx.produced(c.len_utf8());
+        let mut s = String::with_capacity(4);
+        s.push(c);
+        s.into_bytes()
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::CharRange(self)
     }
 }
 
 /// char_range is a helper to create a CharRange Generator
 pub fn char_range(n: char, m: char) -> impl Generator {
-    CharRange { n: n, m: m }
+    CharRange { n, m }
 }
 
 /// Any is a Generator that generates one character worth of value
@@ -132,18 +164,20 @@ pub fn char_range(n: char, m: char) -> impl Generator {
 pub struct Any {}
 
 impl Generator for Any {
-    fn generate(&self) -> Vec<u8> {
-        let mut rng = thread_rng();
-        iter::repeat(())
-            .map::<char, _>(|()| rng.sample::<char, Alphanumeric>(Alphanumeric))
-            .take(1)
-            .collect::<String>()
-            .as_bytes()
-            .to_owned()
+    fn generate_with(&self, ctx: &mut Context) -> Vec<u8> {
+        ctx.produced(1);
+        let mut s = String::with_capacity(1);
+        s.push(ctx.alphanumeric());
+        s.into_bytes()
     }
 
-    fn negate(&self) -> Vec<u8> {
-        unimplemented!()
+    fn negate_with(&self, _ctx: &mut Context) -> Vec<u8> {
+        // the only thing that isn't any character is no character at all
+        vec![]
+    }
+
+    fn node(&self) -> Node<'_> {
+        Node::Any(self)
     }
 }
 
@@ -155,6 +189,7 @@ pub fn any() -> impl Generator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use negation::STRING_MAX;
 
     #[test]
     fn generate_ch() {
@@ -198,7 +233,17 @@ mod tests {
         let generator = char_range('a', 'c');
         let generated = generator.generate();
         let c = generated[0];
-        assert!(c >= 0x61 && c <= 0x63);
+        assert!((0x61..=0x63).contains(&c));
+    }
+
+    #[test]
+    fn negate_char_range() {
+        let generator = char_range('a', 'c');
+        for _ in 0..100 {
+            let generated = String::from_utf8(generator.negate()).unwrap();
+            let c = generated.chars().next().unwrap();
+            assert!(!('a'..='c').contains(&c));
+        }
     }
 
     #[test]